@@ -0,0 +1,74 @@
+use std::{
+    path::PathBuf,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Runs a command and returns its trimmed output, or `None` if it isn't
+/// available or fails.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// Generates the gRPC service from `proto/notes.proto`, with a bundled
+/// protoc so building needs no system install. The client is generated too,
+/// for the tests to call the service with.
+fn compile_protos() {
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("the bundled protoc must be available");
+    let include = protoc_bin_vendored::include_path().expect("the bundled protobuf includes must be available");
+    let mut config = tonic_build::Config::new();
+    config.protoc_executable(protoc);
+
+    tonic_build::configure()
+        .build_client(true)
+        .compile_protos_with_config(config, &["proto/notes.proto"], &[PathBuf::from("proto"), include])
+        .expect("proto/notes.proto must compile");
+}
+
+/// The migration files as `<version>_<description>`, oldest first, for the
+/// health check to compare the database against.
+fn migrations() -> String {
+    let mut names: Vec<String> = std::fs::read_dir("migrations")
+        .expect("migrations/ must be readable")
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_suffix(".sql").map(str::to_string)
+        })
+        .collect();
+    names.sort();
+    names.join(",")
+}
+
+fn main() {
+    compile_protos();
+
+    let commit = output("git", &["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let dirty = output("git", &["status", "--porcelain", "--untracked-files=no"]).is_some();
+    let commit = if dirty && commit != "unknown" { format!("{}-dirty", commit) } else { commit };
+
+    // SOURCE_DATE_EPOCH keeps reproducible builds reproducible.
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default()
+            .to_string()
+    });
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=NOTE_PAD_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=NOTE_PAD_BUILD_TIMESTAMP={}", timestamp);
+    println!("cargo:rustc-env=NOTE_PAD_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=NOTE_PAD_MIGRATIONS={}", migrations());
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
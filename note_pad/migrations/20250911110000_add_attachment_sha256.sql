@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE attachments ADD COLUMN sha256 CHAR(64);
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- A link is pending while target_id is NULL; it resolves by title when a
+-- matching note appears and is tracked by id from then on.
+CREATE TABLE note_links (
+    source_id UUID NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+    target_title VARCHAR(255) NOT NULL,
+    target_id UUID REFERENCES notes(id) ON DELETE SET NULL,
+    PRIMARY KEY (source_id, target_title)
+);
+
+CREATE INDEX note_links_target_id_idx ON note_links (target_id);
+CREATE INDEX note_links_pending_idx ON note_links (target_title) WHERE target_id IS NULL;
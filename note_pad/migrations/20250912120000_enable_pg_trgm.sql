@@ -0,0 +1,2 @@
+-- Add migration script here
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE notes ADD COLUMN due_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX notes_due_at_idx ON notes (due_at) WHERE due_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE notes ADD COLUMN reminded_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX notes_pending_reminder_idx ON notes (due_at) WHERE reminded_at IS NULL AND due_at IS NOT NULL;
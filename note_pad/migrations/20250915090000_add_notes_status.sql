@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TYPE note_status AS ENUM ('draft', 'published');
+
+ALTER TABLE notes
+    ADD COLUMN status note_status NOT NULL DEFAULT 'draft',
+    ADD COLUMN published_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX notes_status_idx ON notes (status);
@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TYPE note_status ADD VALUE 'scheduled';
+
+ALTER TABLE notes ADD COLUMN publish_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX notes_publish_at_idx ON notes (publish_at) WHERE publish_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE note_shares
+    ADD COLUMN view_count BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN last_viewed_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE note_shares ADD COLUMN revoked_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE notes ADD COLUMN password_hash TEXT;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE notes
+    ADD COLUMN content_nonce BYTEA,
+    ADD COLUMN content_ciphertext BYTEA;
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE idempotency_keys (
+    key VARCHAR(255) PRIMARY KEY,
+    request_hash CHAR(64) NOT NULL,
+    note_id UUID REFERENCES notes(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idempotency_keys_created_at_idx ON idempotency_keys (created_at);
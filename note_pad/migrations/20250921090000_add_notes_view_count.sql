@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE notes ADD COLUMN view_count BIGINT NOT NULL DEFAULT 0;
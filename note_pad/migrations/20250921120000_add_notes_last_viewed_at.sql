@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE notes ADD COLUMN last_viewed_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX notes_view_count_idx ON notes (view_count DESC NULLS LAST, id);
+CREATE INDEX notes_last_viewed_at_idx ON notes (last_viewed_at DESC NULLS LAST, id) WHERE last_viewed_at IS NOT NULL;
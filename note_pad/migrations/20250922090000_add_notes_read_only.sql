@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE notes ADD COLUMN read_only BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Positions run from 0 without gaps within a note. The uniqueness check is
+-- deferred so a reorder can shift several items in one transaction.
+CREATE TABLE note_items (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    note_id UUID NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+    text TEXT NOT NULL,
+    done BOOLEAN NOT NULL DEFAULT false,
+    position INTEGER NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT note_items_position_key UNIQUE (note_id, position) DEFERRABLE INITIALLY DEFERRED
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- SHA-256 of the normalized title and content, set by the application
+-- since content may be encrypted. Existing notes are hashed at startup.
+ALTER TABLE notes ADD COLUMN content_hash TEXT;
+CREATE INDEX notes_content_hash_idx ON notes (content_hash);
@@ -0,0 +1,32 @@
+-- Add migration script here
+-- Accent-insensitive title matching. Managed hosts may not allow creating
+-- the extension; the server then falls back to case-insensitive matching,
+-- so this logs a warning instead of failing.
+DO $$
+BEGIN
+    CREATE EXTENSION IF NOT EXISTS unaccent SCHEMA public;
+EXCEPTION WHEN insufficient_privilege OR undefined_file THEN
+    RAISE WARNING 'unaccent is unavailable (%), titles will only match case-insensitively', SQLERRM;
+END
+$$;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'unaccent') THEN
+        -- unaccent() is only STABLE, as its dictionary could change, so
+        -- indexes need a wrapper naming the dictionary.
+        CREATE OR REPLACE FUNCTION immutable_unaccent(text) RETURNS text
+            LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT
+            AS $f$ SELECT public.unaccent('public.unaccent'::regdictionary, $1) $f$;
+        CREATE INDEX IF NOT EXISTS notes_title_folded_idx ON notes USING gin (immutable_unaccent(lower(title)) gin_trgm_ops);
+
+        IF NOT EXISTS (SELECT 1 FROM pg_ts_config WHERE cfgname = 'english_unaccent') THEN
+            CREATE TEXT SEARCH CONFIGURATION english_unaccent (COPY = english);
+            ALTER TEXT SEARCH CONFIGURATION english_unaccent
+                ALTER MAPPING FOR hword, hword_part, word WITH public.unaccent, english_stem;
+        END IF;
+    ELSE
+        CREATE INDEX IF NOT EXISTS notes_title_lower_idx ON notes USING gin (lower(title) gin_trgm_ops);
+    END IF;
+END
+$$;
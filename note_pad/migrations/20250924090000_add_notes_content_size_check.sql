@@ -0,0 +1,7 @@
+-- Backstop for MAX_CONTENT_BYTES at its 16 MiB ceiling; encrypted content
+-- also carries a 16 byte tag. NOT VALID, so notes already over the limit
+-- don't block the migration; only new writes are checked.
+ALTER TABLE notes ADD CONSTRAINT notes_content_size CHECK (
+    octet_length(content) <= 16777216
+    AND (content_ciphertext IS NULL OR octet_length(content_ciphertext) <= 16777232)
+) NOT VALID;
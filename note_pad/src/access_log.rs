@@ -0,0 +1,79 @@
+use axum::{
+    body::HttpBody,
+    extract::{MatchedPath, Request},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+
+use crate::{client_ip::ClientIp, request_id::REQUEST_ID_HEADER};
+
+/// Routes polled by load balancers and monitoring, logged at `debug` so they
+/// don't drown out real traffic. The health check reports its own failures.
+const QUIET_ROUTES: &[&str] = &["/api/v1/healthcheck", "/api/v1/version"];
+
+/// Longest user agent that is logged as sent; longer ones are cut.
+const MAX_USER_AGENT_CHARS: usize = 256;
+
+/// Logs one line per request: `info`, `error` for 5xx responses, and
+/// `debug` for `QUIET_ROUTES` whatever their status. Request and response
+/// bodies are never logged, since they hold note content. The latency is
+/// measured up to the response head, and the size is left out when the body
+/// is streamed without a known length.
+pub async fn log_request(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let client_ip = request.extensions().get::<ClientIp>().map(|ClientIp(ip)| *ip);
+    let headers = request.headers();
+    let user_agent: Option<String> = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|agent| agent.chars().take(MAX_USER_AGENT_CHARS).collect());
+    let request_id = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16();
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let response_bytes = response.body().size_hint().exact();
+    // Unmatched requests are logged without their path, which is whatever
+    // the client sent.
+    let route = route.as_deref().unwrap_or("<unmatched>");
+    let user_agent = user_agent.as_deref().unwrap_or("-");
+    let request_id = request_id.as_deref().unwrap_or("-");
+
+    macro_rules! access {
+        ($level:expr) => {
+            tracing::event!(
+                $level,
+                method = %method,
+                route,
+                status,
+                latency_ms,
+                response_bytes,
+                client_ip = client_ip.map(|ip| ip.to_string()),
+                user_agent,
+                request_id,
+                "request completed"
+            )
+        };
+    }
+
+    if QUIET_ROUTES.contains(&route) {
+        access!(tracing::Level::DEBUG);
+    } else if response.status().is_server_error() {
+        access!(tracing::Level::ERROR);
+    } else {
+        access!(tracing::Level::INFO);
+    }
+
+    response
+}
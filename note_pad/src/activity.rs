@@ -0,0 +1,117 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{error::AppError, extract::{Json, Path, Query}, passwords, query::visible, AppState};
+
+const DEFAULT_LOOKBACK_DAYS: i64 = 30;
+const MAX_LOOKBACK_DAYS: i64 = 366;
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityParams {
+    since: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Something that happened to a note. There are no user accounts, so
+/// `actor` is always `null` for now.
+#[derive(Debug, Serialize)]
+pub struct ActivityEntry {
+    at: DateTime<Utc>,
+    #[serde(rename = "type")]
+    kind: String,
+    actor: Option<String>,
+    details: serde_json::Value,
+}
+
+/// A note's activity, newest first, going back to `since` (default 30 days,
+/// at most 366).
+///
+/// Entries are read from the timestamps the tables already keep rather than a
+/// log of their own, so nothing has to write twice: creation, the latest
+/// edit, publishing, share links created and revoked, attachments and
+/// checklist items added. Earlier edits aren't kept anywhere and so don't
+/// appear.
+pub async fn get_activity(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<ActivityParams>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ActivityEntry>>, AppError> {
+    let now = Utc::now();
+    let since = params.since.unwrap_or(now - Duration::days(DEFAULT_LOOKBACK_DAYS));
+    if since < now - Duration::days(MAX_LOOKBACK_DAYS) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("since may be at most {} days ago", MAX_LOOKBACK_DAYS),
+        )
+            .into());
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let password_hash = sqlx::query_scalar(
+        concat!("SELECT password_hash FROM notes WHERE id = $1 AND ", visible!()),
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+    passwords::unlock(&state, id, password_hash, passwords::supplied(&headers, None)).await?;
+
+    let rows = sqlx::query(
+        "SELECT at, kind, details FROM (
+             SELECT created_at AS at, 'created' AS kind,
+                    jsonb_build_object('title', title) AS details
+             FROM notes WHERE id = $1
+             UNION ALL
+             SELECT updated_at, 'updated', jsonb_build_object('version', version)
+             FROM notes WHERE id = $1 AND updated_at > created_at
+             UNION ALL
+             SELECT published_at, 'published', '{}'::jsonb
+             FROM notes WHERE id = $1 AND published_at IS NOT NULL
+             UNION ALL
+             SELECT created_at, 'shared', jsonb_build_object('share_id', id, 'expires_at', expires_at)
+             FROM note_shares WHERE note_id = $1
+             UNION ALL
+             SELECT revoked_at, 'share_revoked', jsonb_build_object('share_id', id)
+             FROM note_shares WHERE note_id = $1 AND revoked_at IS NOT NULL
+             UNION ALL
+             SELECT created_at, 'attachment_added', jsonb_build_object('attachment_id', id, 'filename', filename)
+             FROM attachments WHERE note_id = $1
+             UNION ALL
+             SELECT created_at, 'item_added', jsonb_build_object('item_id', id, 'text', text)
+             FROM note_items WHERE note_id = $1
+         ) AS activity
+         WHERE at >= $2
+         ORDER BY at DESC, kind
+         LIMIT $3 OFFSET $4",
+    )
+    .bind(id)
+    .bind(since)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        entries.push(ActivityEntry {
+            at: row.try_get("at")?,
+            kind: row.try_get("kind")?,
+            actor: None,
+            details: row.try_get("details")?,
+        });
+    }
+
+    Ok(Json(entries))
+}
@@ -0,0 +1,45 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, StatusCode},
+};
+use std::sync::Arc;
+
+use crate::{error::AppError, shares::hash_token, AppState};
+
+/// Proof that a request carried `Authorization: Bearer <ADMIN_TOKEN>`, for
+/// the `/api/v1/admin` routes. Without `ADMIN_TOKEN` those routes are off.
+pub struct Admin;
+
+/// Reads `ADMIN_TOKEN`, keeping only its digest like share tokens.
+pub fn token_hash_from_env() -> Option<String> {
+    std::env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(|token| hash_token(&token))
+}
+
+impl FromRequestParts<Arc<AppState>> for Admin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let Some(expected) = &state.admin_token_hash else {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                "admin_disabled",
+                "Admin endpoints are disabled; set ADMIN_TOKEN to enable them",
+            ));
+        };
+
+        let presented = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match presented {
+            // Digests are compared, so the time taken says nothing about the
+            // token itself.
+            Some(token) if hash_token(token.trim()) == *expected => Ok(Admin),
+            _ => Err((StatusCode::UNAUTHORIZED, "A valid admin token is required".to_string()).into()),
+        }
+    }
+}
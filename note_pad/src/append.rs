@@ -0,0 +1,124 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use serde::Deserialize;
+use sqlx::PgConnection;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    content_limit, crypto, duplicates, error::AppError, events::NoteEvent, extract::{Json, Path, Query}, hypermedia::{BaseUrl, LinksParams}, links, passwords, raw_notes,
+    read_only, AppState, Note,
+};
+
+const DEFAULT_SEPARATOR: &str = "\n";
+
+#[derive(Debug, Deserialize)]
+struct AppendRequest {
+    content: String,
+    separator: Option<String>,
+}
+
+/// Appends to a note's content, from a JSON `{"content", "separator"}` body
+/// or a raw `text/plain` or `text/markdown` one. The separator (a newline by
+/// default) is only added when the note already has content. Concurrent
+/// appends all land, in the order they get the row.
+pub async fn append_to_note(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(link_params): Query<LinksParams>,
+    base: BaseUrl,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Note>, AppError> {
+    let payload: AppendRequest = serde_json::from_value(raw_notes::append_payload(&headers, &body)?)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+    let separator = payload.separator.as_deref().unwrap_or(DEFAULT_SEPARATOR);
+    content_limit::check(state.max_content_bytes, &payload.content)?;
+
+    let mut tx = state.db.begin().await?;
+
+    read_only::ensure_writable(&mut tx, id).await?;
+    passwords::unlock_note(&state, &mut tx, id, passwords::supplied(&headers, None)).await?;
+
+    // Plaintext content is appended to in SQL; encrypted content has to be
+    // read and sealed again, which the row lock taken above keeps atomic.
+    let appended = if crypto::enabled() {
+        None
+    } else {
+        sqlx::query(
+            "UPDATE notes
+             SET content = CASE WHEN content = '' THEN $1 ELSE content || $2 || $1 END,
+                 updated_at = NOW(),
+                 version = version + 1
+             WHERE id = $3 AND content_nonce IS NULL
+             RETURNING *",
+        )
+        .bind(&payload.content)
+        .bind(separator)
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await?
+    };
+    let row = match appended {
+        Some(row) => row,
+        None => append_sealed(&mut tx, id, &payload.content, separator).await?,
+    };
+
+    // Checked once appended, so the limit applies to the whole content; the
+    // transaction is rolled back on the way out.
+    let mut note = Note::from_row(&row)?;
+    content_limit::check(state.max_content_bytes, &note.content)?;
+    links::sync_links(&mut tx, id, &note.content)
+        .await?;
+    duplicates::store_hash(&mut tx, id, &note.title, &note.content).await?;
+
+    tx.commit().await?;
+
+    note.links = link_params.note_links(&base, id);
+
+    state.events.publish(NoteEvent::Updated { note_id: id });
+
+    Ok(Json(note))
+}
+
+/// Appends by rewriting the whole content, for when it's encrypted.
+async fn append_sealed(
+    conn: &mut PgConnection,
+    id: Uuid,
+    content: &str,
+    separator: &str,
+) -> Result<sqlx::postgres::PgRow, AppError> {
+    let row = sqlx::query("SELECT content, content_nonce, content_ciphertext FROM notes WHERE id = $1")
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let current = crypto::content(&row)?;
+    let merged = if current.is_empty() {
+        content.to_string()
+    } else {
+        format!("{}{}{}", current, separator, content)
+    };
+    let sealed = crypto::seal(&merged);
+
+    let row = sqlx::query(
+        "UPDATE notes
+         SET content = $1,
+             content_nonce = $2,
+             content_ciphertext = $3,
+             updated_at = NOW(),
+             version = version + 1
+         WHERE id = $4
+         RETURNING *",
+    )
+    .bind(sealed.plaintext)
+    .bind(sealed.nonce)
+    .bind(sealed.ciphertext)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(row)
+}
@@ -0,0 +1,672 @@
+use axum::{
+    body::Body,
+    extract::{Multipart, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{postgres::PgRow, Row};
+use std::{
+    collections::HashSet,
+    io::SeekFrom,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{error::AppError, events::NoteEvent, extract::{Json, Path}, passwords, query::visible, read_only, AppState};
+
+/// Content types accepted for upload.
+const ALLOWED_CONTENT_TYPES: &[&str] = &[
+    "application/pdf",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+];
+
+const DEFAULT_DIR: &str = "attachments";
+const DEFAULT_MAX_BYTES: usize = 10 * 1024 * 1024;
+const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 3600;
+
+/// Files younger than this are never swept, since an upload writes its file
+/// before the row is committed.
+const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(15 * 60);
+
+/// Upper bound on files in a single upload request, which together with
+/// `max_bytes` bounds the request body.
+pub const MAX_FILES_PER_REQUEST: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub note_id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub sha256: Option<String>,
+}
+
+impl Attachment {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Attachment {
+            id: row.try_get("id")?,
+            note_id: row.try_get("note_id")?,
+            filename: row.try_get("filename")?,
+            content_type: row.try_get("content_type")?,
+            size: row.try_get("size")?,
+            created_at: row.try_get("created_at")?,
+            sha256: row.try_get("sha256")?,
+        })
+    }
+}
+
+/// Where attachment bytes are stored and how large a single file may be.
+pub struct AttachmentConfig {
+    pub dir: PathBuf,
+    pub max_bytes: usize,
+    pub sweep_interval: Duration,
+}
+
+impl AttachmentConfig {
+    /// Reads `ATTACHMENTS_DIR`, `ATTACHMENT_MAX_BYTES` and
+    /// `ATTACHMENT_SWEEP_INTERVAL_SECS`, falling back to `./attachments`,
+    /// 10 MiB and one hour.
+    pub fn from_env() -> Self {
+        let dir = std::env::var("ATTACHMENTS_DIR").unwrap_or_else(|_| DEFAULT_DIR.to_string());
+        let max_bytes = std::env::var("ATTACHMENT_MAX_BYTES")
+            .ok()
+            .map(|value| value.parse().expect("ATTACHMENT_MAX_BYTES must be a number of bytes"))
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        let sweep_interval = std::env::var("ATTACHMENT_SWEEP_INTERVAL_SECS")
+            .ok()
+            .map(|value| value.parse().expect("ATTACHMENT_SWEEP_INTERVAL_SECS must be a number of seconds"))
+            .unwrap_or(DEFAULT_SWEEP_INTERVAL_SECS);
+
+        AttachmentConfig {
+            dir: PathBuf::from(dir),
+            max_bytes,
+            sweep_interval: Duration::from_secs(sweep_interval),
+        }
+    }
+
+    pub fn path_for(&self, id: Uuid) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+}
+
+/// Reduces a client supplied filename to a safe display name: directory
+/// components and control characters are dropped and the length is capped.
+fn sanitize_filename(raw: &str) -> String {
+    let base = raw.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '"' | '<' | '>' | ':' | '|' | '?' | '*'))
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.');
+
+    if cleaned.is_empty() {
+        return "attachment".to_string();
+    }
+
+    let mut end = cleaned.len().min(255);
+    while !cleaned.is_char_boundary(end) {
+        end -= 1;
+    }
+    cleaned[..end].to_string()
+}
+
+pub async fn note_exists(state: &AppState, note_id: Uuid) -> Result<bool, AppError> {
+    let row = sqlx::query(concat!("SELECT 1 FROM notes WHERE id = $1 AND ", visible!()))
+        .bind(note_id)
+        .fetch_optional(&state.db)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+pub async fn fetch_for_note(state: &AppState, note_id: Uuid) -> Result<Vec<Attachment>, AppError> {
+    let rows = sqlx::query("SELECT * FROM attachments WHERE note_id = $1 ORDER BY created_at")
+        .bind(note_id)
+        .fetch_all(&state.db)
+        .await?;
+
+    Ok(rows.iter().map(Attachment::from_row).collect::<Result<_, _>>()?)
+}
+
+pub async fn upload_attachments(
+    State(state): State<Arc<AppState>>,
+    Path(note_id): Path<Uuid>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<Vec<Attachment>>), AppError> {
+    // The body is only read once fields are pulled, so an unknown,
+    // read-only or locked note is rejected before any bytes are accepted.
+    {
+        let mut conn = state.db.acquire().await?;
+        read_only::ensure_writable(&mut conn, note_id).await?;
+        passwords::unlock_note(&state, &mut conn, note_id, passwords::supplied(&headers, None)).await?;
+    }
+
+    let config = &state.attachments;
+    fs::create_dir_all(&config.dir).await?;
+
+    // Rows are inserted in one transaction; if any file is rejected, the
+    // rollback drops every row and the files written so far are removed.
+    let mut tx = state.db.begin().await?;
+    let mut written = Vec::new();
+
+    let stored: Result<Vec<Attachment>, AppError> = async {
+        let mut attachments = Vec::new();
+        while let Some(mut field) = multipart
+            .next_field()
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+        {
+            let Some(filename) = field.file_name().map(sanitize_filename) else {
+                continue;
+            };
+
+            if attachments.len() == MAX_FILES_PER_REQUEST {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("At most {} files can be uploaded at once", MAX_FILES_PER_REQUEST),
+                )
+                    .into());
+            }
+
+            let content_type = field.content_type().unwrap_or_default().to_ascii_lowercase();
+            if !ALLOWED_CONTENT_TYPES.contains(&content_type.as_str()) {
+                return Err((
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!(
+                        "Content type '{}' is not allowed. Allowed types are: {}",
+                        content_type,
+                        ALLOWED_CONTENT_TYPES.join(", ")
+                    ),
+                )
+                    .into());
+            }
+
+            let id = Uuid::new_v4();
+            let path = config.path_for(id);
+            let mut file = fs::File::create(&path).await?;
+            written.push(path);
+
+            let mut size = 0;
+            let mut hasher = Sha256::new();
+            while let Some(chunk) = field
+                .chunk()
+                .await
+                .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+            {
+                size += chunk.len();
+                if size > config.max_bytes {
+                    return Err((
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!("Attachments may be at most {} bytes", config.max_bytes),
+                    )
+                        .into());
+                }
+                hasher.update(&chunk);
+                file.write_all(&chunk).await?;
+            }
+            file.sync_all().await?;
+
+            let row = sqlx::query(
+                "INSERT INTO attachments (id, note_id, filename, content_type, size, sha256) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+            )
+            .bind(id)
+            .bind(note_id)
+            .bind(&filename)
+            .bind(&content_type)
+            .bind(size as i64)
+            .bind(format!("{:x}", hasher.finalize()))
+            .fetch_one(&mut tx)
+            .await?;
+
+            attachments.push(Attachment::from_row(&row)?);
+        }
+
+        if attachments.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "No file was uploaded".to_string()).into());
+        }
+
+        Ok(attachments)
+    }
+    .await;
+
+    // Checked again before committing, in case the note was made read-only
+    // while the files were uploading.
+    let stored = match stored {
+        Ok(attachments) => async {
+            read_only::ensure_writable(&mut tx, note_id).await?;
+            tx.commit().await?;
+            Ok(attachments)
+        }
+        .await,
+        Err(err) => Err(err),
+    };
+
+    match stored {
+        Ok(attachments) => {
+            state.events.publish(NoteEvent::Updated { note_id });
+            Ok((StatusCode::CREATED, Json(attachments)))
+        }
+        Err(err) => {
+            for path in written {
+                let _ = fs::remove_file(path).await;
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Lists a note's attachments, once its password is given if it has one.
+pub async fn list_attachments(
+    State(state): State<Arc<AppState>>,
+    Path(note_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Attachment>>, AppError> {
+    let password_hash = sqlx::query_scalar(concat!("SELECT password_hash FROM notes WHERE id = $1 AND ", visible!()))
+        .bind(note_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+    passwords::unlock(&state, note_id, password_hash, passwords::supplied(&headers, None)).await?;
+
+    Ok(Json(fetch_for_note(&state, note_id).await?))
+}
+
+/// Interprets a `Range` header against a file of `len` bytes.
+///
+/// Returns `None` when the header should be ignored (malformed, not in
+/// bytes, or asking for several ranges) and `Some(Err(()))` when the range
+/// cannot be satisfied. A satisfiable range is returned as inclusive bounds.
+fn parse_range(value: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => len.saturating_sub(1),
+            end => end.parse::<u64>().ok()?.min(len.saturating_sub(1)),
+        };
+        if start >= len || start > end {
+            return Some(Err(()));
+        }
+        (start, end)
+    };
+
+    Some(Ok(range))
+}
+
+/// Builds a `Content-Disposition` value carrying both an ASCII fallback and
+/// the original UTF-8 filename.
+fn content_disposition(filename: &str) -> HeaderValue {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() && !matches!(c, '"' | '\\') && !c.is_ascii_control() { c } else { '_' })
+        .collect();
+    let encoded = utf8_percent_encode(filename, NON_ALPHANUMERIC);
+
+    HeaderValue::from_str(&format!("inline; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded))
+        .unwrap_or_else(|_| HeaderValue::from_static("inline"))
+}
+
+async fn hash_file(file: &mut fs::File) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    file.seek(SeekFrom::Start(0)).await?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Streams an attachment, honouring `Range` and `If-None-Match`. Like the
+/// note itself, it needs the note's password if it has one.
+pub async fn download_attachment(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let row = sqlx::query(
+        concat!("SELECT a.*, n.password_hash FROM attachments a JOIN notes n ON n.id = a.note_id
+         WHERE a.id = $1 AND ", visible!("n")),
+    )
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Attachment not found".to_string()))?;
+    let attachment = Attachment::from_row(&row)?;
+    passwords::unlock(
+        &state,
+        attachment.note_id,
+        row.try_get("password_hash")?,
+        passwords::supplied(&headers, None),
+    )
+    .await?;
+
+    let path = state.attachments.path_for(id);
+    let mut file = match fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::error!(
+                attachment_id = %id,
+                note_id = %attachment.note_id,
+                path = %path.display(),
+                "attachment row exists but its file is missing from storage"
+            );
+            return Err((StatusCode::NOT_FOUND, "Attachment not found".to_string()).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let len = file.metadata().await?.len();
+
+    // Rows uploaded before hashes were recorded get theirs filled in lazily.
+    let sha256 = match attachment.sha256 {
+        Some(sha256) => sha256,
+        None => {
+            let sha256 = hash_file(&mut file).await?;
+            sqlx::query("UPDATE attachments SET sha256 = $1 WHERE id = $2")
+                .bind(&sha256)
+                .bind(id)
+                .execute(&state.db)
+                .await?;
+            sha256
+        }
+    };
+    let etag = format!("\"{}\"", sha256);
+
+    let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    if if_none_match.is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*")) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    // A stale If-Range means the client's partial copy is outdated, so the
+    // whole file is sent instead.
+    let if_range_matches = headers
+        .get(header::IF_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .is_none_or(|value| value.trim() == etag);
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| if_range_matches)
+        .and_then(|value| parse_range(value, len));
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&attachment.content_type)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    response_headers.insert(header::CONTENT_DISPOSITION, content_disposition(&attachment.filename));
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(header::ETAG, HeaderValue::from_str(&etag).expect("hex digest is a valid header"));
+
+    match range {
+        Some(Err(())) => {
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                format!("bytes */{}", len).parse().expect("valid header"),
+            );
+            Ok((StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response())
+        }
+        Some(Ok((start, end))) => {
+            file.seek(SeekFrom::Start(start)).await?;
+            let part_len = end - start + 1;
+            response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(part_len));
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, len).parse().expect("valid header"),
+            );
+            let body = Body::from_stream(ReaderStream::new(file.take(part_len)));
+            Ok((StatusCode::PARTIAL_CONTENT, response_headers, body).into_response())
+        }
+        None => {
+            response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+            let body = Body::from_stream(ReaderStream::new(file));
+            Ok((StatusCode::OK, response_headers, body).into_response())
+        }
+    }
+}
+
+/// Unlinks attachment files whose rows have already been deleted. A failure
+/// only leaves an orphan behind for the storage sweep, so it is logged rather
+/// than reported to the client.
+pub async fn remove_files(config: &AttachmentConfig, ids: &[Uuid]) {
+    for id in ids {
+        let path = config.path_for(*id);
+        match fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!(attachment_id = %id, path = %path.display(), error = %e, "failed to remove attachment file");
+            }
+            _ => {}
+        }
+    }
+}
+
+pub async fn delete_attachment(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db.begin().await?;
+
+    let note_id: Uuid = sqlx::query_scalar("SELECT note_id FROM attachments WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Attachment not found".to_string()))?;
+    read_only::ensure_writable(&mut tx, note_id).await?;
+    passwords::unlock_note(&state, &mut tx, note_id, passwords::supplied(&headers, None)).await?;
+
+    sqlx::query("DELETE FROM attachments WHERE id = $1")
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    remove_files(&state.attachments, &[id]).await;
+    state.events.publish(NoteEvent::Updated { note_id });
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Differences between the attachments table and the storage directory.
+#[derive(Debug, Default)]
+pub struct StorageReport {
+    /// Files on disk without a row.
+    pub orphaned_files: Vec<PathBuf>,
+    /// Rows whose file is missing from disk.
+    pub missing_files: Vec<Uuid>,
+}
+
+pub async fn check_storage(state: &AppState) -> Result<StorageReport, Box<dyn std::error::Error + Send + Sync>> {
+    let ids: HashSet<Uuid> = sqlx::query("SELECT id FROM attachments")
+        .fetch_all(&state.db)
+        .await?
+        .iter()
+        .map(|row| row.try_get("id"))
+        .collect::<Result<_, _>>()?;
+
+    let mut report = StorageReport::default();
+    let mut on_disk = HashSet::new();
+
+    let mut entries = match fs::read_dir(&state.attachments.dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            report.missing_files = ids.into_iter().collect();
+            return Ok(report);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        match entry.file_name().to_str().and_then(|name| Uuid::parse_str(name).ok()) {
+            Some(id) if ids.contains(&id) => {
+                on_disk.insert(id);
+            }
+            _ => report.orphaned_files.push(path),
+        }
+    }
+
+    report.missing_files = ids.difference(&on_disk).copied().collect();
+
+    Ok(report)
+}
+
+/// Logs storage drift and deletes orphaned files that are past the grace
+/// period.
+async fn sweep_storage(state: &AppState) {
+    let report = match check_storage(state).await {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::error!(error = %e, "attachment storage consistency check failed");
+            return;
+        }
+    };
+
+    for id in &report.missing_files {
+        tracing::error!(attachment_id = %id, "attachment row has no file in storage");
+    }
+
+    let mut removed = 0;
+    for path in &report.orphaned_files {
+        let old_enough = match fs::metadata(path).await.and_then(|meta| meta.modified()) {
+            Ok(modified) => SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|age| age >= ORPHAN_GRACE_PERIOD),
+            Err(_) => false,
+        };
+        if !old_enough {
+            continue;
+        }
+
+        match fs::remove_file(path).await {
+            Ok(()) => removed += 1,
+            Err(e) => tracing::warn!(path = %path.display(), error = %e, "failed to remove orphaned attachment file"),
+        }
+    }
+
+    if !report.missing_files.is_empty() || !report.orphaned_files.is_empty() {
+        tracing::warn!(
+            missing_files = report.missing_files.len(),
+            orphaned_files = report.orphaned_files.len(),
+            removed_orphans = removed,
+            "attachment storage drift detected"
+        );
+    }
+}
+
+/// Runs the storage consistency check at startup and then on every
+/// `sweep_interval`.
+pub fn spawn_storage_sweeper(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(state.attachments.sweep_interval);
+        loop {
+            interval.tick().await;
+            sweep_storage(&state).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::TestApp;
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn deleting_a_note_removes_its_attachment_files(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({"title": "Receipts", "content": "March"})).await;
+        let note_id: Uuid = note["id"].as_str().unwrap().parse().unwrap();
+
+        let uploaded = app
+            .upload(note_id, &[("a.png", b"first"), ("b.png", b"second"), ("c.png", b"third")])
+            .await;
+        assert_eq!(uploaded.status, StatusCode::CREATED, "{}", uploaded.text());
+        assert_eq!(uploaded.header("content-type"), Some("application/json"));
+        assert_eq!(uploaded.json().as_array().unwrap().len(), 3);
+        assert_eq!(std::fs::read_dir(&app.state.attachments.dir).unwrap().count(), 3);
+
+        let deleted = app
+            .request(Request::delete(format!("/api/v1/notes/{}", note_id)).body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(deleted.status, StatusCode::NO_CONTENT, "{}", deleted.text());
+        assert_eq!(app.get(&format!("/api/v1/notes/{}", note_id)).await.status, StatusCode::NOT_FOUND);
+
+        assert_eq!(std::fs::read_dir(&app.state.attachments.dir).unwrap().count(), 0);
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM attachments")
+            .fetch_one(&app.state.db)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn database_errors_reading_attachments_are_not_leaked(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({"title": "Scans", "content": ""})).await;
+        sqlx::query("ALTER TABLE attachments RENAME TO attachments_moved")
+            .execute(&app.state.db)
+            .await
+            .unwrap();
+
+        let response = app.get(&format!("/api/v1/notes/{}/attachments", note["id"].as_str().unwrap())).await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        let error = &response.json()["error"];
+        assert_eq!(error["code"], "internal_error");
+        assert_eq!(error["message"], "Internal server error");
+        assert!(!response.text().contains("attachments"));
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn storage_errors_are_not_leaked(pool: sqlx::PgPool) {
+        let mut state = crate::test_support::state(pool).await;
+        // A file where the directory should be, so nothing can be stored.
+        let blocked = crate::test_support::scratch_dir().join("not-a-directory");
+        std::fs::write(&blocked, b"").unwrap();
+        state.attachments.dir = blocked;
+        let app = TestApp::with_state(state);
+        let note = app.create_note(json!({"title": "Scans", "content": ""})).await;
+
+        let response = app.upload(note["id"].as_str().unwrap().parse().unwrap(), &[("a.png", b"x")]).await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.json()["error"]["message"], "Internal server error");
+        assert!(!response.text().contains("not-a-directory"));
+    }
+}
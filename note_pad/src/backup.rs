@@ -0,0 +1,345 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use s3::{creds::Credentials, Bucket, Region};
+use std::{path::PathBuf, str::FromStr, sync::Arc};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{admin::Admin, error::AppError, extract::Json, statement_timeout, AppState};
+
+/// Version of the backup document, checked before a backup is restored.
+pub const SCHEMA_VERSION: i32 = 1;
+
+/// Every day at 03:00 UTC.
+const DEFAULT_SCHEDULE: &str = "0 0 3 * * *";
+
+const DEFAULT_KEEP: usize = 7;
+
+const FILE_PREFIX: &str = "notes-";
+const FILE_SUFFIX: &str = ".json";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+const DEFAULT_S3_PREFIX: &str = "note_pad/";
+
+/// Where and when backups are written.
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    schedule: cron::Schedule,
+    keep: usize,
+    remote: Option<RemoteTarget>,
+}
+
+impl BackupConfig {
+    /// Reads `BACKUP_DIR`, without which there are no backups,
+    /// `BACKUP_SCHEDULE` (a cron expression with seconds, in UTC, default
+    /// daily at 03:00) and `BACKUP_KEEP`, the number of files kept
+    /// (default 7). Backups are copied to object storage too when
+    /// `BACKUP_S3_BUCKET` is set, as `RemoteTarget::from_env` describes.
+    pub fn from_env() -> Option<Self> {
+        let dir = std::env::var("BACKUP_DIR").ok()?;
+        let schedule = std::env::var("BACKUP_SCHEDULE").unwrap_or_else(|_| DEFAULT_SCHEDULE.to_string());
+        let schedule = cron::Schedule::from_str(&schedule)
+            .unwrap_or_else(|e| panic!("BACKUP_SCHEDULE {:?} is not a cron expression: {}", schedule, e));
+        let keep = std::env::var("BACKUP_KEEP")
+            .ok()
+            .map(|value| value.parse().expect("BACKUP_KEEP must be a number of files"))
+            .unwrap_or(DEFAULT_KEEP);
+        assert!(keep > 0, "BACKUP_KEEP must keep at least one file");
+
+        Some(BackupConfig {
+            dir: PathBuf::from(dir),
+            schedule,
+            keep,
+            remote: RemoteTarget::from_env(),
+        })
+    }
+}
+
+/// An S3 compatible bucket that backups are copied to, so they outlive the
+/// machine. The same `BACKUP_KEEP` applies there.
+pub struct RemoteTarget {
+    bucket: Box<Bucket>,
+    prefix: String,
+}
+
+impl RemoteTarget {
+    /// Reads `BACKUP_S3_BUCKET`, without which backups stay local,
+    /// `BACKUP_S3_REGION` (default us-east-1), `BACKUP_S3_ENDPOINT` for
+    /// storage other than AWS, addressed by path, and `BACKUP_S3_PREFIX`
+    /// (default `note_pad/`). Credentials come from
+    /// `BACKUP_S3_ACCESS_KEY_ID` and `BACKUP_S3_SECRET_ACCESS_KEY`, or else
+    /// from the usual AWS variables and profile.
+    fn from_env() -> Option<Self> {
+        let name = std::env::var("BACKUP_S3_BUCKET").ok()?;
+        let region_name = std::env::var("BACKUP_S3_REGION").unwrap_or_else(|_| DEFAULT_S3_REGION.to_string());
+        let endpoint = std::env::var("BACKUP_S3_ENDPOINT").ok();
+        let region = match &endpoint {
+            Some(endpoint) => Region::Custom {
+                region: region_name,
+                endpoint: endpoint.clone(),
+            },
+            None => region_name.parse().expect("BACKUP_S3_REGION must be an AWS region"),
+        };
+        let credentials = match std::env::var("BACKUP_S3_ACCESS_KEY_ID") {
+            Ok(access_key) => {
+                let secret_key = std::env::var("BACKUP_S3_SECRET_ACCESS_KEY")
+                    .expect("BACKUP_S3_SECRET_ACCESS_KEY must be set with BACKUP_S3_ACCESS_KEY_ID");
+                Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
+            }
+            Err(_) => Credentials::default(),
+        }
+        .unwrap_or_else(|e| panic!("BACKUP_S3 credentials are unusable: {}", e));
+
+        let bucket = Bucket::new(&name, region, credentials)
+            .unwrap_or_else(|e| panic!("BACKUP_S3_BUCKET {:?} is unusable: {}", name, e));
+        let bucket = if endpoint.is_some() { bucket.with_path_style() } else { bucket };
+        let prefix = std::env::var("BACKUP_S3_PREFIX").unwrap_or_else(|_| DEFAULT_S3_PREFIX.to_string());
+
+        Some(RemoteTarget { bucket, prefix })
+    }
+
+    /// Keys are grouped by day, as `<prefix>2025/09/22/notes-....json`.
+    fn key_for(&self, file: &BackupFile) -> String {
+        format!("{}{}/{}", self.prefix, file.created_at.format("%Y/%m/%d"), file.name)
+    }
+
+    /// The backups in the bucket, newest first.
+    async fn list(&self) -> Result<Vec<BackupFile>, s3::error::S3Error> {
+        let mut files = Vec::new();
+        for page in self.bucket.list(self.prefix.clone(), None).await? {
+            for object in page.contents {
+                let name = object.key.rsplit('/').next().unwrap_or_default();
+                if let Some(created_at) = parse_name(name) {
+                    files.push(BackupFile {
+                        name: name.to_string(),
+                        location: BackupLocation::S3,
+                        size: object.size,
+                        created_at,
+                    });
+                }
+            }
+        }
+        files.sort_by_key(|file| std::cmp::Reverse(file.created_at));
+        Ok(files)
+    }
+
+    /// Uploads the local backups the bucket doesn't have yet, which are
+    /// those of failed earlier runs too, then removes the ones past
+    /// `keep`.
+    async fn sync(&self, config: &BackupConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let uploaded = self.list().await?;
+        for file in list_files(config).await? {
+            if uploaded.iter().any(|remote| remote.name == file.name) {
+                continue;
+            }
+            let key = self.key_for(&file);
+            let bytes = fs::read(config.dir.join(&file.name)).await?;
+            self.bucket
+                .put_object_with_content_type(&key, &bytes, "application/json")
+                .await?;
+            tracing::info!(bucket = self.bucket.name, key, bytes = bytes.len(), "uploaded backup");
+        }
+
+        for old in self.list().await?.iter().skip(config.keep) {
+            let key = self.key_for(old);
+            self.bucket.delete_object(&key).await?;
+            tracing::info!(bucket = self.bucket.name, key, "removed old remote backup");
+        }
+        Ok(())
+    }
+}
+
+/// Every note with its checklist items and links, as one JSON document
+/// read in a single statement, so it is a consistent snapshot. Rows are
+/// written by Postgres itself with every column, so new columns are
+/// included without changes here. Content stays as stored, encrypted when
+/// a key is configured. Runs under the bulk statement timeout.
+pub async fn export(state: &AppState) -> Result<serde_json::Value, sqlx::Error> {
+    let mut tx = state.db.begin().await?;
+    statement_timeout::extend(&mut tx, state.timeouts.bulk).await?;
+    let document = sqlx::query_scalar(
+        "SELECT jsonb_build_object(
+             'schema_version', $1::integer,
+             'created_at', NOW(),
+             'notes', COALESCE((SELECT jsonb_agg(to_jsonb(n) ORDER BY n.created_at, n.id) FROM notes n), '[]'),
+             'note_items', COALESCE((SELECT jsonb_agg(to_jsonb(i) ORDER BY i.note_id, i.position) FROM note_items i), '[]'),
+             'note_links', COALESCE((SELECT jsonb_agg(to_jsonb(l)) FROM note_links l), '[]')
+         )",
+    )
+    .bind(SCHEMA_VERSION)
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(document)
+}
+
+/// Downloads the same document a backup writes, named like a backup file,
+/// so it can be restored through `/api/v1/admin/restore` as it is. IDs and
+/// timestamps are kept exactly, to the microsecond.
+pub async fn download_export(_: Admin, State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let document = export(&state).await?;
+    let name = format!("{}{}{}", FILE_PREFIX, Utc::now().format(TIMESTAMP_FORMAT), FILE_SUFFIX);
+    let disposition = HeaderValue::from_str(&format!("attachment; filename=\"{}\"", name))
+        .unwrap_or_else(|_| HeaderValue::from_static("attachment"));
+
+    Ok(([(header::CONTENT_DISPOSITION, disposition)], Json(document)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupLocation {
+    Local,
+    S3,
+}
+
+/// A written backup, as logged and listed.
+#[derive(Debug, Serialize)]
+pub struct BackupFile {
+    pub name: String,
+    pub location: BackupLocation,
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Writes a backup now and removes the ones past `BACKUP_KEEP`. The file is
+/// written under a temporary name and renamed once it is on disk, so a
+/// crash never leaves a truncated backup behind.
+pub async fn run_backup(
+    state: &AppState,
+    config: &BackupConfig,
+) -> Result<(BackupFile, usize), Box<dyn std::error::Error + Send + Sync>> {
+    let document = export(state).await?;
+    let notes = document["notes"].as_array().map_or(0, Vec::len);
+    let bytes = serde_json::to_vec(&document)?;
+
+    let created_at = Utc::now();
+    let name = format!("{}{}{}", FILE_PREFIX, created_at.format(TIMESTAMP_FORMAT), FILE_SUFFIX);
+    let path = config.dir.join(&name);
+    let temp_path = config.dir.join(format!(".{}.tmp", name));
+
+    fs::create_dir_all(&config.dir).await?;
+    let mut file = fs::File::create(&temp_path).await?;
+    file.write_all(&bytes).await?;
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(&temp_path, &path).await?;
+    // The rename itself is only durable once the directory is synced.
+    #[cfg(unix)]
+    fs::File::open(&config.dir).await?.sync_all().await?;
+
+    prune(config).await?;
+
+    Ok((
+        BackupFile {
+            name,
+            location: BackupLocation::Local,
+            size: bytes.len() as u64,
+            created_at,
+        },
+        notes,
+    ))
+}
+
+/// The backups in `BACKUP_DIR`, newest first.
+pub async fn list_files(config: &BackupConfig) -> std::io::Result<Vec<BackupFile>> {
+    let mut files = Vec::new();
+    let mut entries = match fs::read_dir(&config.dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let Some(created_at) = parse_name(&name) else {
+            continue;
+        };
+        files.push(BackupFile {
+            size: entry.metadata().await?.len(),
+            location: BackupLocation::Local,
+            created_at,
+            name,
+        });
+    }
+    files.sort_by_key(|file| std::cmp::Reverse(file.created_at));
+    Ok(files)
+}
+
+/// When a backup named like `notes-20250922T030000Z.json` was written.
+fn parse_name(name: &str) -> Option<DateTime<Utc>> {
+    let timestamp = name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_SUFFIX)?;
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .ok()
+        .map(|created_at| created_at.and_utc())
+}
+
+async fn prune(config: &BackupConfig) -> std::io::Result<()> {
+    for old in list_files(config).await?.iter().skip(config.keep) {
+        fs::remove_file(config.dir.join(&old.name)).await?;
+        tracing::info!(name = old.name, "removed old backup");
+    }
+    Ok(())
+}
+
+/// Writes backups on `BACKUP_SCHEDULE` and copies them to the remote
+/// target. A failed run or upload is logged, and the next run goes ahead as
+/// planned and uploads whatever is missing.
+pub fn spawn_backup_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let Some(config) = &state.backups else {
+            return;
+        };
+        for next in config.schedule.upcoming(Utc) {
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+            match run_backup(&state, config).await {
+                Ok((file, notes)) => tracing::info!(
+                    path = %config.dir.join(&file.name).display(),
+                    notes,
+                    bytes = file.size,
+                    "wrote backup"
+                ),
+                Err(e) => tracing::error!(error = %e, "failed to write backup"),
+            }
+            if let Some(remote) = &config.remote
+                && let Err(e) = remote.sync(config).await
+            {
+                tracing::error!(error = %e, bucket = remote.bucket.name, "failed to upload backups");
+            }
+        }
+    });
+}
+
+/// Lists the backups on disk and in the remote target, newest first.
+pub async fn list_backups(_: Admin, State(state): State<Arc<AppState>>) -> Result<Json<Vec<BackupFile>>, AppError> {
+    let config = state.backups.as_ref().ok_or_else(|| {
+        AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "backups_disabled",
+            "Backups are not configured on this server",
+        )
+    })?;
+
+    let mut files = list_files(config)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Some(remote) = &config.remote {
+        let remote_files = remote.list().await.map_err(|e| {
+            AppError::new(
+                StatusCode::BAD_GATEWAY,
+                "backup_storage_unavailable",
+                format!("Backups in object storage can't be listed: {}", e),
+            )
+        })?;
+        files.extend(remote_files);
+        files.sort_by_key(|file| std::cmp::Reverse(file.created_at));
+    }
+    Ok(Json(files))
+}
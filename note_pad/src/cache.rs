@@ -0,0 +1,686 @@
+use chrono::Utc;
+use moka::sync::Cache;
+use redis::{aio::ConnectionManager, Client};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::{
+    attachments::Attachment,
+    crypto,
+    events::{EventBus, NoteEvent},
+    expires_in_seconds,
+    items::NoteItem,
+    Note,
+};
+
+const DEFAULT_CAPACITY: u64 = 10_000;
+
+const DEFAULT_TTL_SECS: u64 = 30;
+
+/// Header telling whether a response came from the cache.
+pub const CACHE_HEADER: &str = "x-cache";
+
+/// Prefix of the Redis keys holding cached notes and their generations.
+const REDIS_KEY_PREFIX: &str = "note_pad:note:";
+
+/// Channel on which instances announce the notes they evicted.
+const REDIS_CHANNEL: &str = "note_pad:note-invalidations";
+
+/// How long a Redis command may take before the request goes on without it.
+const REDIS_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// How long reads skip Redis after it failed, so an outage costs requests
+/// one timeout rather than one per request.
+const REDIS_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Longest wait between attempts to resubscribe to invalidations.
+const MAX_SUBSCRIBE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Stores `ARGV[1]` under `KEYS[1]` unless the note's generation in
+/// `KEYS[2]` moved on from `ARGV[2]` since the note was read.
+const PUT_SCRIPT: &str = "
+    if (redis.call('GET', KEYS[2]) or '0') == ARGV[2] then
+        redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[3])
+    end
+    return 0";
+
+/// What `GET /api/v1/notes/{id}` needs from the database. The password hash
+/// is kept so protected notes are still checked on every request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedNote {
+    pub note: Note,
+    pub password_hash: Option<String>,
+    pub attachments: Vec<Attachment>,
+    pub items: Vec<NoteItem>,
+}
+
+impl CachedNote {
+    /// The note as it should be served now, or `None` once it has expired.
+    fn fresh(mut self) -> Option<Self> {
+        if self.note.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return None;
+        }
+        self.note.expires_in_seconds = expires_in_seconds(self.note.expires_at);
+        Some(self)
+    }
+}
+
+/// Handed out on a miss and given back to `put` with what was read, so a
+/// read that raced a write doesn't cache what it read from before the write.
+#[derive(Debug, Clone, Copy)]
+pub struct Generation {
+    local: u64,
+    /// The note's generation in Redis, if it could be read.
+    shared: Option<i64>,
+}
+
+pub enum Lookup {
+    Hit(Box<CachedNote>),
+    Miss(Generation),
+}
+
+pub type CacheFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A cache of single notes, keyed by id. Failures of the cache never fail a
+/// request; they are misses.
+pub trait NoteCache: Send + Sync {
+    fn get(&self, id: Uuid) -> CacheFuture<'_, Lookup>;
+
+    fn put(&self, id: Uuid, note: CachedNote, read_at: Generation) -> CacheFuture<'_, ()>;
+
+    /// Drops a note. What this instance holds locally is dropped before the
+    /// call returns; the future only finishes the work elsewhere, so it can
+    /// be spawned.
+    fn invalidate(&self, id: Uuid) -> CacheFuture<'static, ()>;
+
+    /// Counters and state for the health check.
+    fn snapshot(&self) -> serde_json::Value;
+}
+
+/// Builds the configured cache, if any:
+///
+/// - with `CACHE_URL` (such as `redis://cache:6379`), notes are shared
+///   through Redis, and `NOTE_CACHE_ENABLED=true` adds an in-process layer
+///   in front of it that other instances clear over pub/sub;
+/// - otherwise `NOTE_CACHE_ENABLED` (default false) turns on the in-process
+///   cache alone, which other instances' writes only reach through its TTL.
+///
+/// The in-process layer holds up to `NOTE_CACHE_CAPACITY` notes (default
+/// 10000). Entries live for `NOTE_CACHE_TTL_SECS` (default 30) in either.
+pub fn from_env() -> Option<Arc<dyn NoteCache>> {
+    let enabled = std::env::var("NOTE_CACHE_ENABLED")
+        .ok()
+        .map(|value| value.parse().expect("NOTE_CACHE_ENABLED must be true or false"))
+        .unwrap_or(false);
+    let url = std::env::var("CACHE_URL").ok().filter(|url| !url.is_empty());
+    if !enabled && url.is_none() {
+        return None;
+    }
+
+    let capacity = std::env::var("NOTE_CACHE_CAPACITY")
+        .ok()
+        .map(|value| value.parse().expect("NOTE_CACHE_CAPACITY must be a number of notes"))
+        .unwrap_or(DEFAULT_CAPACITY);
+    let ttl = std::env::var("NOTE_CACHE_TTL_SECS")
+        .ok()
+        .map(|value| value.parse().expect("NOTE_CACHE_TTL_SECS must be a number of seconds"))
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(DEFAULT_TTL_SECS));
+    let local = enabled.then(|| MemoryCache::new(capacity, ttl));
+
+    let Some(url) = url else {
+        tracing::info!(capacity, ttl_secs = ttl.as_secs(), "note cache enabled");
+        return local.map(|local| Arc::new(local) as Arc<dyn NoteCache>);
+    };
+
+    let cache = RedisCache::new(&url, ttl, local).expect("CACHE_URL must be a valid Redis URL");
+    tracing::info!(local_layer = enabled, ttl_secs = ttl.as_secs(), "Redis note cache enabled");
+    Some(Arc::new(cache))
+}
+
+/// Evicts notes as events about them are published. Notes can change
+/// without going through a handler, by scheduled publishing, expiry and the
+/// like, and all of those publish events.
+pub fn evict_on_events(cache: &Arc<dyn NoteCache>, events: &EventBus) {
+    let cache = Arc::clone(cache);
+    events.on_publish(move |event| match event {
+        NoteEvent::Published { .. } | NoteEvent::Updated { .. } | NoteEvent::Deleted { .. } => {
+            tokio::spawn(cache.invalidate(event.note_id()));
+        }
+        NoteEvent::Due { .. } => {}
+    });
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Counters {
+    fn count(&self, lookup: Lookup) -> Lookup {
+        let counter = match lookup {
+            Lookup::Hit(_) => &self.hits,
+            Lookup::Miss(_) => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        lookup
+    }
+}
+
+/// The in-process cache. On its own, entries are only evicted by the note
+/// events of this instance.
+pub struct MemoryCache {
+    entries: Cache<Uuid, Arc<CachedNote>>,
+    /// Bumped by every eviction. Conservative, but cheaper than tracking
+    /// evictions per note.
+    generation: AtomicU64,
+    counters: Counters,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: u64, ttl: Duration) -> Self {
+        MemoryCache {
+            entries: Cache::builder().max_capacity(capacity).time_to_live(ttl).build(),
+            generation: AtomicU64::new(0),
+            counters: Counters::default(),
+        }
+    }
+
+    fn lookup(&self, id: Uuid) -> Option<CachedNote> {
+        self.entries.get(&id).and_then(|cached| CachedNote::clone(&cached).fresh())
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn insert(&self, id: Uuid, note: CachedNote, read_at: u64) {
+        if self.generation() == read_at {
+            self.entries.insert(id, Arc::new(note));
+        }
+    }
+
+    fn evict(&self, id: Uuid) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.entries.invalidate(&id);
+    }
+
+    fn clear(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.entries.invalidate_all();
+    }
+}
+
+impl NoteCache for MemoryCache {
+    fn get(&self, id: Uuid) -> CacheFuture<'_, Lookup> {
+        let lookup = match self.lookup(id) {
+            Some(cached) => Lookup::Hit(Box::new(cached)),
+            None => Lookup::Miss(Generation {
+                local: self.generation(),
+                shared: None,
+            }),
+        };
+        let lookup = self.counters.count(lookup);
+        Box::pin(async move { lookup })
+    }
+
+    fn put(&self, id: Uuid, note: CachedNote, read_at: Generation) -> CacheFuture<'_, ()> {
+        self.insert(id, note, read_at.local);
+        Box::pin(async {})
+    }
+
+    fn invalidate(&self, id: Uuid) -> CacheFuture<'static, ()> {
+        self.evict(id);
+        Box::pin(async {})
+    }
+
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "backend": "memory",
+            "hits": self.counters.hits.load(Ordering::Relaxed),
+            "misses": self.counters.misses.load(Ordering::Relaxed),
+            "entries": self.entries.entry_count(),
+        })
+    }
+}
+
+/// Notes shared by all instances through Redis, optionally behind an
+/// in-process layer. Entries are sealed with the content key when one is
+/// configured, so Redis holds no more plaintext than the database does.
+///
+/// Every note has a generation key next to its entry that invalidation
+/// increments, and entries are only stored if it hasn't moved since the
+/// note was read, the same guard the in-process cache keeps locally.
+pub struct RedisCache {
+    client: Client,
+    conn: ConnectionManager,
+    ttl: Duration,
+    local: Option<Arc<MemoryCache>>,
+    /// Whether other instances' invalidations are reaching the local layer.
+    subscribed: Arc<AtomicBool>,
+    counters: Counters,
+    /// Set while reads skip Redis after a failure.
+    unavailable_until: Mutex<Option<Instant>>,
+    /// Whether the last command failed, so an outage is logged once.
+    degraded: Arc<AtomicBool>,
+}
+
+impl RedisCache {
+    /// Connects lazily, so an unreachable Redis doesn't stop the service
+    /// from starting.
+    fn new(url: &str, ttl: Duration, local: Option<MemoryCache>) -> redis::RedisResult<Self> {
+        let client = Client::open(url)?;
+        let config = redis::aio::ConnectionManagerConfig::new()
+            .set_connection_timeout(Some(REDIS_TIMEOUT))
+            .set_response_timeout(Some(REDIS_TIMEOUT))
+            .set_number_of_retries(1);
+        let conn = ConnectionManager::new_lazy_with_config(client.clone(), config)?;
+
+        let cache = RedisCache {
+            client,
+            conn,
+            ttl,
+            local: local.map(Arc::new),
+            subscribed: Arc::new(AtomicBool::new(false)),
+            counters: Counters::default(),
+            unavailable_until: Mutex::new(None),
+            degraded: Arc::new(AtomicBool::new(false)),
+        };
+        if let Some(local) = &cache.local {
+            tokio::spawn(subscribe(cache.client.clone(), Arc::clone(local), Arc::clone(&cache.subscribed)));
+        }
+        Ok(cache)
+    }
+
+    fn available(&self) -> bool {
+        let mut until = self.unavailable_until.lock().unwrap();
+        match *until {
+            Some(instant) if instant > Instant::now() => false,
+            _ => {
+                *until = None;
+                true
+            }
+        }
+    }
+
+    /// Notes the outcome of a command, backing reads off after a failure.
+    fn record<T>(&self, operation: &'static str, result: redis::RedisResult<T>) -> Option<T> {
+        if result.is_err() {
+            *self.unavailable_until.lock().unwrap() = Some(Instant::now() + REDIS_BACKOFF);
+        }
+        record(&self.degraded, operation, result)
+    }
+
+    async fn fetch(&self, id: Uuid) -> Option<(Option<Vec<u8>>, Option<i64>)> {
+        let result = redis::cmd("MGET")
+            .arg(entry_key(id))
+            .arg(generation_key(id))
+            .query_async(&mut self.conn.clone())
+            .await;
+        self.record("get", result)
+    }
+}
+
+impl NoteCache for RedisCache {
+    fn get(&self, id: Uuid) -> CacheFuture<'_, Lookup> {
+        Box::pin(async move {
+            let local = self.local.as_ref().filter(|_| self.subscribed.load(Ordering::SeqCst));
+            let local_generation = local.map_or(0, |local| local.generation());
+            if let Some(cached) = local.and_then(|local| local.lookup(id)) {
+                return self.counters.count(Lookup::Hit(Box::new(cached)));
+            }
+
+            let fetched = match self.available() {
+                true => self.fetch(id).await,
+                false => None,
+            };
+            let Some((entry, shared)) = fetched else {
+                return self.counters.count(Lookup::Miss(Generation {
+                    local: local_generation,
+                    shared: None,
+                }));
+            };
+            let read_at = Generation {
+                local: local_generation,
+                shared: Some(shared.unwrap_or(0)),
+            };
+
+            let cached = entry.and_then(|entry| match decode(&entry) {
+                Ok(cached) => cached.fresh(),
+                Err(e) => {
+                    tracing::warn!(note_id = %id, error = %e, "ignoring unreadable cached note");
+                    None
+                }
+            });
+            let lookup = match cached {
+                Some(cached) => {
+                    if let Some(local) = local {
+                        local.insert(id, cached.clone(), local_generation);
+                    }
+                    Lookup::Hit(Box::new(cached))
+                }
+                None => Lookup::Miss(read_at),
+            };
+            self.counters.count(lookup)
+        })
+    }
+
+    fn put(&self, id: Uuid, note: CachedNote, read_at: Generation) -> CacheFuture<'_, ()> {
+        Box::pin(async move {
+            let Some(shared) = read_at.shared else {
+                // Redis couldn't be read, so its generation is unknown, and
+                // without it nothing invalidates the local copy elsewhere.
+                return;
+            };
+            let encoded = match encode(&note) {
+                Ok(encoded) => encoded,
+                Err(e) => {
+                    tracing::warn!(note_id = %id, error = %e, "could not encode note for the cache");
+                    return;
+                }
+            };
+            if let Some(local) = self.local.as_ref().filter(|_| self.subscribed.load(Ordering::SeqCst)) {
+                local.insert(id, note, read_at.local);
+            }
+
+            if !self.available() {
+                return;
+            }
+            let result = redis::Script::new(PUT_SCRIPT)
+                .key(entry_key(id))
+                .key(generation_key(id))
+                .arg(encoded)
+                .arg(shared)
+                .arg(self.ttl.as_millis() as u64)
+                .invoke_async::<i64>(&mut self.conn.clone())
+                .await;
+            self.record("put", result);
+        })
+    }
+
+    /// Always tries Redis, even while reads are backing off, since a stale
+    /// shared entry is served by every instance.
+    fn invalidate(&self, id: Uuid) -> CacheFuture<'static, ()> {
+        if let Some(local) = &self.local {
+            local.evict(id);
+        }
+
+        let mut conn = self.conn.clone();
+        let degraded = Arc::clone(&self.degraded);
+        // The generation has to outlive any read in flight; the entry TTL
+        // with a minute to spare is plenty.
+        let generation_ttl = (self.ttl + Duration::from_secs(60)).as_millis() as i64;
+        Box::pin(async move {
+            let result = redis::pipe()
+                .atomic()
+                .del(entry_key(id))
+                .ignore()
+                .incr(generation_key(id), 1)
+                .ignore()
+                .pexpire(generation_key(id), generation_ttl)
+                .ignore()
+                .publish(REDIS_CHANNEL, id.to_string())
+                .ignore()
+                .query_async::<()>(&mut conn)
+                .await;
+            record(&degraded, "invalidate", result);
+        })
+    }
+
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "backend": "redis",
+            "redis": if self.degraded.load(Ordering::Relaxed) { "unavailable" } else { "ok" },
+            "hits": self.counters.hits.load(Ordering::Relaxed),
+            "misses": self.counters.misses.load(Ordering::Relaxed),
+            "local_entries": self.local.as_ref().map(|local| local.entries.entry_count()),
+        })
+    }
+}
+
+fn entry_key(id: Uuid) -> String {
+    format!("{}{}", REDIS_KEY_PREFIX, id)
+}
+
+fn generation_key(id: Uuid) -> String {
+    format!("{}{}:generation", REDIS_KEY_PREFIX, id)
+}
+
+fn encode(note: &CachedNote) -> Result<Vec<u8>, serde_json::Error> {
+    Ok(crypto::seal_bytes(&serde_json::to_vec(note)?))
+}
+
+fn decode(entry: &[u8]) -> Result<CachedNote, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_slice(&crypto::open_bytes(entry)?)?)
+}
+
+/// Logs Redis going away and coming back, rather than every failed command.
+fn record<T>(degraded: &AtomicBool, operation: &'static str, result: redis::RedisResult<T>) -> Option<T> {
+    match result {
+        Ok(value) => {
+            if degraded.swap(false, Ordering::Relaxed) {
+                tracing::info!("Redis note cache is reachable again");
+            }
+            Some(value)
+        }
+        Err(e) => {
+            if !degraded.swap(true, Ordering::Relaxed) {
+                tracing::warn!(operation, error = %e, "Redis note cache unavailable, treating it as a miss");
+            } else {
+                tracing::debug!(operation, error = %e, "Redis note cache command failed");
+            }
+            None
+        }
+    }
+}
+
+/// Keeps the local layer in step with the other instances' invalidations,
+/// resubscribing with backoff when the connection drops. Invalidations sent
+/// while disconnected are lost, so the layer is only trusted while
+/// `subscribed`, and cleared on every (re)subscription.
+async fn subscribe(client: Client, local: Arc<MemoryCache>, subscribed: Arc<AtomicBool>) {
+    let mut backoff = Duration::from_secs(1);
+    let mut warned = false;
+    loop {
+        let pubsub = tokio::time::timeout(REDIS_TIMEOUT, client.get_async_pubsub())
+            .await
+            .unwrap_or_else(|_| Err((redis::ErrorKind::Io, "connection timed out").into()));
+        match pubsub {
+            Ok(mut pubsub) => match pubsub.subscribe(REDIS_CHANNEL).await {
+                Ok(()) => {
+                    tracing::info!(channel = REDIS_CHANNEL, "subscribed to note cache invalidations");
+                    local.clear();
+                    subscribed.store(true, Ordering::SeqCst);
+                    backoff = Duration::from_secs(1);
+                    warned = false;
+
+                    let mut messages = pubsub.on_message();
+                    while let Some(message) = messages.next().await {
+                        match message.get_payload::<String>().ok().and_then(|id| id.parse().ok()) {
+                            Some(id) => local.evict(id),
+                            None => tracing::warn!("ignoring malformed note cache invalidation"),
+                        }
+                    }
+                    tracing::warn!("lost the note cache invalidation subscription");
+                    subscribed.store(false, Ordering::SeqCst);
+                    local.clear();
+                }
+                Err(e) => log_failure(&mut warned, &e),
+            },
+            Err(e) => log_failure(&mut warned, &e),
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_SUBSCRIBE_BACKOFF);
+    }
+}
+
+fn log_failure(warned: &mut bool, error: &redis::RedisError) {
+    if *warned {
+        tracing::debug!(error = %error, "could not subscribe to note cache invalidations");
+    } else {
+        tracing::warn!(error = %error, "could not subscribe to note cache invalidations, retrying");
+        *warned = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{self, TestApp};
+
+    fn memory_cache() -> Arc<MemoryCache> {
+        Arc::new(MemoryCache::new(100, Duration::from_secs(60)))
+    }
+
+    /// An app caching notes in memory, evicting them on its own events as
+    /// `main` sets it up.
+    async fn cached_app(pool: sqlx::PgPool) -> TestApp {
+        let mut state = test_support::state(pool).await;
+        let cache: Arc<dyn NoteCache> = memory_cache();
+        evict_on_events(&cache, &state.events);
+        state.note_cache = Some(cache);
+        TestApp::with_state(state)
+    }
+
+    async fn is_cached(cache: &MemoryCache, id: Uuid) -> bool {
+        matches!(cache.get(id).await, Lookup::Hit(_))
+    }
+
+    /// The generation a read starts from, on a miss.
+    async fn read_at(cache: &MemoryCache, id: Uuid) -> Generation {
+        match cache.get(id).await {
+            Lookup::Miss(read_at) => read_at,
+            Lookup::Hit(_) => panic!("note {} is already cached", id),
+        }
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn a_note_is_served_fresh_right_after_an_update(pool: sqlx::PgPool) {
+        let app = cached_app(pool).await;
+        let note = app.create_note(json!({ "title": "Cached", "content": "v1" })).await;
+        let uri = format!("/api/v1/notes/{}", note["id"].as_str().unwrap());
+
+        assert_eq!(app.get(&uri).await.header(CACHE_HEADER), Some("miss"));
+        let response = app.get(&uri).await;
+        assert_eq!(response.header(CACHE_HEADER), Some("hit"));
+        assert_eq!(response.json()["content"], "v1");
+
+        let updated = app.send_json(Method::PUT, &uri, json!({ "content": "v2" })).await;
+        assert_eq!(updated.status, StatusCode::OK, "{}", updated.text());
+
+        let response = app.get(&uri).await;
+        assert_eq!(response.header(CACHE_HEADER), Some("miss"));
+        assert_eq!(response.json()["content"], "v2");
+        assert_eq!(response.header("etag"), Some("\"2\""));
+        let response = app.get(&uri).await;
+        assert_eq!(response.header(CACHE_HEADER), Some("hit"));
+        assert_eq!(response.json()["content"], "v2");
+
+        let item = app
+            .send_json(Method::POST, &format!("{}/items", uri), json!({ "text": "Milk" }))
+            .await;
+        assert!(item.status.is_success(), "{}", item.text());
+        let response = app.get(&uri).await;
+        assert_eq!(response.header(CACHE_HEADER), Some("miss"));
+        assert_eq!(response.json()["items"][0]["text"], "Milk");
+
+        let deleted = app.request(axum::http::Request::delete(&uri).body(Default::default()).unwrap()).await;
+        assert_eq!(deleted.status, StatusCode::NO_CONTENT);
+        assert_eq!(app.get(&uri).await.status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn note_events_evict_the_memory_cache(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({ "title": "Cached", "content": "v1" })).await;
+        let note_id: Uuid = note["id"].as_str().unwrap().parse().unwrap();
+        let cached = crate::load_note(&app.state, note_id).await.unwrap();
+
+        let cache = memory_cache();
+        let events = EventBus::new();
+        evict_on_events(&(cache.clone() as Arc<dyn NoteCache>), &events);
+
+        for event in [NoteEvent::Updated { note_id }, NoteEvent::Deleted { note_id }] {
+            let read_at = read_at(&cache, note_id).await;
+            cache.put(note_id, cached.clone(), read_at).await;
+            assert!(is_cached(&cache, note_id).await);
+
+            events.publish(event);
+            assert!(!is_cached(&cache, note_id).await, "still cached after the event");
+        }
+
+        // Reminders don't change the note.
+        let read_at = read_at(&cache, note_id).await;
+        cache.put(note_id, cached.clone(), read_at).await;
+        events.publish(NoteEvent::Due {
+            note_id,
+            title: cached.note.title.clone(),
+            due_at: Utc::now(),
+        });
+        assert!(is_cached(&cache, note_id).await);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn a_read_that_raced_an_eviction_is_not_cached(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({ "title": "Cached", "content": "v1" })).await;
+        let note_id: Uuid = note["id"].as_str().unwrap().parse().unwrap();
+        let cached = crate::load_note(&app.state, note_id).await.unwrap();
+        let cache = memory_cache();
+
+        // A write lands between the miss and the put of what was read.
+        let stale = read_at(&cache, note_id).await;
+        cache.invalidate(note_id).await;
+        cache.put(note_id, cached.clone(), stale).await;
+        assert!(!is_cached(&cache, note_id).await, "the stale read was cached");
+
+        // A read after the write is cached as usual.
+        let read_at = read_at(&cache, note_id).await;
+        cache.put(note_id, cached, read_at).await;
+        assert!(is_cached(&cache, note_id).await);
+    }
+
+    /// Needs a Redis server at `CACHE_URL`; run with `--ignored`.
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    #[ignore = "needs Redis at CACHE_URL"]
+    async fn invalidation_bumps_the_redis_generation(pool: sqlx::PgPool) {
+        let url = std::env::var("CACHE_URL").expect("CACHE_URL must name a Redis server");
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({ "title": "Cached", "content": "v1" })).await;
+        let note_id: Uuid = note["id"].as_str().unwrap().parse().unwrap();
+        let cached = crate::load_note(&app.state, note_id).await.unwrap();
+        let cache = RedisCache::new(&url, Duration::from_secs(60), None).unwrap();
+        let miss = |lookup| match lookup {
+            Lookup::Miss(read_at) => read_at,
+            Lookup::Hit(_) => panic!("note {} is already cached", note_id),
+        };
+
+        let stale = miss(cache.get(note_id).await);
+        cache.invalidate(note_id).await;
+        cache.put(note_id, cached.clone(), stale).await;
+        assert!(matches!(cache.get(note_id).await, Lookup::Miss(_)), "the stale read was cached");
+
+        let read_at = miss(cache.get(note_id).await);
+        assert!(read_at.shared > stale.shared);
+        cache.put(note_id, cached, read_at).await;
+        assert!(matches!(cache.get(note_id).await, Lookup::Hit(_)));
+
+        cache.invalidate(note_id).await;
+        assert!(matches!(cache.get(note_id).await, Lookup::Miss(_)));
+    }
+}
@@ -0,0 +1,309 @@
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{error::AppError, AppState};
+
+const DEFAULT_FAILURES: u32 = 5;
+
+const DEFAULT_WINDOW_SECS: u64 = 30;
+
+const DEFAULT_COOLDOWN_SECS: u64 = 10;
+
+/// Paths that answer without the database, or report on it themselves.
+const UNGUARDED_PATHS: &[&str] = &["/api/v1/healthcheck"];
+
+/// Set on an error response when the database couldn't be reached, for
+/// `guard_database` to count.
+#[derive(Debug, Clone, Copy)]
+pub struct DatabaseUnreachable;
+
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    Closed { failures: u32, since: Instant },
+    Open { since: Instant },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CircuitState {
+    Closed,
+    Open,
+}
+
+/// Stops sending requests to a database that is down. Once `failures`
+/// requests fail to reach it within `window`, the circuit opens and API
+/// requests get a 503 straight away instead of each waiting out the pool's
+/// acquire timeout. While open, a probe query every `cooldown` decides when
+/// to close it again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failures: u32,
+    window: Duration,
+    cooldown: Duration,
+    circuit: RwLock<Circuit>,
+    opened: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl CircuitBreaker {
+    /// Reads `DB_CIRCUIT_FAILURES` (default 5), `DB_CIRCUIT_WINDOW_SECS`
+    /// (default 30) and `DB_CIRCUIT_COOLDOWN_SECS` (default 10).
+    pub fn from_env() -> Self {
+        let number = |name, default| {
+            std::env::var(name)
+                .ok()
+                .map(|value| value.parse().unwrap_or_else(|_| panic!("{} must be a number", name)))
+                .unwrap_or(default)
+        };
+        let failures = number("DB_CIRCUIT_FAILURES", DEFAULT_FAILURES as u64) as u32;
+        assert!(failures > 0, "DB_CIRCUIT_FAILURES must be at least 1");
+
+        CircuitBreaker {
+            failures,
+            window: Duration::from_secs(number("DB_CIRCUIT_WINDOW_SECS", DEFAULT_WINDOW_SECS)),
+            cooldown: Duration::from_secs(number("DB_CIRCUIT_COOLDOWN_SECS", DEFAULT_COOLDOWN_SECS)),
+            circuit: RwLock::new(Circuit::Closed {
+                failures: 0,
+                since: Instant::now(),
+            }),
+            opened: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.circuit.read().unwrap() {
+            Circuit::Closed { .. } => CircuitState::Closed,
+            Circuit::Open { .. } => CircuitState::Open,
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut circuit = self.circuit.write().unwrap();
+        let Circuit::Closed { failures, since } = *circuit else {
+            return;
+        };
+        let failures = if since.elapsed() > self.window { 1 } else { failures + 1 };
+        if failures >= self.failures {
+            *circuit = Circuit::Open { since: Instant::now() };
+            self.opened.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(failures, cooldown = ?self.cooldown, "database circuit opened");
+        } else {
+            let since = if failures == 1 { Instant::now() } else { since };
+            *circuit = Circuit::Closed { failures, since };
+        }
+    }
+
+    fn close(&self) {
+        let mut circuit = self.circuit.write().unwrap();
+        if let Circuit::Open { since } = *circuit {
+            tracing::info!(open_for = ?since.elapsed(), "database circuit closed");
+        }
+        *circuit = Circuit::Closed {
+            failures: 0,
+            since: Instant::now(),
+        };
+    }
+
+    /// Seconds until the next probe, for `Retry-After`.
+    fn retry_after(&self) -> u64 {
+        match *self.circuit.read().unwrap() {
+            Circuit::Open { since } => self.cooldown.saturating_sub(since.elapsed()).as_secs().max(1),
+            Circuit::Closed { .. } => 0,
+        }
+    }
+
+    /// The 503 for a request arriving while the circuit is open, counted as
+    /// rejected.
+    pub fn rejection(&self) -> Option<AppError> {
+        if self.state() == CircuitState::Closed {
+            return None;
+        }
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        Some(
+            AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "db_unavailable",
+                "The database is unavailable; try again shortly",
+            )
+            .with_retry_after(self.retry_after()),
+        )
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "state": self.state(),
+            "opened": self.opened.load(Ordering::Relaxed),
+            "rejected": self.rejected.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Rejects API requests while the circuit is open, and counts the ones
+/// that failed to reach the database.
+pub async fn guard_database(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    let guarded = path.starts_with("/api/") && !UNGUARDED_PATHS.contains(&path.trim_end_matches('/'));
+    let breaker = &state.circuit;
+    if guarded && let Some(rejection) = breaker.rejection() {
+        return rejection.into_response();
+    }
+
+    let response = next.run(request).await;
+    if response.extensions().get::<DatabaseUnreachable>().is_some() {
+        breaker.record_failure();
+    }
+    response
+}
+
+/// While the circuit is open, sends one `SELECT 1` per cooldown and closes
+/// the circuit once it succeeds.
+pub fn spawn_circuit_probe(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let cooldown = state.circuit.cooldown;
+        loop {
+            tokio::time::sleep(cooldown).await;
+            if state.circuit.state() == CircuitState::Closed {
+                continue;
+            }
+            let probe = tokio::time::timeout(cooldown, sqlx::query("SELECT 1").execute(&state.db)).await;
+            match probe {
+                Ok(Ok(_)) => state.circuit.close(),
+                Ok(Err(e)) => tracing::warn!(error = %e, "database probe failed; circuit stays open"),
+                Err(_) => tracing::warn!("database probe timed out; circuit stays open"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use std::net::SocketAddr;
+    use tokio::{
+        net::{TcpListener, TcpStream},
+        task::{JoinHandle, JoinSet},
+    };
+
+    use super::*;
+    use crate::test_support::{self, TestApp};
+
+    /// Forwards connections to the database until stopped, when every open
+    /// connection is cut and new ones are refused, as if the server died.
+    struct Proxy {
+        addr: SocketAddr,
+        task: JoinHandle<()>,
+    }
+
+    impl Proxy {
+        async fn start(upstream: String) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let task = tokio::spawn(async move {
+                let mut connections = JoinSet::new();
+                while let Ok((mut client, _)) = listener.accept().await {
+                    let upstream = upstream.clone();
+                    connections.spawn(async move {
+                        let mut server = TcpStream::connect(upstream).await.unwrap();
+                        let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+                    });
+                }
+            });
+            Proxy { addr, task }
+        }
+
+        /// Dropping the task drops the listener and aborts every connection.
+        async fn stop(self) {
+            self.task.abort();
+            let _ = self.task.await;
+        }
+    }
+
+    /// The `host:port` of the server in `DATABASE_URL`.
+    fn database_server() -> String {
+        let url = std::env::var("DATABASE_URL").unwrap();
+        let (_, rest) = url.rsplit_once('@').unwrap_or(("", url.trim_start_matches("postgres://")));
+        let host = rest.split('/').next().unwrap();
+        if host.contains(':') { host.to_string() } else { format!("{}:5432", host) }
+    }
+
+    #[test]
+    fn opens_after_enough_failures_within_the_window() {
+        let breaker = CircuitBreaker::from_env();
+        for _ in 1..breaker.failures {
+            breaker.record_failure();
+            assert_eq!(breaker.state(), CircuitState::Closed);
+            assert!(breaker.rejection().is_none());
+        }
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let rejection = breaker.rejection().unwrap();
+        assert_eq!(rejection.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(rejection.code(), "db_unavailable");
+        assert_eq!(breaker.snapshot()["rejected"], 1);
+
+        breaker.close();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn a_stopped_database_gets_a_fast_503(_: PgPoolOptions, options: PgConnectOptions) {
+        let proxy = Proxy::start(database_server()).await;
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .acquire_timeout(Duration::from_secs(1))
+            .connect_with(options.host("127.0.0.1").port(proxy.addr.port()))
+            .await
+            .unwrap();
+        let app = TestApp::with_state(test_support::state(pool).await);
+        let note = app.create_note(json!({"title": "Up", "content": ""})).await;
+        let uri = format!("/api/v1/notes/{}", note["id"].as_str().unwrap());
+        assert_eq!(app.get(&uri).await.status, StatusCode::OK);
+
+        proxy.stop().await;
+
+        let id = note["id"].as_str().unwrap().parse().unwrap();
+        let error = crate::attachments::fetch_for_note(&app.state, id).await.unwrap_err();
+        assert!(error.database_unreachable());
+        assert_eq!(error.code(), "db_unavailable");
+
+        // Each failure to reach the database is a 503 and counts toward
+        // the circuit...
+        for _ in 0..app.state.circuit.failures {
+            assert_eq!(app.state.circuit.state(), CircuitState::Closed);
+            let response = app.get(&uri).await;
+            assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE, "{}", response.text());
+            assert_eq!(response.json()["error"]["code"], "db_unavailable");
+        }
+        assert_eq!(app.state.circuit.state(), CircuitState::Open);
+
+        // ...and once it's open, requests are turned away without waiting
+        // on the pool.
+        let started = Instant::now();
+        let response = app.get(&uri).await;
+        assert!(started.elapsed() < Duration::from_millis(100), "took {:?}", started.elapsed());
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.json()["error"]["code"], "db_unavailable");
+        assert!(response.header("retry-after").is_some());
+
+        // The health check isn't turned away; it reports the open circuit.
+        let health = app.get("/api/v1/healthcheck").await;
+        assert_eq!(health.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(health.json()["status"], "unavailable");
+        assert_eq!(health.json()["circuit"]["state"], "open");
+    }
+}
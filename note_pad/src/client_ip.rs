@@ -0,0 +1,335 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{error::AppError, AppState};
+
+/// An address block such as `10.0.0.0/8` or `::1/128`.
+#[derive(Debug, Clone, Copy)]
+struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Accepts a bare address as a block of one.
+    fn from_str(value: &str) -> Result<Self, String> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = address
+            .trim()
+            .parse()
+            .map_err(|_| format!("{:?} is not an IP address", address))?;
+        let network = network.to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("{:?} is not a prefix length for {}", prefix, network))?,
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl Cidr {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The proxies allowed to say who the client is.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<Cidr>);
+
+impl FromStr for TrustedProxies {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(TrustedProxies)
+    }
+}
+
+impl TrustedProxies {
+    /// Reads `TRUSTED_PROXIES`, a comma separated list of addresses and CIDR
+    /// blocks such as `127.0.0.1,10.0.0.0/8`. Empty by default, so
+    /// forwarding headers are ignored unless proxies are configured.
+    pub fn from_env() -> Self {
+        let Ok(value) = std::env::var("TRUSTED_PROXIES") else {
+            return TrustedProxies::default();
+        };
+        value.parse().unwrap_or_else(|e| panic!("TRUSTED_PROXIES is invalid: {}", e))
+    }
+
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// The client behind `peer`. Forwarding headers are only read when the
+    /// peer is a trusted proxy; the client is then the rightmost address in
+    /// them that isn't one, since everything to its left was supplied by
+    /// the client itself. `Forwarded` is preferred over `X-Forwarded-For`.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.trusts(peer) {
+            return peer;
+        }
+
+        let mut client = peer;
+        for hop in forwarded_chain(headers).iter().rev() {
+            // An obfuscated or malformed hop can't be checked, so the last
+            // known address is as far as the chain can be followed.
+            let Some(hop) = hop else {
+                break;
+            };
+            client = hop.to_canonical();
+            if !self.trusts(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The `for` addresses of `Forwarded`, or else of `X-Forwarded-For`, from
+/// the client outwards. Entries that aren't addresses are `None`.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .flat_map(|value| value.to_str().ok().unwrap_or_default().split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim().eq_ignore_ascii_case("for").then(|| parse_node(value))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .flat_map(|value| value.to_str().ok().unwrap_or_default().split(','))
+        .filter(|entry| !entry.trim().is_empty())
+        .map(parse_node)
+        .collect()
+}
+
+/// Parses one hop: `192.0.2.1`, `192.0.2.1:8080`, `2001:db8::1`, or the
+/// bracketed and optionally quoted `"[2001:db8::1]:8080"` of `Forwarded`.
+fn parse_node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(ip) = value.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    value.strip_prefix('[')?.split(']').next()?.parse().ok()
+}
+
+/// The address of the client that sent a request, as resolved by
+/// `resolve_client_ip`. Falls back to the socket address for requests that
+/// didn't go through it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(client_ip) = parts.extensions.get::<ClientIp>() {
+            return Ok(*client_ip);
+        }
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| ClientIp(addr.ip().to_canonical()))
+            .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "Client address unavailable".to_string()).into())
+    }
+}
+
+/// Works out the client address once per request, for `ClientIp`.
+pub async fn resolve_client_ip(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(peer) = peer {
+        let client_ip = state.trusted_proxies.client_ip(peer, request.headers());
+        request.extensions_mut().insert(ClientIp(client_ip));
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn proxies(list: &[&str]) -> TrustedProxies {
+        list.join(",").parse().unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in pairs {
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_cidr_blocks() {
+        let block: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(block.contains(ip("10.255.0.1")));
+        assert!(!block.contains(ip("11.0.0.1")));
+        assert!(block.contains(ip("::ffff:10.1.2.3")));
+
+        let single: Cidr = "2001:db8::1".parse().unwrap();
+        assert!(single.contains(ip("2001:db8::1")));
+        assert!(!single.contains(ip("2001:db8::2")));
+
+        let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains(ip("203.0.113.9")));
+        assert!(!everything.contains(ip("2001:db8::1")));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("not-an-ip".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_claim_another_address() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+        let spoofed = headers(&[
+            ("x-forwarded-for", "198.51.100.1"),
+            ("forwarded", "for=198.51.100.2"),
+        ]);
+        assert_eq!(trusted.client_ip(ip("203.0.113.5"), &spoofed), ip("203.0.113.5"));
+        assert_eq!(TrustedProxies::default().client_ip(ip("10.0.0.1"), &spoofed), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_without_headers_is_the_client() {
+        let trusted = proxies(&["10.0.0.1"]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.1"), &HeaderMap::new()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn follows_trusted_hops_to_the_first_untrusted_address() {
+        let trusted = proxies(&["10.0.0.0/8", "192.168.1.1"]);
+        // The client put a fake address in front; the two proxies appended.
+        let chain = headers(&[("x-forwarded-for", "6.6.6.6, 203.0.113.7, 192.168.1.1, 10.1.1.1")]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.2"), &chain), ip("203.0.113.7"));
+
+        // Appended as separate headers, the order is the same.
+        let split = headers(&[
+            ("x-forwarded-for", "6.6.6.6, 203.0.113.7"),
+            ("x-forwarded-for", "192.168.1.1"),
+        ]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.2"), &split), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn an_all_trusted_chain_ends_at_its_first_hop() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+        let chain = headers(&[("x-forwarded-for", "10.9.9.9, 10.1.1.1")]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.2"), &chain), ip("10.9.9.9"));
+    }
+
+    #[test]
+    fn reads_rfc_7239_forwarded_with_quoted_ipv6_and_ports() {
+        let trusted = proxies(&["10.0.0.0/8", "2001:db8:cafe::/48"]);
+        let forwarded = headers(&[(
+            "forwarded",
+            "for=\"[2001:db8::17]:4711\";proto=https, For=\"[2001:db8:cafe::1]\", for=10.0.0.3:8080",
+        )]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.2"), &forwarded), ip("2001:db8::17"));
+
+        let ipv4 = headers(&[("forwarded", "proto=http;for=198.51.100.4:51000;by=10.0.0.2")]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.2"), &ipv4), ip("198.51.100.4"));
+    }
+
+    #[test]
+    fn forwarded_wins_over_x_forwarded_for() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+        let both = headers(&[
+            ("x-forwarded-for", "198.51.100.1"),
+            ("forwarded", "for=198.51.100.2"),
+        ]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.2"), &both), ip("198.51.100.2"));
+    }
+
+    #[test]
+    fn stops_at_hops_that_are_not_addresses() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+        for value in ["for=unknown", "for=_hidden", "for=\"[bogus]\"", "for="] {
+            let obfuscated = headers(&[("forwarded", value)]);
+            assert_eq!(trusted.client_ip(ip("10.0.0.2"), &obfuscated), ip("10.0.0.2"), "{}", value);
+        }
+        // The client is the last address the chain could be followed to.
+        let garbage = headers(&[("x-forwarded-for", "198.51.100.1, not-an-ip, 10.2.2.2")]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.2"), &garbage), ip("10.2.2.2"));
+        let empty = headers(&[("x-forwarded-for", " , ,")]);
+        assert_eq!(trusted.client_ip(ip("10.0.0.2"), &empty), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn parse_node_accepts_each_hop_form() {
+        assert_eq!(parse_node(" 192.0.2.1 "), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[2001:db8::1]:8080"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("\"[2001:db8::1]\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("999.1.1.1"), None);
+        assert_eq!(parse_node(""), None);
+    }
+
+    #[test]
+    fn mapped_ipv4_peers_match_ipv4_blocks() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+        let chain = headers(&[("x-forwarded-for", "::ffff:198.51.100.8")]);
+        assert_eq!(trusted.client_ip(ip("::ffff:10.0.0.2"), &chain), ip("198.51.100.8"));
+    }
+}
@@ -0,0 +1,134 @@
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use chrono::{DateTime, Utc};
+
+use crate::{error::AppError, Note};
+
+/// A parsed `If-Match` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IfMatch {
+    /// `*`: any current version.
+    Any,
+    Version(i32),
+}
+
+/// The ETag of a note at `version`.
+pub fn etag(version: i32) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", version)).expect("a quoted number is a valid header value")
+}
+
+/// Whether `If-None-Match` names `etag`, a quoted tag, or is `*`, so a GET
+/// can be answered with 304.
+pub fn none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+            })
+        })
+}
+
+/// Whether deletes must carry `If-Match`, from `REQUIRE_IF_MATCH_ON_DELETE`
+/// (default false).
+pub fn require_if_match_from_env() -> bool {
+    std::env::var("REQUIRE_IF_MATCH_ON_DELETE")
+        .ok()
+        .map(|value| value.parse().expect("REQUIRE_IF_MATCH_ON_DELETE must be true or false"))
+        .unwrap_or(false)
+}
+
+/// Reads `If-Match`, accepting the note's ETag with or without quotes and a
+/// weak `W/` prefix. Lists of several tags aren't supported, since a note
+/// only ever has one current version.
+pub fn if_match(headers: &HeaderMap) -> Result<Option<IfMatch>, AppError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+
+    let value = value.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return Ok(Some(IfMatch::Any));
+    }
+
+    let tag = value.strip_prefix("W/").unwrap_or(value);
+    let tag = tag
+        .strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+        .unwrap_or(tag);
+
+    tag.parse().map(|version| Some(IfMatch::Version(version))).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("If-Match must be a note ETag such as \"3\", got '{}'", value),
+        )
+            .into()
+    })
+}
+
+/// The 412 returned when `If-Match` names an outdated version.
+pub fn precondition_failed(current_version: i32) -> AppError {
+    AppError::new(
+        StatusCode::PRECONDITION_FAILED,
+        "version_mismatch",
+        "The note was changed since the given version",
+    )
+    .with_details(serde_json::json!({
+        "current_version": current_version,
+        "etag": format!("\"{}\"", current_version),
+    }))
+}
+
+/// The 409 returned when an update's `base_updated_at` is outdated. Both
+/// sides are included so a client can offer to merge.
+pub fn edit_conflict(base_updated_at: DateTime<Utc>, current: &Note) -> AppError {
+    AppError::new(
+        StatusCode::CONFLICT,
+        "edit_conflict",
+        "The note was changed since base_updated_at",
+    )
+    .with_details(serde_json::json!({
+        "base_updated_at": base_updated_at,
+        "current": current,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_none_match(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn none_match_finds_the_tag_in_a_list() {
+        assert!(none_match(&if_none_match("\"abc\""), "\"abc\""));
+        assert!(none_match(&if_none_match("\"x\", \"abc\" ,\"y\""), "\"abc\""));
+        assert!(none_match(&if_none_match("\"x\",\"abc\""), "\"abc\""));
+    }
+
+    #[test]
+    fn none_match_accepts_weak_tags() {
+        assert!(none_match(&if_none_match("W/\"abc\""), "\"abc\""));
+        assert!(none_match(&if_none_match("\"x\", W/\"abc\""), "\"abc\""));
+    }
+
+    #[test]
+    fn none_match_matches_anything_for_a_star() {
+        assert!(none_match(&if_none_match("*"), "\"abc\""));
+        assert!(none_match(&if_none_match(" * "), "\"abc\""));
+    }
+
+    #[test]
+    fn none_match_rejects_other_tags() {
+        assert!(!none_match(&HeaderMap::new(), "\"abc\""));
+        assert!(!none_match(&if_none_match("\"abd\""), "\"abc\""));
+        assert!(!none_match(&if_none_match("\"x\", W/\"y\""), "\"abc\""));
+        assert!(!none_match(&if_none_match("abc"), "\"abc\""));
+        assert!(!none_match(&if_none_match(""), "\"abc\""));
+    }
+}
@@ -0,0 +1,52 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use crate::error::AppError;
+
+/// Largest `MAX_CONTENT_BYTES` allowed, which the `notes_content_size`
+/// check constraint enforces as a backstop.
+pub const CEILING: usize = 16 * 1024 * 1024;
+
+const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+
+/// Extra room in a request body for the other fields and JSON syntax.
+const BODY_OVERHEAD: usize = 64 * 1024;
+
+/// Reads `MAX_CONTENT_BYTES`, the largest note content in bytes of UTF-8
+/// (default 1 MiB, at most 16 MiB).
+pub fn max_bytes_from_env() -> usize {
+    let max_bytes = std::env::var("MAX_CONTENT_BYTES")
+        .ok()
+        .map(|value| value.parse().expect("MAX_CONTENT_BYTES must be a number of bytes"))
+        .unwrap_or(DEFAULT_MAX_BYTES);
+    assert!(
+        (1..=CEILING).contains(&max_bytes),
+        "MAX_CONTENT_BYTES must be between 1 and {}",
+        CEILING
+    );
+    max_bytes
+}
+
+/// The request body limit for routes taking note content, twice the content
+/// limit so that escaped JSON over the limit still gets the 422 from
+/// `check` rather than a bare 413.
+pub fn body_limit(max_bytes: usize) -> usize {
+    max_bytes * 2 + BODY_OVERHEAD
+}
+
+/// Refuses content longer than `max_bytes` bytes once encoded as UTF-8.
+pub fn check(max_bytes: usize, content: &str) -> Result<(), AppError> {
+    if content.len() <= max_bytes {
+        return Ok(());
+    }
+    Err(AppError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "content_too_large",
+        format!("Content is {} bytes, over the limit of {} bytes", content.len(), max_bytes),
+    )
+    .with_details(json!({
+        "field": "content",
+        "limit": max_bytes,
+        "size": content.len(),
+    })))
+}
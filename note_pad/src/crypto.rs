@@ -0,0 +1,259 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sqlx::{postgres::PgRow, PgPool, Row};
+use std::{fmt, sync::OnceLock};
+use uuid::Uuid;
+
+/// Rows re-encrypted per transaction by `rekey`.
+const REKEY_BATCH_SIZE: i64 = 100;
+
+/// First byte of `seal_bytes` output.
+const SEALED_PLAINTEXT: u8 = 0;
+const SEALED_ENCRYPTED: u8 = 1;
+
+/// Set once at startup. Content is decoded in `from_row` constructors, which
+/// have no access to application state.
+static CIPHER: OnceLock<Option<ContentCipher>> = OnceLock::new();
+
+pub struct ContentCipher {
+    cipher: Aes256Gcm,
+}
+
+/// Stored content that can't be decrypted: a wrong or missing key, or a
+/// corrupted row.
+#[derive(Debug)]
+pub struct DecryptError;
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("note content could not be decrypted")
+    }
+}
+
+impl std::error::Error for DecryptError {}
+
+/// How a note's content is written to the `content`, `content_nonce` and
+/// `content_ciphertext` columns.
+pub struct SealedContent {
+    pub plaintext: String,
+    pub nonce: Option<Vec<u8>>,
+    pub ciphertext: Option<Vec<u8>>,
+}
+
+impl ContentCipher {
+    /// Parses a base64 encoded 32-byte key.
+    pub fn from_base64(key: &str) -> Result<Self, String> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|e| format!("key is not valid base64: {}", e))?;
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| format!("key must be 32 bytes, got {}", key.len()))?;
+        Ok(ContentCipher { cipher })
+    }
+
+    fn from_env_var(name: &str) -> Option<Self> {
+        let key = std::env::var(name).ok()?;
+        Some(Self::from_base64(&key).unwrap_or_else(|e| panic!("{} is invalid: {}", name, e)))
+    }
+
+    fn encrypt(&self, plaintext: &str) -> (Vec<u8>, Vec<u8>) {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("AES-GCM encryption cannot fail for in-memory buffers");
+        (nonce.to_vec(), ciphertext)
+    }
+
+    fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<String, DecryptError> {
+        if nonce.len() != 12 {
+            return Err(DecryptError);
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| DecryptError)?;
+        String::from_utf8(plaintext).map_err(|_| DecryptError)
+    }
+}
+
+/// Loads `ENCRYPTION_KEY` (base64, 32 bytes), if set, as the key for all
+/// content reads and writes.
+///
+/// With a key, new and updated content is stored as AES-256-GCM ciphertext
+/// with a random per-write nonce, and the plaintext `content` column is left
+/// empty. Titles stay in plaintext so listing, wiki links and title search
+/// keep working; the price is that titles are readable in backups and that
+/// related-note ranking only sees the titles of encrypted notes. Rows written
+/// before a key was configured stay readable and are encrypted by
+/// `note_pad rekey`.
+pub fn init_from_env() {
+    let cipher = ContentCipher::from_env_var("ENCRYPTION_KEY");
+    if cipher.is_some() {
+        tracing::info!("note content encryption enabled");
+    }
+    CIPHER.set(cipher).ok();
+}
+
+fn cipher() -> Option<&'static ContentCipher> {
+    CIPHER.get().and_then(Option::as_ref)
+}
+
+/// Whether content is being encrypted, so it can't be changed in SQL.
+pub fn enabled() -> bool {
+    cipher().is_some()
+}
+
+/// Prepares content for storage, encrypting it when a key is configured.
+pub fn seal(content: &str) -> SealedContent {
+    seal_with(cipher(), content)
+}
+
+fn seal_with(cipher: Option<&ContentCipher>, content: &str) -> SealedContent {
+    match cipher {
+        Some(cipher) => {
+            let (nonce, ciphertext) = cipher.encrypt(content);
+            SealedContent {
+                plaintext: String::new(),
+                nonce: Some(nonce),
+                ciphertext: Some(ciphertext),
+            }
+        }
+        None => SealedContent {
+            plaintext: content.to_string(),
+            nonce: None,
+            ciphertext: None,
+        },
+    }
+}
+
+/// Seals data kept outside the database, such as notes in a shared cache,
+/// the same way content is: encrypted when a key is configured.
+pub fn seal_bytes(plaintext: &[u8]) -> Vec<u8> {
+    match cipher() {
+        Some(cipher) => {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = cipher
+                .cipher
+                .encrypt(&nonce, plaintext)
+                .expect("AES-GCM encryption cannot fail for in-memory buffers");
+            [&[SEALED_ENCRYPTED][..], &nonce, &ciphertext].concat()
+        }
+        None => [&[SEALED_PLAINTEXT][..], plaintext].concat(),
+    }
+}
+
+/// Opens what `seal_bytes` produced, possibly on another instance.
+pub fn open_bytes(sealed: &[u8]) -> Result<Vec<u8>, DecryptError> {
+    match sealed.split_first() {
+        Some((&SEALED_PLAINTEXT, plaintext)) => Ok(plaintext.to_vec()),
+        Some((&SEALED_ENCRYPTED, rest)) if rest.len() >= 12 => {
+            let (nonce, ciphertext) = rest.split_at(12);
+            let cipher = cipher().ok_or(DecryptError)?;
+            cipher
+                .cipher
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| DecryptError)
+        }
+        _ => Err(DecryptError),
+    }
+}
+
+/// Reads a note's content from a row that selected `content`,
+/// `content_nonce` and `content_ciphertext`, decrypting it if needed.
+pub fn content(row: &PgRow) -> Result<String, sqlx::Error> {
+    open_with(cipher(), row).map_err(|e| match e {
+        OpenError::Decrypt(e) => sqlx::Error::Decode(Box::new(e)),
+        OpenError::Sql(e) => e,
+    })
+}
+
+/// Whether an error came from content that couldn't be decrypted.
+pub fn is_decrypt_error(error: &sqlx::Error) -> bool {
+    matches!(error, sqlx::Error::Decode(source) if source.is::<DecryptError>())
+}
+
+enum OpenError {
+    Decrypt(DecryptError),
+    Sql(sqlx::Error),
+}
+
+fn open_with(cipher: Option<&ContentCipher>, row: &PgRow) -> Result<String, OpenError> {
+    let nonce: Option<Vec<u8>> = row.try_get("content_nonce").map_err(OpenError::Sql)?;
+    let Some(nonce) = nonce else {
+        return row.try_get("content").map_err(OpenError::Sql);
+    };
+    let ciphertext: Vec<u8> = row.try_get("content_ciphertext").map_err(OpenError::Sql)?;
+    let cipher = cipher.ok_or(OpenError::Decrypt(DecryptError))?;
+
+    cipher.decrypt(&nonce, &ciphertext).map_err(OpenError::Decrypt)
+}
+
+/// `note_pad rekey`: re-encrypts every note under `ENCRYPTION_KEY`. Rows
+/// encrypted under the previous key need `OLD_ENCRYPTION_KEY`; plaintext
+/// rows are encrypted as well. Each batch commits on its own, so an
+/// interrupted run can simply be restarted with the same keys.
+pub async fn rekey(pool: &PgPool) -> Result<u64, String> {
+    let new = ContentCipher::from_env_var("ENCRYPTION_KEY").ok_or("ENCRYPTION_KEY must be set to rekey")?;
+    let old = ContentCipher::from_env_var("OLD_ENCRYPTION_KEY");
+
+    let mut rekeyed = 0;
+    let mut after = Uuid::nil();
+
+    loop {
+        let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+        let rows = sqlx::query(
+            "SELECT id, content, content_nonce, content_ciphertext FROM notes
+             WHERE id > $1 ORDER BY id LIMIT $2 FOR UPDATE",
+        )
+        .bind(after)
+        .bind(REKEY_BATCH_SIZE)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        for row in &rows {
+            let id: Uuid = row.try_get("id").map_err(|e| e.to_string())?;
+            after = id;
+
+            // Rows already under the new key are left alone, which makes
+            // restarts cheap.
+            let encrypted = row
+                .try_get::<Option<Vec<u8>>, _>("content_nonce")
+                .map_err(|e| e.to_string())?
+                .is_some();
+            if encrypted && open_with(Some(&new), row).is_ok() {
+                continue;
+            }
+
+            let content = match open_with(old.as_ref(), row) {
+                Ok(content) => content,
+                Err(OpenError::Decrypt(_)) => {
+                    return Err(format!("note {} could not be decrypted with OLD_ENCRYPTION_KEY", id));
+                }
+                Err(OpenError::Sql(e)) => return Err(e.to_string()),
+            };
+
+            let sealed = seal_with(Some(&new), &content);
+            sqlx::query("UPDATE notes SET content = $1, content_nonce = $2, content_ciphertext = $3 WHERE id = $4")
+                .bind(sealed.plaintext)
+                .bind(sealed.nonce)
+                .bind(sealed.ciphertext)
+                .bind(id)
+                .execute(&mut tx)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            rekeyed += 1;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        if (rows.len() as i64) < REKEY_BATCH_SIZE {
+            return Ok(rekeyed);
+        }
+    }
+}
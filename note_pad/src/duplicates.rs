@@ -0,0 +1,407 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{postgres::PgRow, PgConnection, Row};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{crypto, error::AppError, extract::{Json, Query}, query::visible, unaccent, AppState, NoteSummary, SUMMARY_COLUMNS};
+
+/// Notes hashed per statement by the startup backfill.
+const BACKFILL_BATCH_SIZE: i64 = 500;
+
+const DEFAULT_LIMIT: i64 = 20;
+
+const MAX_LIMIT: i64 = 100;
+
+/// Trigram similarity from which a title counts as close to another, on
+/// pg_trgm's 0 to 1 scale. "Meeting notes 2024-05-02" and "Meeting notes
+/// 2024-05-03" score about 0.8; unrelated titles rarely pass 0.2.
+const SIMILAR_TITLE_THRESHOLD: f32 = 0.6;
+
+const MAX_SIMILAR_TITLES: i64 = 10;
+
+/// What creating a note identical to an existing one does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Create it anyway, naming the existing note in `duplicate_of`.
+    Allow,
+    /// Refuse it with a 409 naming the existing note.
+    Reject,
+}
+
+impl DuplicatePolicy {
+    /// Reads `DUPLICATE_NOTES`: `allow` (the default) or `reject`.
+    pub fn from_env() -> Self {
+        match std::env::var("DUPLICATE_NOTES").as_deref() {
+            Err(_) | Ok("allow") => DuplicatePolicy::Allow,
+            Ok("reject") => DuplicatePolicy::Reject,
+            Ok(_) => panic!("DUPLICATE_NOTES must be allow or reject"),
+        }
+    }
+}
+
+/// Normalizes text for duplicate detection: trimmed, each run of
+/// whitespace collapsed to one space, and lowercased, so notes differing
+/// only in spacing, line breaks or case count as the same.
+pub fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// The hex SHA-256 of the normalized title and content. They're joined by a
+/// newline, which normalized text never contains, so moving words between
+/// title and content changes the hash.
+pub fn content_hash(title: &str, content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(normalize(title));
+    hasher.update("\n");
+    hasher.update(normalize(content));
+    format!("{:x}", hasher.finalize())
+}
+
+/// Stores the hash for a note's current title and content, for writes that
+/// change either after the note was inserted.
+pub async fn store_hash(conn: &mut PgConnection, id: Uuid, title: &str, content: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE notes SET content_hash = $1 WHERE id = $2")
+        .bind(content_hash(title, content))
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Recomputes the hash of the given notes, for writes that bypass the
+/// usual ones, such as restoring a backup.
+pub async fn rehash(conn: &mut PgConnection, ids: &[Uuid]) -> Result<u64, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, title, content, content_nonce, content_ciphertext FROM notes WHERE id = ANY($1)")
+        .bind(ids)
+        .fetch_all(&mut *conn)
+        .await?;
+    store_hashes(conn, &rows).await
+}
+
+async fn store_hashes(conn: &mut PgConnection, rows: &[PgRow]) -> Result<u64, sqlx::Error> {
+    let mut ids = Vec::with_capacity(rows.len());
+    let mut hashes = Vec::with_capacity(rows.len());
+    for row in rows {
+        ids.push(row.try_get::<Uuid, _>("id")?);
+        hashes.push(content_hash(row.try_get("title")?, &crypto::content(row)?));
+    }
+
+    let result = sqlx::query(
+        "UPDATE notes SET content_hash = h.content_hash
+         FROM UNNEST($1::uuid[], $2::text[]) AS h(id, content_hash)
+         WHERE notes.id = h.id",
+    )
+    .bind(&ids)
+    .bind(&hashes)
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// The oldest note with the same title and content, ignoring password
+/// protected notes so their content can't be probed for.
+pub async fn find_duplicate(conn: &mut PgConnection, title: &str, content: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar(
+        concat!("SELECT id FROM notes
+         WHERE content_hash = $1 AND password_hash IS NULL AND ", visible!(), "
+         ORDER BY created_at, id
+         LIMIT 1"),
+    )
+    .bind(content_hash(title, content))
+    .fetch_optional(&mut *conn)
+    .await
+}
+
+/// `?check_duplicates=true` on creating a note.
+#[derive(Debug, Default, Deserialize)]
+pub struct CheckDuplicatesParams {
+    #[serde(default)]
+    check_duplicates: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimilarNote {
+    id: Uuid,
+    title: String,
+    /// 1 for the same title, ignoring case, accents and spacing.
+    similarity: f32,
+}
+
+/// Notes whose title is the same as `title` once normalized or close to it
+/// by trigram similarity, the closest first. Titles are folded as for the
+/// title filter, so this can use its index.
+pub async fn find_similar_titles(conn: &mut PgConnection, title: &str) -> Result<Vec<SimilarNote>, sqlx::Error> {
+    let folded = unaccent::fold("title");
+    let folded_input = unaccent::fold("$1");
+    let rows = sqlx::query(&format!(
+        concat!("SELECT id, title, similarity FROM (
+             SELECT id, title,
+                    CASE WHEN lower(regexp_replace(btrim(title), '\\s+', ' ', 'g')) = $2 THEN 1
+                         ELSE similarity({folded}, {folded_input}) END::real AS similarity
+             FROM notes
+             WHERE ", visible!(), "
+               AND ({folded} % {folded_input} OR lower(regexp_replace(btrim(title), '\\s+', ' ', 'g')) = $2)
+         ) candidates
+         WHERE similarity >= $3
+         ORDER BY similarity DESC, id
+         LIMIT $4"),
+        folded = folded,
+        folded_input = folded_input,
+    ))
+    .bind(title)
+    .bind(normalize(title))
+    .bind(SIMILAR_TITLE_THRESHOLD)
+    .bind(MAX_SIMILAR_TITLES)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut similar = Vec::with_capacity(rows.len());
+    for row in rows {
+        similar.push(SimilarNote {
+            id: row.try_get("id")?,
+            title: row.try_get("title")?,
+            similarity: row.try_get("similarity")?,
+        });
+    }
+    Ok(similar)
+}
+
+/// With `?check_duplicates=true`, refuses a new note whose title matches or
+/// nearly matches existing ones with a 409 listing them, so the client can
+/// ask before creating it anyway without the flag.
+pub async fn check_similar_titles(
+    params: &CheckDuplicatesParams,
+    conn: &mut PgConnection,
+    title: &str,
+) -> Result<(), AppError> {
+    if !params.check_duplicates {
+        return Ok(());
+    }
+    let similar = find_similar_titles(conn, title).await?;
+    if similar.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::new(
+        StatusCode::CONFLICT,
+        "similar_notes",
+        "Notes with the same or a similar title already exist",
+    )
+    .with_details(serde_json::json!({ "similar_notes": similar })))
+}
+
+/// Checks a new note against existing ones under the configured policy,
+/// returning the note it duplicates when that's allowed.
+pub async fn check_new(
+    state: &AppState,
+    conn: &mut PgConnection,
+    title: &str,
+    content: &str,
+) -> Result<Option<Uuid>, AppError> {
+    let duplicate_of = find_duplicate(conn, title, content).await?;
+    match (duplicate_of, state.duplicate_policy) {
+        (Some(duplicate_of), DuplicatePolicy::Reject) => Err(AppError::new(
+            StatusCode::CONFLICT,
+            "duplicate_note",
+            "A note with the same title and content already exists",
+        )
+        .with_details(serde_json::json!({ "duplicate_of": duplicate_of }))),
+        (duplicate_of, _) => Ok(duplicate_of),
+    }
+}
+
+/// Hashes notes written before hashes were kept, in batches, once at
+/// startup. Stops at the first note that can't be read, such as one
+/// encrypted under another key, leaving the rest for the next start.
+pub fn spawn_hash_backfill(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut hashed = 0;
+        loop {
+            let batch = async {
+                let mut conn = state.db.acquire().await?;
+                let rows = sqlx::query(
+                    "SELECT id, title, content, content_nonce, content_ciphertext FROM notes
+                     WHERE content_hash IS NULL
+                     LIMIT $1",
+                )
+                .bind(BACKFILL_BATCH_SIZE)
+                .fetch_all(&mut *conn)
+                .await?;
+                store_hashes(&mut conn, &rows).await
+            };
+            match batch.await {
+                Ok(0) => break,
+                Ok(count) => hashed += count,
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to hash existing notes");
+                    return;
+                }
+            }
+        }
+        if hashed > 0 {
+            tracing::info!(hashed, "hashed existing notes for duplicate detection");
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DuplicatesParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    content_hash: String,
+    /// Oldest first, so the first is usually the one to keep.
+    notes: Vec<NoteSummary>,
+}
+
+/// Groups of notes sharing a title and content, the group with the oldest
+/// note first. `limit` (default 20, at most 100) and `offset` count groups.
+/// Password protected notes aren't included.
+pub async fn get_duplicates(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DuplicatesParams>,
+) -> Result<Json<Vec<DuplicateGroup>>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let rows = sqlx::query(&format!(
+        concat!("WITH duplicate_groups AS (
+             SELECT content_hash, MIN(created_at) AS first_created_at FROM notes
+             WHERE content_hash IS NOT NULL AND password_hash IS NULL AND ", visible!(), "
+             GROUP BY content_hash
+             HAVING COUNT(*) > 1
+             ORDER BY first_created_at, content_hash
+             LIMIT $1 OFFSET $2
+         )
+         SELECT {} FROM notes JOIN duplicate_groups USING (content_hash)
+         WHERE password_hash IS NULL AND ", visible!(), "
+         ORDER BY duplicate_groups.first_created_at, content_hash, notes.created_at, notes.id"),
+        SUMMARY_COLUMNS
+    ))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    for row in &rows {
+        let content_hash: String = row.try_get("content_hash")?;
+        let note = NoteSummary::from_row(row, false)?;
+        match groups.last_mut() {
+            Some(group) if group.content_hash == content_hash => group.notes.push(note),
+            _ => groups.push(DuplicateGroup {
+                content_hash,
+                notes: vec![note],
+            }),
+        }
+    }
+
+    Ok(Json(groups))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::TestApp;
+
+    #[test]
+    fn normalize_collapses_whitespace_and_case() {
+        assert_eq!(normalize("  Buy\tMILK\n\nand  eggs \r\n"), "buy milk and eggs");
+        assert_eq!(normalize(" \n\t "), "");
+    }
+
+    #[test]
+    fn whitespace_and_case_variants_hash_the_same() {
+        let hash = content_hash("Groceries", "Buy milk and eggs");
+        assert_eq!(content_hash("  groceries ", "buy  MILK\nand\teggs\n"), hash);
+        assert_eq!(content_hash("GROCERIES", "Buy milk and eggs"), hash);
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn moving_words_between_title_and_content_changes_the_hash() {
+        let hash = content_hash("Groceries", "Buy milk and eggs");
+        assert_ne!(content_hash("Groceries Buy", "milk and eggs"), hash);
+        assert_ne!(content_hash("", "Groceries Buy milk and eggs"), hash);
+        assert_ne!(content_hash("Groceries Buy milk and eggs", ""), hash);
+        assert_ne!(content_hash("Groceries", "Buy milk and bread"), hash);
+    }
+
+    async fn similar_titles(app: &TestApp, title: &str) -> Vec<(String, f32)> {
+        let mut conn = app.state.db.acquire().await.unwrap();
+        find_similar_titles(&mut conn, title)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|note| (note.title, note.similarity))
+            .collect()
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn titles_equal_once_normalized_score_one(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        app.create_note(json!({ "title": "Meeting notes", "content": "a" })).await;
+
+        assert_eq!(similar_titles(&app, "  MEETING\tnotes ").await, [("Meeting notes".to_string(), 1.0)]);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn only_titles_past_the_threshold_are_similar(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        for title in ["Meeting notes 2024-05-02", "Project plans for Q3", "Grocery list"] {
+            app.create_note(json!({ "title": title, "content": "a" })).await;
+        }
+
+        let similar = similar_titles(&app, "Meeting notes 2024-05-03").await;
+        assert_eq!(similar.len(), 1, "{:?}", similar);
+        assert_eq!(similar[0].0, "Meeting notes 2024-05-02");
+        assert!((SIMILAR_TITLE_THRESHOLD..1.0).contains(&similar[0].1), "{:?}", similar);
+
+        // About 0.52: close enough for pg_trgm's own 0.3 cut-off, not ours.
+        assert_eq!(similar_titles(&app, "Project plan").await, []);
+        assert_eq!(similar_titles(&app, "Holiday ideas").await, []);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn similar_titles_are_refused_only_when_asked(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let existing = app.create_note(json!({ "title": "Meeting notes", "content": "a" })).await;
+
+        app.create_note(json!({ "title": "Meeting Notes", "content": "b" })).await;
+
+        let response = app
+            .send_json(
+                Method::POST,
+                "/api/v1/notes?check_duplicates=true",
+                json!({ "title": "meeting notes", "content": "c" }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        let error = &response.json()["error"];
+        assert_eq!(error["code"], "similar_notes");
+        let similar = error["details"]["similar_notes"].as_array().unwrap();
+        assert_eq!(similar.len(), 2);
+        assert!(similar.iter().any(|note| note["id"] == existing["id"]));
+        assert!(similar.iter().all(|note| note["similarity"] == 1.0));
+
+        let response = app
+            .send_json(
+                Method::POST,
+                "/api/v1/notes?check_duplicates=true",
+                json!({ "title": "Grocery list", "content": "c" }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    }
+}
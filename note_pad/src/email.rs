@@ -0,0 +1,223 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use lettre::{
+    message::{Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    client_ip::ClientIp, crypto, error::AppError, extract::{Json, Path}, passwords, query::visible, rate_limit::RateLimiter, render, AppState,
+};
+
+/// Longest an SMTP exchange may take before the delivery is marked failed.
+const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long delivery statuses can be looked up, and how many are kept.
+const DELIVERY_TTL: Duration = Duration::from_secs(24 * 3600);
+const MAX_DELIVERIES: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailFormat {
+    /// The rendered Markdown, with the raw content as the plain text part.
+    #[default]
+    Html,
+    Text,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailRequest {
+    to: String,
+    #[serde(default)]
+    format: EmailFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Queued,
+    Sent,
+    Failed,
+}
+
+/// The outcome of one email, kept in memory for `DELIVERY_TTL`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    id: Uuid,
+    note_id: Uuid,
+    to: String,
+    format: EmailFormat,
+    status: DeliveryStatus,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+/// An SMTP connection and the deliveries sent through it.
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    deliveries: Cache<Uuid, Delivery>,
+    limiter: RateLimiter,
+}
+
+impl Mailer {
+    /// Reads `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`, `SMTP_PASSWORD`,
+    /// `SMTP_FROM` and `SMTP_TLS` (`starttls`, the default, `tls` or `none`).
+    /// Email is disabled without `SMTP_HOST`. Sends are limited per client
+    /// by `EMAIL_RATE_LIMIT` per `EMAIL_RATE_WINDOW_SECS` (10 an hour), so
+    /// the endpoint can't be turned into a spam relay.
+    pub fn from_env() -> Option<Self> {
+        let host = std::env::var("SMTP_HOST").ok()?;
+        let tls = std::env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string());
+        let builder = match tls.as_str() {
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host),
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host),
+            "none" => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host)),
+            _ => panic!("SMTP_TLS must be starttls, tls or none"),
+        }
+        .unwrap_or_else(|e| panic!("SMTP_HOST {:?} is invalid: {}", host, e));
+        let default_port = match tls.as_str() {
+            "tls" => 465,
+            "none" => 25,
+            _ => 587,
+        };
+        let port = std::env::var("SMTP_PORT")
+            .ok()
+            .map(|value| value.parse().expect("SMTP_PORT must be a port number"))
+            .unwrap_or(default_port);
+        let mut builder = builder.port(port).timeout(Some(SEND_TIMEOUT));
+        if let Ok(username) = std::env::var("SMTP_USERNAME") {
+            let password = std::env::var("SMTP_PASSWORD").unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        let from = std::env::var("SMTP_FROM")
+            .expect("SMTP_FROM must be set when SMTP_HOST is")
+            .parse()
+            .expect("SMTP_FROM must be an address such as Notes <notes@example.com>");
+
+        tracing::info!(host, port, "sending email through SMTP");
+        Some(Mailer {
+            transport: builder.build(),
+            from,
+            deliveries: Cache::builder()
+                .max_capacity(MAX_DELIVERIES)
+                .time_to_live(DELIVERY_TTL)
+                .build(),
+            limiter: RateLimiter::from_env("EMAIL", 10, 3600),
+        })
+    }
+}
+
+fn mailer(state: &AppState) -> Result<&Mailer, AppError> {
+    state.mailer.as_ref().ok_or_else(|| {
+        AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "email_disabled",
+            "Email is not configured on this server",
+        )
+    })
+}
+
+/// Emails a note, titled by the note's title. The message is sent in the
+/// background: the response is a 202 with the queued delivery, whose status
+/// can then be polled at the `Location` it names.
+pub async fn send_note(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client_ip): ClientIp,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(payload): Json<EmailRequest>,
+) -> Result<Response, AppError> {
+    let mailer = mailer(&state)?;
+    let to: Mailbox = payload.to.parse().map_err(|_| {
+        AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "validation_failed",
+            format!("{:?} is not an email address", payload.to),
+        )
+        .with_details(serde_json::json!({ "field": "to" }))
+    })?;
+    mailer.limiter.check(client_ip)?;
+
+    let row = sqlx::query(concat!("SELECT * FROM notes WHERE id = $1 AND ", visible!()))
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+    passwords::unlock(&state, id, row.try_get("password_hash")?, passwords::supplied(&headers, None)).await?;
+    let title: String = row.try_get("title")?;
+    let content = crypto::content(&row)?;
+
+    let message = Message::builder().from(mailer.from.clone()).to(to).subject(&title);
+    let message = match payload.format {
+        EmailFormat::Text => message.singlepart(SinglePart::plain(content)),
+        EmailFormat::Html => {
+            let html = format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n{body}</body>\n</html>\n",
+                title = render::escape_html(&title),
+                body = render::markdown_to_html(&content),
+            );
+            message.multipart(MultiPart::alternative_plain_html(content, html))
+        }
+    }
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let delivery = Delivery {
+        id: Uuid::new_v4(),
+        note_id: id,
+        to: payload.to,
+        format: payload.format,
+        status: DeliveryStatus::Queued,
+        error: None,
+        created_at: Utc::now(),
+        finished_at: None,
+    };
+    mailer.deliveries.insert(delivery.id, delivery.clone());
+
+    tokio::spawn({
+        let state = state.clone();
+        let mut delivery = delivery.clone();
+        async move {
+            let Some(mailer) = state.mailer.as_ref() else {
+                return;
+            };
+            match mailer.transport.send(message).await {
+                Ok(_) => delivery.status = DeliveryStatus::Sent,
+                Err(e) => {
+                    tracing::warn!(delivery_id = %delivery.id, error = %e, "failed to send note email");
+                    delivery.status = DeliveryStatus::Failed;
+                    delivery.error = Some(e.to_string());
+                }
+            }
+            delivery.finished_at = Some(Utc::now());
+            mailer.deliveries.insert(delivery.id, delivery);
+        }
+    });
+
+    let location = format!("/api/v1/notes/{}/email/{}", id, delivery.id);
+    Ok((StatusCode::ACCEPTED, [(header::LOCATION, location)], Json(delivery)).into_response())
+}
+
+/// The status of an email sent in the last `DELIVERY_TTL`.
+pub async fn get_delivery(
+    State(state): State<Arc<AppState>>,
+    Path((note_id, delivery_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<Delivery>, AppError> {
+    mailer(&state)?
+        .deliveries
+        .get(&delivery_id)
+        .filter(|delivery| delivery.note_id == note_id)
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Delivery not found".to_string()).into())
+}
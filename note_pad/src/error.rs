@@ -0,0 +1,463 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use std::{any::Any, fmt};
+
+use crate::{circuit, crypto, request_id, retry, statement_timeout};
+
+/// An API error, rendered as `{"error": {"code": ..., "message": ...}}`,
+/// plus `details` when there's structured context and `request_id`, the
+/// same ID as the `X-Request-Id` response header. `code` is a stable
+/// machine-readable identifier; `message` is for humans and may change.
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: Option<serde_json::Value>,
+    retry_after: Option<u64>,
+    database_unreachable: bool,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        AppError {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+            retry_after: None,
+            database_unreachable: false,
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn details(&self) -> Option<&serde_json::Value> {
+        self.details.as_ref()
+    }
+
+    /// Whether this is a failure to reach the database, which counts
+    /// towards opening the circuit breaker.
+    pub fn database_unreachable(&self) -> bool {
+        self.database_unreachable
+    }
+
+    /// Adds a `Retry-After` header, in seconds.
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+/// Errors raised as a bare status and message get the generic code for
+/// their status.
+impl From<(StatusCode, String)> for AppError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        AppError::new(status, default_code(status), message)
+    }
+}
+
+/// A body, query string or path that `crate::extract` couldn't parse keeps
+/// axum's status and message, in the usual error body.
+macro_rules! from_rejection {
+    ($($rejection:ty),*) => {$(
+        impl From<$rejection> for AppError {
+            fn from(rejection: $rejection) -> Self {
+                (rejection.status(), rejection.body_text()).into()
+            }
+        }
+    )*};
+}
+
+from_rejection!(JsonRejection, QueryRejection, PathRejection);
+
+/// What a violated constraint means to a client.
+struct Constraint {
+    name: &'static str,
+    /// The request field at fault.
+    field: &'static str,
+    code: &'static str,
+    message: &'static str,
+}
+
+/// Known constraints, by name. Violations of others still get the generic
+/// code for their class.
+const CONSTRAINTS: &[Constraint] = &[
+    Constraint {
+        name: "note_shares_token_hash_key",
+        field: "token",
+        code: "share_token_taken",
+        message: "A share link with this token already exists",
+    },
+    Constraint {
+        name: "attachments_note_id_fkey",
+        field: "note_id",
+        code: "note_not_found",
+        message: "The note this attachment belongs to does not exist",
+    },
+    Constraint {
+        name: "note_shares_note_id_fkey",
+        field: "note_id",
+        code: "note_not_found",
+        message: "The shared note does not exist",
+    },
+    Constraint {
+        name: "note_links_source_id_fkey",
+        field: "source_id",
+        code: "note_not_found",
+        message: "The linking note does not exist",
+    },
+    Constraint {
+        name: "note_links_target_id_fkey",
+        field: "target_id",
+        code: "note_not_found",
+        message: "The linked note does not exist",
+    },
+    Constraint {
+        name: "notes_content_size",
+        field: "content",
+        code: "content_too_large",
+        message: "The content is over the size limit",
+    },
+    Constraint {
+        name: "idempotency_keys_note_id_fkey",
+        field: "note_id",
+        code: "note_not_found",
+        message: "The note created under this idempotency key does not exist",
+    },
+];
+
+/// Maps unique (23505), foreign key (23503) and check (23514) violations to
+/// client errors.
+fn constraint_error(error: &dyn sqlx::error::DatabaseError) -> Option<AppError> {
+    let (status, generic_code, generic_message) = match error.code()?.as_ref() {
+        "23505" => (StatusCode::CONFLICT, "already_exists", "A record with these values already exists"),
+        "23503" => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_reference",
+            "A referenced record does not exist",
+        ),
+        "23514" => (StatusCode::UNPROCESSABLE_ENTITY, "validation_failed", "A value is not allowed"),
+        _ => return None,
+    };
+
+    let known = error
+        .constraint()
+        .and_then(|name| CONSTRAINTS.iter().find(|constraint| constraint.name == name));
+    Some(match known {
+        Some(constraint) => AppError::new(status, constraint.code, constraint.message)
+            .with_details(serde_json::json!({ "field": constraint.field })),
+        None => AppError::new(status, generic_code, generic_message),
+    })
+}
+
+/// Constraint violations become client errors, and lost connections and
+/// statements cut off by the statement timeout a 503. Everything else is internal and logged,
+/// with a generic message so SQL and schema details don't leak; content
+/// that can't be decrypted gets its own code so a key misconfiguration is
+/// obvious.
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        if crypto::is_decrypt_error(&error) {
+            return AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "decryption_failed", error.to_string());
+        }
+        if let Some(mapped) = error.as_database_error().and_then(constraint_error) {
+            return mapped;
+        }
+        if retry::is_transient(&error) {
+            tracing::error!(error = %error, "database unreachable");
+            let mut unavailable = AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "db_unavailable",
+                "The database is unavailable; try again shortly",
+            );
+            unavailable.database_unreachable = true;
+            return unavailable;
+        }
+        if statement_timeout::is_timeout(&error) {
+            let route = statement_timeout::record();
+            tracing::warn!(error = %error, route, "database statement timed out");
+            return AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "db_timeout",
+                "The database took too long to answer; try again, or narrow the request",
+            );
+        }
+
+        tracing::error!(error = %error, "database error");
+        AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error")
+    }
+}
+
+/// File storage failures are internal; like database errors they're logged
+/// and the client gets a generic message, never a path.
+impl From<std::io::Error> for AppError {
+    fn from(error: std::io::Error) -> Self {
+        tracing::error!(error = %error, "storage error");
+        AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error")
+    }
+}
+
+/// The response for a handler that panicked. The panic is logged with the
+/// request it happened in, and the client gets a normal error body instead
+/// of a dropped connection.
+pub fn panic_response(payload: Box<dyn Any + Send + 'static>) -> Response {
+    let panic = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string());
+    let request = request_id::current();
+    let request_id = request.as_ref().map(|request| request.id.as_str());
+    let route = request.as_ref().and_then(|request| request.route.as_deref());
+    tracing::error!(panic, route, request_id, "handler panicked");
+
+    AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_panic", "Internal server error").into_response()
+}
+
+/// The fallback for paths no route matches, so they get an error body too.
+pub async fn route_not_found() -> AppError {
+    (StatusCode::NOT_FOUND, "No such route".to_string()).into()
+}
+
+fn default_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PRECONDITION_FAILED => "precondition_failed",
+        StatusCode::PRECONDITION_REQUIRED => "precondition_required",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::RANGE_NOT_SATISFIABLE => "range_not_satisfiable",
+        StatusCode::UNPROCESSABLE_ENTITY => "validation_failed",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        status if status.is_server_error() => "internal_error",
+        _ => "error",
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let mut error = serde_json::json!({
+            "code": self.code,
+            "message": self.message,
+        });
+        if let Some(details) = self.details {
+            error["details"] = details;
+        }
+        // Errors are turned into responses inside the request's task, where
+        // `assign_request_id` has put its ID in scope.
+        if let Some(request) = request_id::current() {
+            error["request_id"] = request.id.into();
+        }
+        let body = serde_json::json!({ "error": error });
+
+        let mut response = (self.status, Json(body)).into_response();
+        if self.database_unreachable {
+            response.extensions_mut().insert(circuit::DatabaseUnreachable);
+        }
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::test_support::TestApp;
+
+    /// Runs `sql` expecting it to fail, and maps the failure as a handler's
+    /// `?` would.
+    async fn violation(pool: &sqlx::PgPool, sql: &str) -> AppError {
+        sqlx::query(sql).execute(pool).await.expect_err("the statement violates a constraint").into()
+    }
+
+    async fn insert_note(pool: &sqlx::PgPool) -> uuid::Uuid {
+        sqlx::query_scalar("INSERT INTO notes (title, content) VALUES ('Target', '') RETURNING id")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn unique_violations_are_conflicts(pool: sqlx::PgPool) {
+        let note_id = insert_note(&pool).await;
+        let insert = format!(
+            "INSERT INTO note_shares (note_id, token_hash) VALUES ('{}', '{}')",
+            note_id,
+            "a".repeat(64)
+        );
+        sqlx::query(&insert).execute(&pool).await.unwrap();
+
+        let error = violation(&pool, &insert).await;
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+        assert_eq!(error.code(), "share_token_taken");
+        assert_eq!(error.details(), Some(&serde_json::json!({ "field": "token" })));
+
+        // A constraint without an entry gets the generic code for its class.
+        let insert = format!("INSERT INTO notes (id, title, content) VALUES ('{}', 'Again', '')", note_id);
+        let error = violation(&pool, &insert).await;
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+        assert_eq!(error.code(), "already_exists");
+        assert_eq!(error.details(), None);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn foreign_key_violations_are_invalid_references(pool: sqlx::PgPool) {
+        let error = violation(
+            &pool,
+            &format!(
+                "INSERT INTO attachments (id, note_id, filename, content_type, size) VALUES ('{}', '{}', 'a.png', 'image/png', 1)",
+                uuid::Uuid::new_v4(),
+                uuid::Uuid::new_v4()
+            ),
+        )
+        .await;
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), "note_not_found");
+        assert_eq!(error.details(), Some(&serde_json::json!({ "field": "note_id" })));
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn check_violations_are_validation_errors(pool: sqlx::PgPool) {
+        let note_id = insert_note(&pool).await;
+        let error = violation(
+            &pool,
+            &format!("UPDATE notes SET content = repeat('x', 16777217) WHERE id = '{}'", note_id),
+        )
+        .await;
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), "content_too_large");
+        assert_eq!(error.details(), Some(&serde_json::json!({ "field": "content" })));
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn other_database_errors_are_internal_and_say_nothing_of_the_schema(pool: sqlx::PgPool) {
+        let error = violation(&pool, "SELECT no_such_column FROM notes").await;
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.code(), "internal_error");
+        assert_eq!(error.message(), "Internal server error");
+    }
+
+    /// Sends a GET on an open keep-alive connection and reads the response's
+    /// status and body.
+    async fn get(connection: &mut BufReader<TcpStream>, path: &str) -> (u16, Value) {
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\r\n", path);
+        connection.get_mut().write_all(request.as_bytes()).await.unwrap();
+
+        let mut status_line = String::new();
+        connection.read_line(&mut status_line).await.unwrap();
+        let status = status_line.split(' ').nth(1).unwrap().parse().unwrap();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            connection.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        connection.read_exact(&mut body).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn a_panicking_handler_gets_a_json_500_and_the_server_keeps_serving(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let addr = app.serve().await;
+        let mut connection = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+        let (status, body) = get(&mut connection, "/api/v1/debug/panic").await;
+        assert_eq!(status, 500);
+        assert_eq!(body["error"]["code"], "internal_panic");
+        assert_eq!(body["error"]["message"], "Internal server error");
+        assert!(body["error"]["request_id"].is_string());
+        // The panic message stays in the log.
+        assert!(!body.to_string().contains("deliberate"));
+
+        // The same connection, and new ones, are still served.
+        let (status, body) = get(&mut connection, "/api/v1/notes").await;
+        assert_eq!(status, 200);
+        assert!(body["notes"].is_array());
+        let mut fresh = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!(get(&mut fresh, "/api/v1/notes").await.0, 200);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn requests_the_extractors_reject_get_error_bodies(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let error = |response: &crate::test_support::TestResponse| response.json()["error"].clone();
+
+        let response = app.get("/api/v1/notes?limit=abc").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        assert_eq!(error(&response)["code"], "bad_request");
+        assert!(error(&response)["message"].as_str().unwrap().contains("limit"), "{}", response.text());
+        assert!(error(&response)["request_id"].is_string());
+
+        let response = app.get("/api/v1/notes/not-a-uuid").await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error(&response)["code"], "bad_request");
+
+        let post = |content_type: &str, body: &'static str| {
+            axum::http::Request::post("/api/v1/templates")
+                .header("content-type", content_type)
+                .body(axum::body::Body::from(body))
+                .unwrap()
+        };
+        let response = app.request(post("application/json", "{not json")).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error(&response)["code"], "bad_request");
+        let response = app.request(post("text/plain", "{}")).await;
+        assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(error(&response)["code"], "unsupported_media_type");
+        assert!(error(&response)["request_id"].is_string());
+        let response = app.request(post("application/json", r#"{"name": 1}"#)).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error(&response)["code"], "validation_failed");
+    }
+}
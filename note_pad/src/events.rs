@@ -0,0 +1,113 @@
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    sync::{Arc, RwLock},
+};
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use uuid::Uuid;
+
+use crate::AppState;
+
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Something that happened to a note, fanned out to every subscriber.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum NoteEvent {
+    #[serde(rename = "note.due")]
+    Due {
+        note_id: Uuid,
+        title: String,
+        due_at: DateTime<Utc>,
+    },
+    #[serde(rename = "note.published")]
+    Published {
+        note_id: Uuid,
+        title: String,
+        published_at: DateTime<Utc>,
+    },
+    /// Anything about the note or its attachments changed.
+    #[serde(rename = "note.updated")]
+    Updated { note_id: Uuid },
+    #[serde(rename = "note.deleted")]
+    Deleted { note_id: Uuid },
+}
+
+impl NoteEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteEvent::Due { .. } => "note.due",
+            NoteEvent::Published { .. } => "note.published",
+            NoteEvent::Updated { .. } => "note.updated",
+            NoteEvent::Deleted { .. } => "note.deleted",
+        }
+    }
+
+    pub fn note_id(&self) -> Uuid {
+        match self {
+            NoteEvent::Due { note_id, .. }
+            | NoteEvent::Published { note_id, .. }
+            | NoteEvent::Updated { note_id }
+            | NoteEvent::Deleted { note_id } => *note_id,
+        }
+    }
+}
+
+type Hook = Box<dyn Fn(&NoteEvent) + Send + Sync>;
+
+/// In-process publish/subscribe channel for note events. Publishing never
+/// blocks; subscribers that fall too far behind miss events.
+pub struct EventBus {
+    sender: broadcast::Sender<NoteEvent>,
+    hooks: RwLock<Vec<Hook>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        EventBus {
+            sender,
+            hooks: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Runs `hook` inside every `publish`, before it returns. Unlike
+    /// subscribers, hooks can't lag or miss events, so they suit state that
+    /// must be current by the time the publishing request responds, such as
+    /// cache invalidation. Hooks must be quick and must not publish.
+    pub fn on_publish(&self, hook: impl Fn(&NoteEvent) + Send + Sync + 'static) {
+        self.hooks.write().unwrap().push(Box::new(hook));
+    }
+
+    pub fn publish(&self, event: NoteEvent) {
+        tracing::debug!(event = event.name(), "publishing note event");
+        for hook in self.hooks.read().unwrap().iter() {
+            hook(&event);
+        }
+        // An error only means nobody is subscribed right now.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NoteEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Streams note events to the client as server-sent events.
+pub async fn stream_events(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(|event| {
+        // Lagged receivers skip what they missed and keep streaming.
+        let event = event.ok()?;
+        Event::default().event(event.name()).json_data(&event).ok().map(Ok)
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
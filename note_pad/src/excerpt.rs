@@ -0,0 +1,114 @@
+use pulldown_cmark::{Event, Parser, TagEnd};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Number of grapheme clusters kept in a list excerpt.
+pub const EXCERPT_LENGTH: usize = 280;
+
+/// Builds a plain-text preview of a note's Markdown content.
+///
+/// Markdown syntax is stripped, whitespace is collapsed, and the result is
+/// cut to at most `max_len` grapheme clusters so that combined characters and
+/// emoji are never split. An ellipsis is appended when text was cut off.
+pub fn excerpt(content: &str, max_len: usize) -> String {
+    let text = collapse_whitespace(&plain_text(content));
+
+    let mut graphemes = text.grapheme_indices(true);
+    match graphemes.nth(max_len) {
+        Some((cut, _)) => format!("{}…", text[..cut].trim_end()),
+        None => text,
+    }
+}
+
+/// Extracts the readable text of a Markdown document, separating blocks with
+/// spaces.
+fn plain_text(content: &str) -> String {
+    let mut text = String::new();
+
+    for event in Parser::new(content) {
+        match event {
+            Event::Text(chunk) | Event::Code(chunk) => text.push_str(&chunk),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::CodeBlock
+                | TagEnd::BlockQuote(_)
+                | TagEnd::TableCell,
+            ) => text.push(' '),
+            _ => {}
+        }
+    }
+
+    text
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: &str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+
+    #[test]
+    fn short_content_is_returned_whole() {
+        assert_eq!(excerpt("Buy *milk*", EXCERPT_LENGTH), "Buy milk");
+        assert_eq!(excerpt("", EXCERPT_LENGTH), "");
+    }
+
+    #[test]
+    fn content_at_the_limit_gets_no_ellipsis() {
+        let content = "a".repeat(EXCERPT_LENGTH);
+        assert_eq!(excerpt(&content, EXCERPT_LENGTH), content);
+    }
+
+    #[test]
+    fn counts_multibyte_characters_not_bytes() {
+        let content = "é".repeat(10) + "ü";
+        assert_eq!(excerpt(&content, 10), format!("{}…", "é".repeat(10)));
+        assert_eq!(excerpt("日本語のノート", 3), "日本語…");
+    }
+
+    #[test]
+    fn keeps_combining_marks_with_their_base() {
+        // "e" followed by a combining acute accent is one grapheme.
+        let content = "e\u{301}".repeat(3);
+        assert_eq!(excerpt(&content, 2), format!("{}…", "e\u{301}".repeat(2)));
+    }
+
+    #[test]
+    fn never_splits_a_zwj_emoji_at_the_boundary() {
+        let before = "a".repeat(EXCERPT_LENGTH - 1);
+
+        let ends_on_emoji = format!("{}{}b", before, FAMILY);
+        assert_eq!(excerpt(&ends_on_emoji, EXCERPT_LENGTH), format!("{}{}…", before, FAMILY));
+
+        let starts_past_limit = format!("{}a{}", before, FAMILY);
+        let cut = excerpt(&starts_past_limit, EXCERPT_LENGTH);
+        assert_eq!(cut, format!("{}a…", before));
+        assert!(!cut.contains('\u{200D}'));
+    }
+
+    #[test]
+    fn strips_markdown_syntax() {
+        assert_eq!(
+            excerpt("# Title\n\n- one\n- two\n\n> quoted `code`", EXCERPT_LENGTH),
+            "Title one two quoted code"
+        );
+        assert_eq!(excerpt("[link](https://example.com) and ![alt](x.png)", EXCERPT_LENGTH), "link and alt");
+    }
+
+    #[test]
+    fn content_of_only_markdown_syntax_is_empty() {
+        assert_eq!(excerpt("---\n\n***\n\n#\n\n-\n\n>", EXCERPT_LENGTH), "");
+    }
+
+    #[test]
+    fn collapses_whitespace_before_counting() {
+        assert_eq!(excerpt("a   b\n\n\n\tc", 3), "a b…");
+        assert_eq!(excerpt("one\ntwo", EXCERPT_LENGTH), "one two");
+    }
+}
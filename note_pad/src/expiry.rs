@@ -0,0 +1,67 @@
+use sqlx::Row;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{attachments, events::NoteEvent, idempotency, AppState};
+
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+
+/// How often expired notes are purged, from `EXPIRY_PURGE_INTERVAL_SECS`
+/// (default 60).
+pub fn interval_from_env() -> Duration {
+    let secs = std::env::var("EXPIRY_PURGE_INTERVAL_SECS")
+        .ok()
+        .map(|value| value.parse().expect("EXPIRY_PURGE_INTERVAL_SECS must be a number of seconds"))
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+    Duration::from_secs(secs)
+}
+
+/// Hard-deletes every note past its `expires_at`, together with its
+/// attachments, and returns how many notes were removed. Reads already hide
+/// expired notes, so this only reclaims storage.
+pub async fn purge_expired(state: &AppState) -> Result<u64, sqlx::Error> {
+    let mut tx = state.db.begin().await?;
+
+    let attachment_ids: Vec<Uuid> = sqlx::query(
+        "DELETE FROM attachments WHERE note_id IN (SELECT id FROM notes WHERE expires_at <= NOW()) RETURNING id",
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .iter()
+    .map(|row| row.try_get("id"))
+    .collect::<Result<_, _>>()?;
+
+    let purged: Vec<Uuid> = sqlx::query_scalar("DELETE FROM notes WHERE expires_at <= NOW() RETURNING id")
+        .fetch_all(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    for &note_id in &purged {
+        state.events.publish(NoteEvent::Deleted { note_id });
+    }
+
+    attachments::remove_files(&state.attachments, &attachment_ids).await;
+
+    Ok(purged.len() as u64)
+}
+
+pub fn spawn_expiry_purger(state: Arc<AppState>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match purge_expired(&state).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!(purged, "purged expired notes"),
+                Err(e) => tracing::error!(error = %e, "failed to purge expired notes"),
+            }
+            match idempotency::purge_expired_keys(&state.db).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!(purged, "purged expired idempotency keys"),
+                Err(e) => tracing::error!(error = %e, "failed to purge expired idempotency keys"),
+            }
+        }
+    });
+}
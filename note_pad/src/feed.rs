@@ -0,0 +1,191 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use std::{fmt::Write, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    crypto,
+    error::AppError,
+    query::visible,
+    render::{escape_html, markdown_to_html},
+    AppState,
+};
+
+/// Entries per feed.
+const FEED_SIZE: i64 = 50;
+
+const FEED_TITLE: &str = "Note Pad";
+
+/// Published notes that can appear in a feed, newest update first.
+/// Password protected notes are left out since feeds are read without it.
+const FEED_NOTES: &str = concat!("FROM notes
+     WHERE status = 'published' AND password_hash IS NULL
+       AND ", visible!(), "
+     ORDER BY updated_at DESC, id
+     LIMIT $1");
+
+struct Entry {
+    id: Uuid,
+    title: String,
+    content: String,
+    updated_at: DateTime<Utc>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+/// `GET /api/v1/notes/feed.atom`: the most recently updated published notes
+/// as an Atom feed.
+pub async fn atom_feed(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Result<Response, AppError> {
+    serve(&state, &headers, "application/atom+xml; charset=utf-8", render_atom).await
+}
+
+/// `GET /api/v1/notes/feed.rss`: the same entries as RSS 2.0.
+pub async fn rss_feed(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Result<Response, AppError> {
+    serve(&state, &headers, "application/rss+xml; charset=utf-8", render_rss).await
+}
+
+/// Answers conditional requests from a cheap query over ids and versions,
+/// so readers polling with `If-None-Match` get a 304 without the notes
+/// being loaded and rendered.
+async fn serve(
+    state: &AppState,
+    headers: &HeaderMap,
+    content_type: &'static str,
+    render: fn(&str, &[Entry]) -> String,
+) -> Result<Response, AppError> {
+    let versions = sqlx::query(&format!("SELECT id, version {}", FEED_NOTES))
+        .bind(FEED_SIZE)
+        .fetch_all(&state.db)
+        .await?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(content_type.as_bytes());
+    for row in &versions {
+        let id: Uuid = row.try_get("id")?;
+        let version: i32 = row.try_get("version")?;
+        hasher.update(id.as_bytes());
+        hasher.update(version.to_be_bytes());
+    }
+    let etag = format!("\"{:x}\"", hasher.finalize());
+
+    let etag_matches = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    let etag = HeaderValue::from_str(&etag).expect("a quoted hex digest is a valid header value");
+
+    if etag_matches {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    let rows = sqlx::query(&format!(
+        "SELECT id, title, content, content_nonce, content_ciphertext, updated_at, published_at {}",
+        FEED_NOTES
+    ))
+    .bind(FEED_SIZE)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        entries.push(Entry {
+            id: row.try_get("id")?,
+            title: row.try_get("title")?,
+            content: crypto::content(&row)?,
+            updated_at: row.try_get("updated_at")?,
+            published_at: row.try_get("published_at")?,
+        });
+    }
+
+    let body = render(&state.public_base_url, &entries);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+fn feed_updated(entries: &[Entry]) -> DateTime<Utc> {
+    entries.iter().map(|entry| entry.updated_at).max().unwrap_or_else(Utc::now)
+}
+
+fn render_atom(base_url: &str, entries: &[Entry]) -> String {
+    let feed_url = format!("{}/api/v1/notes/feed.atom", base_url);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let _ = writeln!(xml, "  <id>{}</id>", escape_html(&feed_url));
+    let _ = writeln!(xml, "  <title>{}</title>", FEED_TITLE);
+    let _ = writeln!(xml, "  <updated>{}</updated>", feed_updated(entries).to_rfc3339());
+    let _ = writeln!(xml, "  <link rel=\"self\" href=\"{}\"/>", escape_html(&feed_url));
+    let _ = writeln!(xml, "  <author><name>{}</name></author>", FEED_TITLE);
+
+    for entry in entries {
+        xml.push_str("  <entry>\n");
+        let _ = writeln!(xml, "    <id>urn:uuid:{}</id>", entry.id);
+        let _ = writeln!(xml, "    <title>{}</title>", escape_html(&entry.title));
+        let _ = writeln!(xml, "    <updated>{}</updated>", entry.updated_at.to_rfc3339());
+        if let Some(published_at) = entry.published_at {
+            let _ = writeln!(xml, "    <published>{}</published>", published_at.to_rfc3339());
+        }
+        let _ = writeln!(
+            xml,
+            "    <link href=\"{}\"/>",
+            escape_html(&format!("{}/api/v1/notes/{}", base_url, entry.id))
+        );
+        let _ = writeln!(
+            xml,
+            "    <content type=\"html\">{}</content>",
+            escape_html(&markdown_to_html(&entry.content))
+        );
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn render_rss(base_url: &str, entries: &[Entry]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\">\n  <channel>\n");
+    let _ = writeln!(xml, "    <title>{}</title>", FEED_TITLE);
+    let _ = writeln!(xml, "    <link>{}</link>", escape_html(base_url));
+    let _ = writeln!(xml, "    <description>Published notes</description>");
+    let _ = writeln!(xml, "    <lastBuildDate>{}</lastBuildDate>", feed_updated(entries).to_rfc2822());
+
+    for entry in entries {
+        xml.push_str("    <item>\n");
+        let _ = writeln!(xml, "      <guid isPermaLink=\"false\">urn:uuid:{}</guid>", entry.id);
+        let _ = writeln!(xml, "      <title>{}</title>", escape_html(&entry.title));
+        let _ = writeln!(
+            xml,
+            "      <link>{}</link>",
+            escape_html(&format!("{}/api/v1/notes/{}", base_url, entry.id))
+        );
+        let _ = writeln!(
+            xml,
+            "      <pubDate>{}</pubDate>",
+            entry.published_at.unwrap_or(entry.updated_at).to_rfc2822()
+        );
+        let _ = writeln!(
+            xml,
+            "      <description>{}</description>",
+            escape_html(&markdown_to_html(&entry.content))
+        );
+        xml.push_str("    </item>\n");
+    }
+
+    xml.push_str("  </channel>\n</rss>\n");
+    xml
+}
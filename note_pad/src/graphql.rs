@@ -0,0 +1,417 @@
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    ComplexObject, Context, EmptySubscription, ErrorExtensions, InputObject, MaybeUndefined, Object, Schema,
+    SimpleObject,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    items,
+    items::NoteItem,
+    links::{self, Backlink},
+    list_summaries, load_note, passwords,
+    publishing::NoteStatus,
+    remove_note, request_id, AppState, CreateNote, ListNotesParams, Note, NoteSummary, UpdateNote,
+};
+
+/// Deepest selection allowed, counting from the operation. Notes nest
+/// only a level or two, but the introspection query GraphiQL and client
+/// generators send goes over a dozen deep.
+const MAX_DEPTH: usize = 16;
+
+/// Allowed query complexity, where each field costs 1 and a list of notes
+/// costs its `limit` times the fields selected on each.
+const MAX_COMPLEXITY: usize = 1000;
+
+/// Explorer assets come from a CDN, so the page can't use the default
+/// policy, which allows no scripts.
+#[cfg(debug_assertions)]
+const GRAPHIQL_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; script-src 'unsafe-inline' https://unpkg.com; \
+     style-src 'unsafe-inline' https://unpkg.com; font-src https://unpkg.com; img-src https: data:; \
+     connect-src 'self'; frame-ancestors 'none'";
+
+pub type NoteSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn schema() -> NoteSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// An `AppError` as a GraphQL error, with the REST error's `code` and HTTP
+/// `status` in its extensions, plus `details` and `request_id` when there
+/// are any.
+fn graphql_error(error: AppError) -> async_graphql::Error {
+    async_graphql::Error::new(error.message()).extend_with(|_, extensions| {
+        extensions.set("code", error.code());
+        extensions.set("status", error.status().as_u16());
+        if let Some(details) = error.details().cloned().and_then(|details| async_graphql::Value::from_json(details).ok()) {
+            extensions.set("details", details);
+        }
+        if let Some(request) = request_id::current() {
+            extensions.set("request_id", request.id);
+        }
+    })
+}
+
+/// The note password sent in the request headers.
+struct SuppliedPassword(Option<String>);
+
+/// Serves `POST /api/graphql`. Each request gets its own data loaders, so
+/// nested fields of a page of notes are read in one query per field rather
+/// than one per note.
+pub async fn graphql_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let request = request
+        .into_inner()
+        .data(DataLoader::new(ItemsLoader(state.db.clone()), tokio::spawn))
+        .data(DataLoader::new(BacklinksLoader(state.db.clone()), tokio::spawn))
+        .data(SuppliedPassword(passwords::supplied(&headers, None)))
+        .data(state.clone());
+    state.graphql.execute(request).await.into()
+}
+
+/// The GraphiQL explorer, in debug builds only.
+#[cfg(debug_assertions)]
+pub async fn graphiql() -> impl axum::response::IntoResponse {
+    use async_graphql::http::GraphiQLSource;
+    use axum::{http::header, response::Html};
+
+    (
+        [(header::CONTENT_SECURITY_POLICY, GRAPHIQL_CONTENT_SECURITY_POLICY)],
+        Html(GraphiQLSource::build().endpoint("/api/graphql").finish()),
+    )
+}
+
+struct ItemsLoader(PgPool);
+
+impl Loader<Uuid> for ItemsLoader {
+    type Value = Vec<NoteItem>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        items::fetch_for_notes(&self.0, keys)
+            .await
+            .map_err(|e| graphql_error(e.into()))
+    }
+}
+
+struct BacklinksLoader(PgPool);
+
+impl Loader<Uuid> for BacklinksLoader {
+    type Value = Vec<Backlink>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        links::backlinks_for(&self.0, keys)
+            .await
+            .map_err(|e| graphql_error(e.into()))
+    }
+}
+
+/// A note. In lists, password protected notes have no `content`,
+/// `excerpt`, items or backlinks; `note(id)` with the password has them.
+#[derive(SimpleObject)]
+#[graphql(name = "Note", complex)]
+struct GraphqlNote {
+    id: Uuid,
+    title: String,
+    content: Option<String>,
+    excerpt: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    due_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    status: NoteStatus,
+    published_at: Option<DateTime<Utc>>,
+    publish_at: Option<DateTime<Utc>>,
+    /// Whether the note has a password, needed to read or change it. Not the
+    /// same as `readOnly`.
+    locked: bool,
+    /// Whether the note refuses every change until it's made writable again.
+    /// Not the same as `locked`.
+    read_only: bool,
+    version: i32,
+    #[graphql(skip)]
+    readable: bool,
+}
+
+impl GraphqlNote {
+    /// A note that was unlocked, or has no password.
+    fn unlocked(note: Note, locked: bool) -> Self {
+        GraphqlNote {
+            excerpt: Some(crate::excerpt::excerpt(&note.content, crate::excerpt::EXCERPT_LENGTH)),
+            content: Some(note.content),
+            id: note.id,
+            title: note.title,
+            created_at: note.created_at,
+            updated_at: note.updated_at,
+            due_at: note.due_at,
+            expires_at: note.expires_at,
+            status: note.status,
+            published_at: note.published_at,
+            publish_at: note.publish_at,
+            locked,
+            read_only: note.read_only,
+            version: note.version,
+            readable: true,
+        }
+    }
+
+    fn ensure_readable(&self) -> async_graphql::Result<()> {
+        if self.readable {
+            return Ok(());
+        }
+        Err(graphql_error(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "note_locked",
+            "This note is password protected; read it with note(id) and the password",
+        )))
+    }
+}
+
+impl From<NoteSummary> for GraphqlNote {
+    fn from(note: NoteSummary) -> Self {
+        GraphqlNote {
+            id: note.id,
+            title: note.title,
+            content: note.content.flatten(),
+            excerpt: note.excerpt,
+            created_at: note.created_at,
+            updated_at: note.updated_at,
+            due_at: note.due_at,
+            expires_at: note.expires_at,
+            status: note.status,
+            published_at: note.published_at,
+            publish_at: note.publish_at,
+            locked: note.locked,
+            read_only: note.read_only,
+            version: note.version,
+            readable: !note.locked,
+        }
+    }
+}
+
+impl GraphqlNote {
+    async fn load_items(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<NoteItem>> {
+        self.ensure_readable()?;
+        let items = ctx.data_unchecked::<DataLoader<ItemsLoader>>().load_one(self.id).await?;
+        Ok(items.unwrap_or_default())
+    }
+}
+
+/// On locked notes in a list, these fields have no value and a
+/// `note_locked` error instead.
+#[ComplexObject]
+impl GraphqlNote {
+    async fn items(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Vec<NoteItem>>> {
+        self.load_items(ctx).await.map(Some)
+    }
+
+    async fn items_total(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<usize>> {
+        Ok(Some(self.load_items(ctx).await?.len()))
+    }
+
+    async fn items_done(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<usize>> {
+        Ok(Some(self.load_items(ctx).await?.iter().filter(|item| item.done).count()))
+    }
+
+    /// Notes linking to this one, most recently updated first.
+    async fn backlinks(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Vec<Backlink>>> {
+        self.ensure_readable()?;
+        let backlinks = ctx.data_unchecked::<DataLoader<BacklinksLoader>>().load_one(self.id).await?;
+        Ok(Some(backlinks.unwrap_or_default()))
+    }
+}
+
+#[derive(InputObject, Default)]
+struct NoteFilter {
+    due_before: Option<DateTime<Utc>>,
+    due_after: Option<DateTime<Utc>>,
+    has_due: Option<bool>,
+    /// All statuses when left out.
+    status: Option<NoteStatus>,
+    /// Titles containing this, ignoring case and accents.
+    title: Option<String>,
+}
+
+#[derive(InputObject)]
+struct CreateNoteInput {
+    title: String,
+    content: String,
+    due_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    status: Option<NoteStatus>,
+}
+
+/// Fields left out are kept; `dueAt` and `expiresAt` are cleared with
+/// `null`.
+#[derive(InputObject)]
+struct UpdateNoteInput {
+    title: Option<String>,
+    content: Option<String>,
+    due_at: MaybeUndefined<DateTime<Utc>>,
+    expires_at: MaybeUndefined<DateTime<Utc>>,
+    status: Option<NoteStatus>,
+    /// The `updatedAt` last seen; the update fails if the note changed
+    /// since.
+    base_updated_at: Option<DateTime<Utc>>,
+}
+
+fn maybe<T>(value: MaybeUndefined<T>) -> Option<Option<T>> {
+    match value {
+        MaybeUndefined::Undefined => None,
+        MaybeUndefined::Null => Some(None),
+        MaybeUndefined::Value(value) => Some(Some(value)),
+    }
+}
+
+/// `password` stands in for the `X-Note-Password` header, which wins when
+/// both are sent.
+fn supplied(ctx: &Context<'_>, password: Option<String>) -> Option<String> {
+    ctx.data_unchecked::<SuppliedPassword>().0.clone().or(password)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A note by id. Reading it counts as a view unless `countView` is
+    /// false.
+    async fn note(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        password: Option<String>,
+        #[graphql(default = true)] count_view: bool,
+    ) -> async_graphql::Result<GraphqlNote> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let cached = load_note(state, id).await.map_err(graphql_error)?;
+        let locked = cached.password_hash.is_some();
+        passwords::unlock(state, id, cached.password_hash, supplied(ctx, password))
+            .await
+            .map_err(graphql_error)?;
+        if count_view {
+            state.views.record(id);
+        }
+        Ok(GraphqlNote::unlocked(cached.note, locked))
+    }
+
+    /// A page of notes, with the same filters and sorting as
+    /// `GET /api/v1/notes`.
+    #[graphql(complexity = "limit.max(0) as usize * child_complexity")]
+    async fn notes(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<NoteFilter>,
+        #[graphql(default = 10)] limit: i64,
+        #[graphql(default = 0)] offset: i64,
+        sort_by: Option<String>,
+        order: Option<String>,
+    ) -> async_graphql::Result<Vec<GraphqlNote>> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let filter = filter.unwrap_or_default();
+        let params = ListNotesParams {
+            limit: Some(limit),
+            offset: Some(offset),
+            page: None,
+            per_page: None,
+            fields: None,
+            full_content: true,
+            due_before: filter.due_before,
+            due_after: filter.due_after,
+            has_due: filter.has_due,
+            sort_by,
+            order,
+            status: filter.status.map(|status| status.name().to_string()),
+            title: filter.title,
+        };
+        let query = params.to_query(state.default_sort).map_err(|e| graphql_error(e.into()))?;
+        let notes = list_summaries(state, &query, true).await.map_err(graphql_error)?;
+
+        Ok(notes.into_iter().map(GraphqlNote::from).collect())
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn create_note(&self, ctx: &Context<'_>, input: CreateNoteInput) -> async_graphql::Result<GraphqlNote> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let payload = CreateNote {
+            id: None,
+            title: input.title,
+            content: input.content,
+            due_at: input.due_at,
+            expires_at: input.expires_at,
+            status: input.status,
+        };
+        let note = crate::add_note(state, &payload).await.map_err(graphql_error)?;
+        Ok(GraphqlNote::unlocked(note, false))
+    }
+
+    async fn update_note(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        input: UpdateNoteInput,
+        password: Option<String>,
+    ) -> async_graphql::Result<GraphqlNote> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let payload = UpdateNote {
+            title: input.title,
+            content: input.content,
+            due_at: maybe(input.due_at),
+            expires_at: maybe(input.expires_at),
+            status: input.status,
+            password: None,
+            base_updated_at: input.base_updated_at,
+        };
+        let supplied = supplied(ctx, password);
+        let locked = supplied.is_some();
+        let note = crate::apply_update(state, id, payload, supplied, None)
+            .await
+            .map_err(graphql_error)?;
+        Ok(GraphqlNote::unlocked(note, locked))
+    }
+
+    /// Deletes a note. `version`, when given, must be the current one; it
+    /// is required when the server requires `If-Match` on REST deletes. A
+    /// protected note needs its password.
+    async fn delete_note(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        version: Option<i32>,
+        password: Option<String>,
+    ) -> async_graphql::Result<bool> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        if version.is_none() && state.require_if_match {
+            return Err(graphql_error(
+                (
+                    StatusCode::PRECONDITION_REQUIRED,
+                    "Deleting a note requires its current version".to_string(),
+                )
+                    .into(),
+            ));
+        }
+        remove_note(state, id, version, supplied(ctx, password))
+            .await
+            .map_err(graphql_error)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,462 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use prost_types::Timestamp;
+use std::{future::Future, net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+use tonic::{transport::server::TcpIncoming, Code, Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    add_note, apply_update, content_limit, error::AppError, excerpt, listen, list_summaries, load_note, passwords, publishing,
+    remove_note, AppState, CreateNote, ListNotesParams, Note, NoteSummary, UpdateNote,
+};
+
+pub mod proto {
+    tonic::include_proto!("note_pad.v1");
+}
+
+use proto::note_service_server::{NoteService, NoteServiceServer};
+
+const DEFAULT_PAGE_SIZE: i32 = 10;
+
+/// Prefix of the decoded page token, so a stray number isn't taken for one.
+const PAGE_TOKEN_PREFIX: &str = "offset:";
+
+/// Where to serve gRPC, from `GRPC_ADDR` such as `0.0.0.0:50051`. gRPC is
+/// off when it isn't set.
+pub fn addr_from_env() -> Option<SocketAddr> {
+    std::env::var("GRPC_ADDR")
+        .ok()
+        .map(|value| value.parse().expect("GRPC_ADDR must be an address such as 0.0.0.0:50051"))
+}
+
+/// Serves `NoteService` on `listener` until Ctrl-C or SIGTERM. A server
+/// that fails exits the process, as the HTTP listeners do. Messages may be
+/// as large as HTTP bodies, so content over the limit gets the same error.
+pub fn spawn_grpc_server(state: Arc<AppState>, listener: TcpListener) {
+    tokio::spawn(async move {
+        let message_limit = content_limit::body_limit(state.max_content_bytes);
+        let service = NoteServiceServer::new(Notes { state }).max_decoding_message_size(message_limit);
+        let result = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming_shutdown(TcpIncoming::from(listener), listen::shutdown_signal())
+            .await;
+        if let Err(e) = result {
+            tracing::error!(error = %e, "gRPC server stopped");
+            std::process::exit(1);
+        }
+    });
+}
+
+/// Maps an API error to the closest gRPC status, with the REST error code
+/// in the `x-error-code` metadata entry.
+impl From<AppError> for Status {
+    fn from(error: AppError) -> Self {
+        let code = match error.status() {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY | StatusCode::PAYLOAD_TOO_LARGE => {
+                Code::InvalidArgument
+            }
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::CONFLICT => Code::Aborted,
+            StatusCode::PRECONDITION_FAILED | StatusCode::PRECONDITION_REQUIRED | StatusCode::LOCKED => {
+                Code::FailedPrecondition
+            }
+            StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            StatusCode::GATEWAY_TIMEOUT => Code::DeadlineExceeded,
+            _ => Code::Internal,
+        };
+        let mut status = Status::new(code, error.message());
+        status
+            .metadata_mut()
+            .insert("x-error-code", error.code().parse().expect("error codes are valid metadata"));
+        status
+    }
+}
+
+fn invalid_argument(message: impl Into<String>) -> AppError {
+    (StatusCode::BAD_REQUEST, message.into()).into()
+}
+
+fn parse_id(id: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(id).map_err(|_| invalid_argument(format!("id {:?} is not a UUID", id)))
+}
+
+fn timestamp(at: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn date_time(at: Timestamp) -> Result<DateTime<Utc>, AppError> {
+    u32::try_from(at.nanos)
+        .ok()
+        .and_then(|nanos| DateTime::from_timestamp(at.seconds, nanos))
+        .ok_or_else(|| invalid_argument("timestamp is out of range"))
+}
+
+fn date_time_opt(at: Option<Timestamp>) -> Result<Option<DateTime<Utc>>, AppError> {
+    at.map(date_time).transpose()
+}
+
+impl From<publishing::NoteStatus> for proto::NoteStatus {
+    fn from(status: publishing::NoteStatus) -> Self {
+        match status {
+            publishing::NoteStatus::Draft => proto::NoteStatus::Draft,
+            publishing::NoteStatus::Scheduled => proto::NoteStatus::Scheduled,
+            publishing::NoteStatus::Published => proto::NoteStatus::Published,
+        }
+    }
+}
+
+/// A status from a request, or `None` when unspecified.
+fn note_status(status: i32) -> Result<Option<publishing::NoteStatus>, AppError> {
+    match proto::NoteStatus::try_from(status) {
+        Ok(proto::NoteStatus::Unspecified) => Ok(None),
+        Ok(proto::NoteStatus::Draft) => Ok(Some(publishing::NoteStatus::Draft)),
+        Ok(proto::NoteStatus::Scheduled) => Ok(Some(publishing::NoteStatus::Scheduled)),
+        Ok(proto::NoteStatus::Published) => Ok(Some(publishing::NoteStatus::Published)),
+        Err(_) => Err(invalid_argument(format!("status {} is not a NoteStatus", status))),
+    }
+}
+
+impl From<Note> for proto::Note {
+    fn from(note: Note) -> Self {
+        proto::Note {
+            id: note.id.to_string(),
+            title: note.title,
+            excerpt: Some(excerpt::excerpt(&note.content, excerpt::EXCERPT_LENGTH)),
+            content: Some(note.content),
+            created_at: Some(timestamp(note.created_at)),
+            updated_at: Some(timestamp(note.updated_at)),
+            due_at: note.due_at.map(timestamp),
+            expires_at: note.expires_at.map(timestamp),
+            status: proto::NoteStatus::from(note.status).into(),
+            published_at: note.published_at.map(timestamp),
+            publish_at: note.publish_at.map(timestamp),
+            locked: note.locked,
+            read_only: note.read_only,
+            version: note.version,
+        }
+    }
+}
+
+impl From<NoteSummary> for proto::Note {
+    fn from(note: NoteSummary) -> Self {
+        proto::Note {
+            id: note.id.to_string(),
+            title: note.title,
+            content: note.content.flatten(),
+            excerpt: note.excerpt,
+            created_at: Some(timestamp(note.created_at)),
+            updated_at: Some(timestamp(note.updated_at)),
+            due_at: note.due_at.map(timestamp),
+            expires_at: note.expires_at.map(timestamp),
+            status: proto::NoteStatus::from(note.status).into(),
+            published_at: note.published_at.map(timestamp),
+            publish_at: note.publish_at.map(timestamp),
+            locked: note.locked,
+            read_only: note.read_only,
+            version: note.version,
+        }
+    }
+}
+
+fn encode_page_token(offset: i64) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}{}", PAGE_TOKEN_PREFIX, offset))
+}
+
+fn decode_page_token(token: &str) -> Result<i64, AppError> {
+    if token.is_empty() {
+        return Ok(0);
+    }
+    URL_SAFE_NO_PAD
+        .decode(token)
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| decoded.strip_prefix(PAGE_TOKEN_PREFIX)?.parse().ok())
+        .filter(|offset: &i64| *offset >= 0)
+        .ok_or_else(|| invalid_argument("page_token is not one this server returned"))
+}
+
+/// The note password from the `x-note-password` metadata entry.
+fn supplied<T>(request: &Request<T>) -> Option<String> {
+    passwords::supplied(&request.metadata().clone().into_headers(), None)
+}
+
+struct Notes {
+    state: Arc<AppState>,
+}
+
+impl Notes {
+    /// Runs a call unless the database circuit is open, and counts it
+    /// towards opening the circuit if it couldn't reach the database, as
+    /// `circuit::guard_database` does for HTTP.
+    async fn guarded<T>(&self, call: impl Future<Output = Result<T, AppError>>) -> Result<Response<T>, Status> {
+        if let Some(rejection) = self.state.circuit.rejection() {
+            return Err(rejection.into());
+        }
+        match call.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(e) => {
+                if e.database_unreachable() {
+                    self.state.circuit.record_failure();
+                }
+                Err(e.into())
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl NoteService for Notes {
+    async fn get_note(&self, request: Request<proto::GetNoteRequest>) -> Result<Response<proto::Note>, Status> {
+        let supplied = supplied(&request);
+        self.guarded(get_note(&self.state, request.into_inner(), supplied)).await
+    }
+
+    async fn list_notes(
+        &self,
+        request: Request<proto::ListNotesRequest>,
+    ) -> Result<Response<proto::ListNotesResponse>, Status> {
+        self.guarded(list_notes(&self.state, request.into_inner())).await
+    }
+
+    async fn create_note(&self, request: Request<proto::CreateNoteRequest>) -> Result<Response<proto::Note>, Status> {
+        self.guarded(create_note(&self.state, request.into_inner())).await
+    }
+
+    async fn update_note(&self, request: Request<proto::UpdateNoteRequest>) -> Result<Response<proto::Note>, Status> {
+        let supplied = supplied(&request);
+        self.guarded(update_note(&self.state, request.into_inner(), supplied)).await
+    }
+
+    async fn delete_note(&self, request: Request<proto::DeleteNoteRequest>) -> Result<Response<()>, Status> {
+        let supplied = supplied(&request);
+        self.guarded(delete_note(&self.state, request.into_inner(), supplied)).await
+    }
+}
+
+async fn get_note(
+    state: &AppState,
+    request: proto::GetNoteRequest,
+    supplied: Option<String>,
+) -> Result<proto::Note, AppError> {
+    let id = parse_id(&request.id)?;
+    let cached = load_note(state, id).await?;
+    passwords::unlock(state, id, cached.password_hash, supplied).await?;
+    if request.count_view {
+        state.views.record(id);
+    }
+    Ok(cached.note.into())
+}
+
+async fn list_notes(state: &AppState, request: proto::ListNotesRequest) -> Result<proto::ListNotesResponse, AppError> {
+    let page_size = match request.page_size {
+        0 => DEFAULT_PAGE_SIZE,
+        size if size < 0 => return Err(invalid_argument("page_size must not be negative")),
+        size => size.min(crate::MAX_PAGE_SIZE as i32),
+    };
+    let offset = decode_page_token(&request.page_token)?;
+    let non_empty = |value: String| (!value.is_empty()).then_some(value);
+
+    let params = ListNotesParams {
+        limit: Some(i64::from(page_size)),
+        offset: Some(offset),
+        page: None,
+        per_page: None,
+        fields: None,
+        full_content: request.full_content,
+        due_before: date_time_opt(request.due_before)?,
+        due_after: date_time_opt(request.due_after)?,
+        has_due: request.has_due,
+        sort_by: non_empty(request.sort_by),
+        order: non_empty(request.order),
+        status: note_status(request.status)?.map(|status| status.name().to_string()),
+        title: non_empty(request.title),
+    };
+    let mut query = params.to_query(state.default_sort)?;
+    // One extra row tells whether there is another page.
+    query.limit += 1;
+    let mut notes = list_summaries(state, &query, request.full_content).await?;
+
+    let next_page_token = if notes.len() > page_size as usize {
+        notes.truncate(page_size as usize);
+        encode_page_token(offset + i64::from(page_size))
+    } else {
+        String::new()
+    };
+
+    Ok(proto::ListNotesResponse {
+        notes: notes.into_iter().map(proto::Note::from).collect(),
+        next_page_token,
+    })
+}
+
+async fn create_note(state: &AppState, request: proto::CreateNoteRequest) -> Result<proto::Note, AppError> {
+    let payload = CreateNote {
+        id: None,
+        due_at: date_time_opt(request.due_at)?,
+        expires_at: date_time_opt(request.expires_at)?,
+        status: note_status(request.status)?,
+        title: request.title,
+        content: request.content,
+    };
+    Ok(add_note(state, &payload).await?.into())
+}
+
+/// Applies the fields named in `update_mask`, leaving the rest as they
+/// are, like a JSON `PATCH` that only sends those fields.
+async fn update_note(
+    state: &AppState,
+    request: proto::UpdateNoteRequest,
+    supplied: Option<String>,
+) -> Result<proto::Note, AppError> {
+    let id = parse_id(&request.id)?;
+    let paths = request.update_mask.map(|mask| mask.paths).unwrap_or_default();
+    if paths.is_empty() {
+        return Err(invalid_argument("update_mask must name at least one field"));
+    }
+    let note = request.note.unwrap_or_default();
+
+    let mut payload = UpdateNote {
+        title: None,
+        content: None,
+        due_at: None,
+        expires_at: None,
+        status: None,
+        password: None,
+        base_updated_at: date_time_opt(request.base_updated_at)?,
+    };
+    for path in &paths {
+        match path.as_str() {
+            "title" => payload.title = Some(note.title.clone()),
+            "content" => payload.content = Some(note.content.clone().unwrap_or_default()),
+            "due_at" => payload.due_at = Some(date_time_opt(note.due_at)?),
+            "expires_at" => payload.expires_at = Some(date_time_opt(note.expires_at)?),
+            "status" => {
+                payload.status = Some(
+                    note_status(note.status)?
+                        .ok_or_else(|| invalid_argument("note.status must be set when update_mask names it"))?,
+                )
+            }
+            path => return Err(invalid_argument(format!("update_mask names {:?}, which can't be updated", path))),
+        }
+    }
+
+    Ok(apply_update(state, id, payload, supplied, None).await?.into())
+}
+
+async fn delete_note(
+    state: &AppState,
+    request: proto::DeleteNoteRequest,
+    supplied: Option<String>,
+) -> Result<(), AppError> {
+    let id = parse_id(&request.id)?;
+    if request.version.is_none() && state.require_if_match {
+        return Err((
+            StatusCode::PRECONDITION_REQUIRED,
+            "Deleting a note requires its current version".to_string(),
+        )
+            .into());
+    }
+    remove_note(state, id, request.version, supplied).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use proto::note_service_client::NoteServiceClient;
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn notes_can_be_created_read_and_listed_over_grpc(pool: sqlx::PgPool) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn_grpc_server(Arc::new(test_support::state(pool).await), listener);
+        let mut client = NoteServiceClient::connect(format!("http://{}", addr)).await.unwrap();
+
+        let created = client
+            .create_note(proto::CreateNoteRequest {
+                title: "Groceries".to_string(),
+                content: "Eggs and milk".to_string(),
+                status: proto::NoteStatus::Published.into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(created.title, "Groceries");
+        assert_eq!(created.content.as_deref(), Some("Eggs and milk"));
+        assert_eq!(created.status(), proto::NoteStatus::Published);
+        assert_eq!(created.version, 1);
+
+        let fetched = client
+            .get_note(proto::GetNoteRequest {
+                id: created.id.clone(),
+                count_view: false,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(fetched, created);
+
+        let listed = client
+            .list_notes(proto::ListNotesRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.notes.len(), 1);
+        assert_eq!(listed.notes[0].id, created.id);
+        assert_eq!(listed.notes[0].excerpt.as_deref(), Some("Eggs and milk"));
+        assert!(listed.next_page_token.is_empty());
+
+        let missing = client
+            .get_note(proto::GetNoteRequest {
+                id: Uuid::new_v4().to_string(),
+                count_view: false,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+        assert_eq!(missing.metadata().get("x-error-code").unwrap(), "not_found");
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn a_full_page_of_the_largest_size_still_has_a_next_page(pool: sqlx::PgPool) {
+        sqlx::query("INSERT INTO notes (title, content) SELECT 'Note ' || i, '' FROM generate_series(1, 105) AS i")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let state = test_support::state(pool).await;
+
+        let page = list_notes(
+            &state,
+            proto::ListNotesRequest {
+                page_size: 1000,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.notes.len(), crate::MAX_PAGE_SIZE as usize);
+        assert!(!page.next_page_token.is_empty());
+
+        let last = list_notes(
+            &state,
+            proto::ListNotesRequest {
+                page_size: 1000,
+                page_token: page.next_page_token,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(last.notes.len(), 5);
+        assert!(last.next_page_token.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{circuit::CircuitState, extract::Json, statement_timeout, version, AppState, MAX_DB_CONNECTIONS};
+
+const MESSAGE: &str = "Note Pad API Services";
+
+/// How long the check waits for a pooled connection before calling the
+/// service degraded. It still waits out the pool's own timeout afterwards,
+/// to tell a busy pool from an unreachable database.
+const ACQUIRE_WARN_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    /// The database answers, but getting a connection is slow.
+    Degraded,
+    /// The database lacks migrations this build needs.
+    Outdated,
+    Unavailable,
+}
+
+/// Whether pending migrations make the health check fail, from
+/// `HEALTH_REQUIRE_MIGRATIONS` (default true).
+pub fn require_migrations_from_env() -> bool {
+    std::env::var("HEALTH_REQUIRE_MIGRATIONS")
+        .ok()
+        .map(|value| value.parse().expect("HEALTH_REQUIRE_MIGRATIONS must be true or false"))
+        .unwrap_or(true)
+}
+
+/// The connection pool at one moment. sqlx doesn't expose the number of
+/// waiting acquires, so a full pool with nothing idle is the sign to look
+/// for.
+#[derive(Debug, Serialize)]
+pub struct PoolSnapshot {
+    pub size: u32,
+    pub idle: usize,
+    pub in_use: usize,
+    pub max_connections: u32,
+}
+
+impl PoolSnapshot {
+    fn of(pool: &PgPool) -> Self {
+        let size = pool.size();
+        let idle = pool.num_idle();
+        PoolSnapshot {
+            size,
+            idle,
+            in_use: (size as usize).saturating_sub(idle),
+            max_connections: MAX_DB_CONNECTIONS,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DatabaseCheck {
+    reachable: bool,
+    acquire_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Acquires a connection and runs `SELECT 1`.
+async fn check_database(pool: &PgPool) -> (HealthStatus, DatabaseCheck) {
+    let started = Instant::now();
+    let acquire = pool.acquire();
+    tokio::pin!(acquire);
+
+    let (slow, conn) = match tokio::time::timeout(ACQUIRE_WARN_AFTER, &mut acquire).await {
+        Ok(conn) => (false, conn),
+        Err(_) => (true, acquire.await),
+    };
+    let acquire_ms = started.elapsed().as_millis();
+
+    let result = match conn {
+        Ok(mut conn) => sqlx::query("SELECT 1").execute(&mut conn).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => (
+            if slow { HealthStatus::Degraded } else { HealthStatus::Ok },
+            DatabaseCheck {
+                reachable: true,
+                acquire_ms,
+                error: None,
+            },
+        ),
+        Err(e) => (
+            HealthStatus::Unavailable,
+            DatabaseCheck {
+                reachable: false,
+                acquire_ms,
+                error: Some(e.to_string()),
+            },
+        ),
+    }
+}
+
+/// Reports `ok`, `degraded` when connections are slow to come by,
+/// `outdated` with a 503 when the database is missing migrations of this
+/// build (unless `HEALTH_REQUIRE_MIGRATIONS` is off), or `unavailable` with
+/// a 503 when the database can't be queried or the circuit breaker is
+/// open, along with the migrations, the state of the connection pool, the
+/// circuit, statement timeouts per route and, if enabled, the note cache.
+pub async fn health_check_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    // An open circuit already means the database is unreachable, and
+    // checking again would wait out the acquire timeout.
+    let (status, database) = if state.circuit.state() == CircuitState::Open {
+        (
+            HealthStatus::Unavailable,
+            DatabaseCheck {
+                reachable: false,
+                acquire_ms: 0,
+                error: Some("Circuit open".to_string()),
+            },
+        )
+    } else {
+        check_database(&state.db).await
+    };
+
+    let migrations = if database.reachable {
+        Some(version::migration_status(&state.db).await)
+    } else {
+        None
+    };
+    let pending = migrations.as_ref().and_then(|migrations| migrations.pending).unwrap_or(0);
+    let status = if status != HealthStatus::Unavailable && state.require_migrations && pending > 0 {
+        HealthStatus::Outdated
+    } else {
+        status
+    };
+
+    let mut json_response = serde_json::json!({
+        "status": status,
+        "message": MESSAGE,
+        "database": database,
+        "migrations": migrations,
+        "pool": PoolSnapshot::of(&state.db),
+        "db_timeouts": statement_timeout::snapshot(),
+        "circuit": state.circuit.snapshot(),
+    });
+    if let Some(cache) = &state.note_cache {
+        json_response["cache"] = cache.snapshot();
+    }
+
+    let code = if matches!(status, HealthStatus::Unavailable | HealthStatus::Outdated) {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+
+    (code, Json(json_response))
+}
@@ -0,0 +1,390 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, HeaderValue},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use uuid::Uuid;
+
+use crate::{query::PageStyle, AppState};
+
+/// A `_links` block, keyed by relation.
+pub type Links = BTreeMap<&'static str, Link>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Link {
+    pub href: String,
+}
+
+/// `?links=false` turns off `_links` blocks.
+#[derive(Debug, Deserialize)]
+pub struct LinksParams {
+    links: Option<bool>,
+}
+
+impl LinksParams {
+    pub fn enabled(&self) -> bool {
+        self.links.unwrap_or(true)
+    }
+
+    /// The `_links` block of a note response, unless the client turned it off.
+    pub fn note_links(&self, base: &BaseUrl, id: Uuid) -> Option<Links> {
+        self.enabled().then(|| note_links(base, id))
+    }
+}
+
+/// The externally visible root of the API for one request, such as
+/// `https://notes.example.com/pad`.
+#[derive(Debug, Clone)]
+pub struct BaseUrl(String);
+
+/// The base as seen by the client, believing `X-Forwarded-Proto`,
+/// `X-Forwarded-Host` and `X-Forwarded-Prefix` only from a peer in
+/// `TRUSTED_PROXIES`, as for the client address.
+impl FromRequestParts<Arc<AppState>> for BaseUrl {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let trust_proxy = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .is_some_and(|ConnectInfo(peer)| state.trusted_proxies.trusts(peer.ip().to_canonical()));
+        Ok(BaseUrl::from_headers(&parts.headers, trust_proxy, &state.public_base_url))
+    }
+}
+
+impl BaseUrl {
+    /// Builds the base from the request's `Host`, or from proxy headers when
+    /// `trust_proxy`, falling back to `fallback` when the request names no
+    /// host.
+    pub fn from_headers(headers: &HeaderMap, trust_proxy: bool, fallback: &str) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                // Proxies append to these, so the first value is the client's.
+                .and_then(|value| value.split(',').next())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        let forwarded_host = trust_proxy.then(|| header("x-forwarded-host")).flatten();
+        let Some(host) = forwarded_host.or_else(|| header(header::HOST.as_str())) else {
+            return BaseUrl(fallback.trim_end_matches('/').to_string());
+        };
+
+        let scheme = trust_proxy
+            .then(|| header("x-forwarded-proto"))
+            .flatten()
+            .filter(|scheme| scheme.eq_ignore_ascii_case("https") || scheme.eq_ignore_ascii_case("http"))
+            .unwrap_or("http")
+            .to_ascii_lowercase();
+        let prefix = trust_proxy
+            .then(|| header("x-forwarded-prefix"))
+            .flatten()
+            .map(|prefix| format!("/{}", prefix.trim_matches('/')))
+            .filter(|prefix| prefix != "/")
+            .unwrap_or_default();
+
+        BaseUrl(format!("{}://{}{}", scheme, host, prefix))
+    }
+
+    /// `path` must start with `/`.
+    pub fn join(&self, path: &str) -> String {
+        format!("{}{}", self.0, path)
+    }
+}
+
+/// Links for a single note.
+pub fn note_links(base: &BaseUrl, id: Uuid) -> Links {
+    Links::from([
+        ("self", Link { href: base.join(&format!("/api/v1/notes/{}", id)) }),
+        ("collection", Link { href: base.join("/api/v1/notes") }),
+        (
+            "attachments",
+            Link { href: base.join(&format!("/api/v1/notes/{}/attachments", id)) },
+        ),
+    ])
+}
+
+/// The response header giving how many pages a listing has.
+pub const TOTAL_PAGES_HEADER: &str = "x-total-pages";
+
+/// Response headers naming the sort a listing used, whether asked for or
+/// the default.
+pub const SORT_BY_HEADER: &str = "x-sort-by";
+pub const SORT_ORDER_HEADER: &str = "x-sort-order";
+
+/// Pages of `limit` notes needed for `total`.
+pub fn total_pages(total: i64, limit: i64) -> i64 {
+    if limit > 0 { (total + limit - 1) / limit } else { 0 }
+}
+
+/// The `first`, `self`, `next`, `prev` and `last` links of a listing at
+/// `path` with `total` notes across its pages, sent both as a `Link` header
+/// and as the list's `_links` block. The links page the way the request
+/// did, by `offset` or by `page`.
+pub struct PageLinks(Vec<(&'static str, String)>);
+
+impl PageLinks {
+    pub fn new(
+        base: &BaseUrl,
+        path: &str,
+        query: Option<&str>,
+        style: PageStyle,
+        offset: i64,
+        limit: i64,
+        total: i64,
+    ) -> Self {
+        let replaced = match style {
+            PageStyle::Offset => "offset=",
+            PageStyle::Page => "page=",
+        };
+        let page = |offset: i64| {
+            let mut pairs: Vec<String> = query
+                .unwrap_or_default()
+                .split('&')
+                .filter(|pair| !pair.is_empty() && !pair.starts_with(replaced))
+                .map(String::from)
+                .collect();
+            pairs.push(match style {
+                PageStyle::Offset => format!("offset={}", offset),
+                PageStyle::Page => format!("page={}", offset / limit + 1),
+            });
+            format!("{}?{}", base.join(path), pairs.join("&"))
+        };
+
+        let mut links = vec![("first", page(0)), ("self", page(offset))];
+        if limit > 0 && offset + limit < total {
+            links.push(("next", page(offset + limit)));
+        }
+        if offset > 0 {
+            links.push(("prev", page((offset - limit).max(0))));
+        }
+        let pages = total_pages(total, limit);
+        if pages > 0 {
+            links.push(("last", page((pages - 1) * limit)));
+        }
+
+        PageLinks(links)
+    }
+
+    /// The links as an RFC 8288 `Link` header.
+    pub fn header(&self) -> HeaderValue {
+        let links: Vec<String> = self.0.iter().map(|(rel, href)| format!("<{}>; rel=\"{}\"", href, rel)).collect();
+        HeaderValue::from_str(&links.join(", ")).unwrap_or_else(|_| HeaderValue::from_static(""))
+    }
+
+    /// The links as a `_links` block.
+    pub fn block(&self) -> Links {
+        self.0
+            .iter()
+            .map(|(rel, href)| (*rel, Link { href: href.clone() }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{TestApp, PEER};
+
+    const FALLBACK: &str = "http://localhost:8080/";
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in pairs {
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    const PROXIED: &[(&str, &str)] = &[
+        ("host", "10.0.0.5:8080"),
+        ("x-forwarded-proto", "https"),
+        ("x-forwarded-host", "notes.example.com"),
+        ("x-forwarded-prefix", "/pad/"),
+    ];
+
+    #[test]
+    fn uses_the_host_header() {
+        let base = BaseUrl::from_headers(&headers(&[("host", "notes.example.com")]), false, FALLBACK);
+        assert_eq!(base.join("/api/v1/notes"), "http://notes.example.com/api/v1/notes");
+    }
+
+    #[test]
+    fn falls_back_without_a_host() {
+        let base = BaseUrl::from_headers(&HeaderMap::new(), false, FALLBACK);
+        assert_eq!(base.join("/api/v1/notes"), "http://localhost:8080/api/v1/notes");
+        let base = BaseUrl::from_headers(&headers(&[("host", "  ")]), true, FALLBACK);
+        assert_eq!(base.join("/x"), "http://localhost:8080/x");
+    }
+
+    #[test]
+    fn ignores_proxy_headers_unless_trusted() {
+        let base = BaseUrl::from_headers(&headers(PROXIED), false, FALLBACK);
+        assert_eq!(base.join("/api"), "http://10.0.0.5:8080/api");
+    }
+
+    #[test]
+    fn trusted_proxy_headers_set_scheme_host_and_prefix() {
+        let base = BaseUrl::from_headers(&headers(PROXIED), true, FALLBACK);
+        assert_eq!(base.join("/api"), "https://notes.example.com/pad/api");
+    }
+
+    #[test]
+    fn trusted_proxy_headers_are_each_optional() {
+        let base = BaseUrl::from_headers(&headers(&[("host", "a.example"), ("x-forwarded-proto", "HTTPS")]), true, FALLBACK);
+        assert_eq!(base.join("/api"), "https://a.example/api");
+
+        let base = BaseUrl::from_headers(&headers(&[("x-forwarded-host", "b.example")]), true, FALLBACK);
+        assert_eq!(base.join("/api"), "http://b.example/api");
+
+        let base = BaseUrl::from_headers(&headers(&[("host", "c.example"), ("x-forwarded-prefix", "/")]), true, FALLBACK);
+        assert_eq!(base.join("/api"), "http://c.example/api");
+    }
+
+    #[test]
+    fn takes_the_first_of_appended_proxy_values_and_rejects_odd_schemes() {
+        let base = BaseUrl::from_headers(
+            &headers(&[
+                ("x-forwarded-host", "client.example, proxy.internal"),
+                ("x-forwarded-proto", "javascript"),
+            ]),
+            true,
+            FALLBACK,
+        );
+        assert_eq!(base.join("/api"), "http://client.example/api");
+    }
+
+    fn rels(links: &PageLinks) -> Vec<&'static str> {
+        links.0.iter().map(|(rel, _)| *rel).collect()
+    }
+
+    fn href(links: &PageLinks, rel: &str) -> String {
+        links.0.iter().find(|(name, _)| *name == rel).map(|(_, href)| href.clone()).unwrap()
+    }
+
+    fn base() -> BaseUrl {
+        BaseUrl::from_headers(&HeaderMap::new(), false, "https://notes.example.com")
+    }
+
+    #[test]
+    fn first_page_has_next_but_no_prev() {
+        let links = PageLinks::new(&base(), "/api/v1/notes", Some("limit=10"), PageStyle::Offset, 0, 10, 25);
+        assert_eq!(rels(&links), ["first", "self", "next", "last"]);
+        assert_eq!(href(&links, "first"), "https://notes.example.com/api/v1/notes?limit=10&offset=0");
+        assert_eq!(href(&links, "next"), "https://notes.example.com/api/v1/notes?limit=10&offset=10");
+        assert_eq!(href(&links, "last"), "https://notes.example.com/api/v1/notes?limit=10&offset=20");
+    }
+
+    #[test]
+    fn middle_page_has_both_neighbours() {
+        let links = PageLinks::new(&base(), "/n", Some("offset=10&limit=10&sort_by=title"), PageStyle::Offset, 10, 10, 25);
+        assert_eq!(rels(&links), ["first", "self", "next", "prev", "last"]);
+        assert_eq!(href(&links, "self"), "https://notes.example.com/n?limit=10&sort_by=title&offset=10");
+        assert_eq!(href(&links, "prev"), "https://notes.example.com/n?limit=10&sort_by=title&offset=0");
+    }
+
+    #[test]
+    fn last_page_has_prev_but_no_next() {
+        let links = PageLinks::new(&base(), "/n", None, PageStyle::Offset, 20, 10, 25);
+        assert_eq!(rels(&links), ["first", "self", "prev", "last"]);
+        assert_eq!(href(&links, "self"), href(&links, "last"));
+        // An exactly full last page is still the last.
+        let links = PageLinks::new(&base(), "/n", None, PageStyle::Offset, 10, 10, 20);
+        assert_eq!(rels(&links), ["first", "self", "prev", "last"]);
+    }
+
+    #[test]
+    fn prev_of_an_unaligned_offset_stops_at_zero() {
+        let links = PageLinks::new(&base(), "/n", None, PageStyle::Offset, 5, 10, 25);
+        assert_eq!(href(&links, "prev"), "https://notes.example.com/n?offset=0");
+    }
+
+    #[test]
+    fn empty_listing_has_no_last() {
+        let links = PageLinks::new(&base(), "/n", None, PageStyle::Offset, 0, 10, 0);
+        assert_eq!(rels(&links), ["first", "self"]);
+    }
+
+    #[test]
+    fn page_style_links_count_pages_from_one() {
+        let links = PageLinks::new(&base(), "/n", Some("page=2&per_page=10"), PageStyle::Page, 10, 10, 25);
+        assert_eq!(href(&links, "first"), "https://notes.example.com/n?per_page=10&page=1");
+        assert_eq!(href(&links, "next"), "https://notes.example.com/n?per_page=10&page=3");
+        assert_eq!(href(&links, "prev"), "https://notes.example.com/n?per_page=10&page=1");
+        assert_eq!(href(&links, "last"), "https://notes.example.com/n?per_page=10&page=3");
+    }
+
+    #[test]
+    fn header_and_block_carry_the_same_links() {
+        let links = PageLinks::new(&base(), "/n", None, PageStyle::Offset, 10, 10, 30);
+        assert_eq!(
+            links.header().to_str().unwrap(),
+            "<https://notes.example.com/n?offset=0>; rel=\"first\", \
+             <https://notes.example.com/n?offset=10>; rel=\"self\", \
+             <https://notes.example.com/n?offset=20>; rel=\"next\", \
+             <https://notes.example.com/n?offset=0>; rel=\"prev\", \
+             <https://notes.example.com/n?offset=20>; rel=\"last\""
+        );
+        let block = serde_json::to_value(links.block()).unwrap();
+        assert_eq!(block["next"]["href"], "https://notes.example.com/n?offset=20");
+        assert_eq!(block["prev"]["href"], "https://notes.example.com/n?offset=0");
+        assert_eq!(block.as_object().unwrap().len(), 5);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn list_body_carries_paging_links_unless_turned_off(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        for title in ["one", "two", "three"] {
+            app.create_note(json!({"title": title, "content": ""})).await;
+        }
+
+        let response = app.get("/api/v1/notes?limit=1&offset=1").await;
+        let body = response.json();
+        assert_eq!(body["notes"].as_array().unwrap().len(), 1);
+        let links = &body["_links"];
+        assert_eq!(links["self"]["href"], "http://localhost:8080/api/v1/notes?limit=1&offset=1");
+        assert_eq!(links["next"]["href"], "http://localhost:8080/api/v1/notes?limit=1&offset=2");
+        assert_eq!(links["prev"]["href"], "http://localhost:8080/api/v1/notes?limit=1&offset=0");
+        let header = response.header("link").unwrap();
+        for rel in ["self", "next", "prev"] {
+            assert!(header.contains(&format!("<{}>; rel=\"{}\"", links[rel]["href"].as_str().unwrap(), rel)));
+        }
+
+        let response = app.get("/api/v1/notes?limit=1&links=false").await;
+        assert!(response.json().get("_links").is_none());
+        assert!(response.header("link").is_some());
+    }
+
+    fn forwarded_create() -> Request<Body> {
+        Request::post("/api/v1/notes")
+            .header("content-type", "application/json")
+            .header("x-forwarded-proto", "https")
+            .header("x-forwarded-host", "notes.example.com")
+            .header("x-forwarded-prefix", "/pad")
+            .body(Body::from(json!({"title": "t", "content": ""}).to_string()))
+            .unwrap()
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn forwarded_headers_shape_links_only_from_a_trusted_proxy(pool: sqlx::PgPool) {
+        let untrusted = TestApp::new(pool.clone()).await;
+        let response = untrusted.request(forwarded_create()).await;
+        assert_eq!(response.status, 200);
+        let id = response.json()["id"].as_str().unwrap().to_owned();
+        assert_eq!(response.json()["_links"]["self"]["href"], format!("http://localhost:8080/api/v1/notes/{id}"));
+
+        let mut state = crate::test_support::state(pool).await;
+        state.trusted_proxies = std::net::IpAddr::from(PEER.0).to_string().parse().unwrap();
+        let trusted = TestApp::with_state(state);
+        let response = trusted.request(forwarded_create()).await;
+        assert_eq!(response.status, 200);
+        let id = response.json()["id"].as_str().unwrap().to_owned();
+        assert_eq!(response.json()["_links"]["self"]["href"], format!("https://notes.example.com/pad/api/v1/notes/{id}"));
+    }
+}
@@ -0,0 +1,114 @@
+use axum::http::{HeaderMap, StatusCode};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool, Row};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+pub const KEY_HEADER: &str = "idempotency-key";
+
+/// Set on responses that replay an earlier request instead of creating
+/// anything.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// How long a key is remembered.
+const KEY_LIFETIME: chrono::Duration = chrono::Duration::hours(24);
+
+/// What to do with a request carrying an idempotency key.
+pub enum Claim {
+    /// First use of the key: go ahead and create.
+    New,
+    /// A retry of a request that already created this note.
+    Replay(Uuid),
+}
+
+/// Reads the `Idempotency-Key` header, if any.
+pub fn key_from(headers: &HeaderMap) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(value) = headers.get(KEY_HEADER) else {
+        return Ok(None);
+    };
+
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => Ok(Some(key.to_string())),
+        _ => Err((
+            StatusCode::BAD_REQUEST,
+            format!("Idempotency-Key must be 1 to {} visible ASCII characters", MAX_KEY_LENGTH),
+        )),
+    }
+}
+
+/// Fingerprint of a JSON request body. Parsed values serialize with sorted
+/// keys, so formatting and key order don't matter.
+pub fn request_hash(body: &serde_json::Value) -> String {
+    format!("{:x}", Sha256::digest(body.to_string().as_bytes()))
+}
+
+/// Claims `key` for this request inside the creating transaction. The
+/// primary key makes concurrent retries wait for the first one to commit,
+/// after which they replay its note instead of inserting again.
+pub async fn claim(conn: &mut PgConnection, key: &str, request_hash: &str) -> Result<Claim, AppError> {
+    let inserted = sqlx::query(
+        "INSERT INTO idempotency_keys (key, request_hash) VALUES ($1, $2)
+         ON CONFLICT (key) DO NOTHING
+         RETURNING key",
+    )
+    .bind(key)
+    .bind(request_hash)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if inserted.is_some() {
+        return Ok(Claim::New);
+    }
+
+    let row = sqlx::query("SELECT request_hash, note_id FROM idempotency_keys WHERE key = $1")
+        .bind(key)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or((
+            StatusCode::CONFLICT,
+            "Idempotency-Key was released concurrently; retry the request".to_string(),
+        ))?;
+
+    let stored_hash: String = row.try_get("request_hash")?;
+    if stored_hash != request_hash {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "idempotency_key_reused",
+            "Idempotency-Key was already used with a different request body",
+        ));
+    }
+
+    let note_id: Option<Uuid> = row.try_get("note_id")?;
+    note_id.map(Claim::Replay).ok_or_else(|| {
+        (
+            StatusCode::CONFLICT,
+            "A request with this Idempotency-Key is still in progress".to_string(),
+        )
+            .into()
+    })
+}
+
+/// Records the note created under a claimed key.
+pub async fn record(conn: &mut PgConnection, key: &str, note_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE idempotency_keys SET note_id = $1 WHERE key = $2")
+        .bind(note_id)
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Forgets keys older than their lifetime.
+pub async fn purge_expired_keys(db: &PgPool) -> Result<u64, sqlx::Error> {
+    let purged = sqlx::query("DELETE FROM idempotency_keys WHERE created_at < $1")
+        .bind(chrono::Utc::now() - KEY_LIFETIME)
+        .execute(db)
+        .await?
+        .rows_affected();
+
+    Ok(purged)
+}
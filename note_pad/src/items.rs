@@ -0,0 +1,329 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, PgConnection, PgPool, Row};
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+use crate::{error::AppError, events::NoteEvent, extract::{Json, Path}, passwords, query::visible, read_only, AppState};
+
+/// A checklist item of a note. Items are ordered by `position`, which runs
+/// from 0 without gaps.
+#[derive(Debug, Clone, Serialize, Deserialize, async_graphql::SimpleObject)]
+pub struct NoteItem {
+    pub id: Uuid,
+    pub text: String,
+    pub done: bool,
+    pub position: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl NoteItem {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        Ok(NoteItem {
+            id: row.try_get("id")?,
+            text: row.try_get("text")?,
+            done: row.try_get("done")?,
+            position: row.try_get("position")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateItem {
+    text: String,
+    #[serde(default)]
+    done: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateItem {
+    text: Option<String>,
+    done: Option<bool>,
+    /// Moves the item here, shifting the ones in between. Positions past
+    /// the end move it to the end.
+    position: Option<i32>,
+}
+
+fn validate_text(text: Option<&str>) -> Result<(), (StatusCode, String)> {
+    match text {
+        Some(text) if text.trim().is_empty() => {
+            Err((StatusCode::UNPROCESSABLE_ENTITY, "text must not be empty".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub async fn fetch_for_note(state: &AppState, note_id: Uuid) -> Result<Vec<NoteItem>, sqlx::Error> {
+    let mut items = fetch_for_notes(&state.db, &[note_id]).await?;
+    Ok(items.remove(&note_id).unwrap_or_default())
+}
+
+/// The items of several notes in one query, keyed by note. Notes without
+/// items are left out.
+pub async fn fetch_for_notes(db: &PgPool, note_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<NoteItem>>, sqlx::Error> {
+    let rows = sqlx::query("SELECT * FROM note_items WHERE note_id = ANY($1) ORDER BY note_id, position")
+        .bind(note_ids)
+        .fetch_all(db)
+        .await?;
+
+    let mut items: HashMap<Uuid, Vec<NoteItem>> = HashMap::new();
+    for row in &rows {
+        items.entry(row.try_get("note_id")?).or_default().push(NoteItem::from_row(row)?);
+    }
+    Ok(items)
+}
+
+/// Locks the note for an item change, refusing read-only notes and checking
+/// the password like any other edit.
+async fn lock_note(
+    state: &AppState,
+    conn: &mut PgConnection,
+    note_id: Uuid,
+    headers: &HeaderMap,
+) -> Result<(), AppError> {
+    read_only::ensure_writable(&mut *conn, note_id).await?;
+    passwords::unlock_note(state, &mut *conn, note_id, passwords::supplied(headers, None)).await
+}
+
+/// Item changes are changes to the note, so its ETag moves with them.
+async fn touch_note(conn: &mut PgConnection, note_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE notes SET updated_at = NOW(), version = version + 1 WHERE id = $1")
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn list_items(
+    State(state): State<Arc<AppState>>,
+    Path(note_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<NoteItem>>, AppError> {
+    let password_hash = sqlx::query_scalar(
+        concat!("SELECT password_hash FROM notes WHERE id = $1 AND ", visible!()),
+    )
+    .bind(note_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+    passwords::unlock(&state, note_id, password_hash, passwords::supplied(&headers, None)).await?;
+
+    Ok(Json(fetch_for_note(&state, note_id).await?))
+}
+
+/// Adds an item at the end of the list.
+pub async fn create_item(
+    State(state): State<Arc<AppState>>,
+    Path(note_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateItem>,
+) -> Result<(StatusCode, Json<NoteItem>), AppError> {
+    validate_text(Some(&payload.text))?;
+
+    let mut tx = state.db.begin().await?;
+
+    lock_note(&state, &mut tx, note_id, &headers).await?;
+
+    let row = sqlx::query(
+        "INSERT INTO note_items (note_id, text, done, position)
+         SELECT $1, $2, $3, COUNT(*) FROM note_items WHERE note_id = $1
+         RETURNING *",
+    )
+    .bind(note_id)
+    .bind(&payload.text)
+    .bind(payload.done)
+    .fetch_one(&mut tx)
+    .await?;
+    touch_note(&mut tx, note_id).await?;
+
+    tx.commit().await?;
+
+    state.events.publish(NoteEvent::Updated { note_id });
+
+    Ok((StatusCode::CREATED, Json(NoteItem::from_row(&row)?)))
+}
+
+/// Edits an item's text and done state, and moves it when `position` is
+/// given. The items in between shift by one in the same transaction, which
+/// holds the note's row lock, so concurrent reorders can't leave gaps or
+/// duplicates.
+pub async fn update_item(
+    State(state): State<Arc<AppState>>,
+    Path((note_id, item_id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateItem>,
+) -> Result<Json<NoteItem>, AppError> {
+    validate_text(payload.text.as_deref())?;
+
+    let mut tx = state.db.begin().await?;
+
+    lock_note(&state, &mut tx, note_id, &headers).await?;
+
+    let current: i32 = sqlx::query_scalar("SELECT position FROM note_items WHERE id = $1 AND note_id = $2")
+        .bind(item_id)
+        .bind(note_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Item not found".to_string()))?;
+
+    let mut position = current;
+    if let Some(requested) = payload.position {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM note_items WHERE note_id = $1")
+            .bind(note_id)
+            .fetch_one(&mut tx)
+            .await?;
+        position = requested.clamp(0, count as i32 - 1);
+
+        if position != current {
+            sqlx::query(
+                "UPDATE note_items
+                 SET position = position + CASE WHEN $2 < $3 THEN 1 ELSE -1 END
+                 WHERE note_id = $1 AND position BETWEEN LEAST($2, $3) AND GREATEST($2, $3) AND position <> $3",
+            )
+            .bind(note_id)
+            .bind(position)
+            .bind(current)
+            .execute(&mut tx)
+            .await?;
+        }
+    }
+
+    let row = sqlx::query(
+        "UPDATE note_items
+         SET text = COALESCE($1, text),
+             done = COALESCE($2, done),
+             position = $3
+         WHERE id = $4
+         RETURNING *",
+    )
+    .bind(payload.text)
+    .bind(payload.done)
+    .bind(position)
+    .bind(item_id)
+    .fetch_one(&mut tx)
+    .await?;
+    touch_note(&mut tx, note_id).await?;
+
+    tx.commit().await?;
+
+    state.events.publish(NoteEvent::Updated { note_id });
+
+    Ok(Json(NoteItem::from_row(&row)?))
+}
+
+/// Removes an item, closing the gap it leaves.
+pub async fn delete_item(
+    State(state): State<Arc<AppState>>,
+    Path((note_id, item_id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db.begin().await?;
+
+    lock_note(&state, &mut tx, note_id, &headers).await?;
+
+    let position: i32 =
+        sqlx::query_scalar("DELETE FROM note_items WHERE id = $1 AND note_id = $2 RETURNING position")
+            .bind(item_id)
+            .bind(note_id)
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or((StatusCode::NOT_FOUND, "Item not found".to_string()))?;
+    sqlx::query("UPDATE note_items SET position = position - 1 WHERE note_id = $1 AND position > $2")
+        .bind(note_id)
+        .bind(position)
+        .execute(&mut tx)
+        .await?;
+    touch_note(&mut tx, note_id).await?;
+
+    tx.commit().await?;
+
+    state.events.publish(NoteEvent::Updated { note_id });
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Flips an item's done state and returns the item.
+pub async fn toggle_item(
+    State(state): State<Arc<AppState>>,
+    Path((note_id, item_id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+) -> Result<Json<NoteItem>, AppError> {
+    let mut tx = state.db.begin().await?;
+
+    lock_note(&state, &mut tx, note_id, &headers).await?;
+
+    let row = sqlx::query("UPDATE note_items SET done = NOT done WHERE id = $1 AND note_id = $2 RETURNING *")
+        .bind(item_id)
+        .bind(note_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Item not found".to_string()))?;
+    touch_note(&mut tx, note_id).await?;
+
+    tx.commit().await?;
+
+    state.events.publish(NoteEvent::Updated { note_id });
+
+    Ok(Json(NoteItem::from_row(&row)?))
+}
+
+/// Checks every item of the note.
+pub async fn complete_all(
+    State(state): State<Arc<AppState>>,
+    Path(note_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<NoteItem>>, AppError> {
+    set_all_done(&state, note_id, &headers, true).await
+}
+
+/// Unchecks every item of the note.
+pub async fn uncheck_all(
+    State(state): State<Arc<AppState>>,
+    Path(note_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<NoteItem>>, AppError> {
+    set_all_done(&state, note_id, &headers, false).await
+}
+
+/// Sets `done` on every item in one statement and returns the whole list.
+/// The note only counts as changed, with one `note.updated` event, if an
+/// item actually changed.
+async fn set_all_done(
+    state: &AppState,
+    note_id: Uuid,
+    headers: &HeaderMap,
+    done: bool,
+) -> Result<Json<Vec<NoteItem>>, AppError> {
+    let mut tx = state.db.begin().await?;
+
+    lock_note(state, &mut tx, note_id, headers).await?;
+
+    let changed = sqlx::query("UPDATE note_items SET done = $2 WHERE note_id = $1 AND done <> $2")
+        .bind(note_id)
+        .bind(done)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+    if changed > 0 {
+        touch_note(&mut tx, note_id).await?;
+    }
+
+    let rows = sqlx::query("SELECT * FROM note_items WHERE note_id = $1 ORDER BY position")
+        .bind(note_id)
+        .fetch_all(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    if changed > 0 {
+        state.events.publish(NoteEvent::Updated { note_id });
+    }
+
+    Ok(Json(rows.iter().map(NoteItem::from_row).collect::<Result<_, _>>()?))
+}
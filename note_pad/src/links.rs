@@ -0,0 +1,155 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool, Row};
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+use crate::{attachments::note_exists, error::AppError, extract::{Json, Path}, query::visible, AppState};
+
+/// A note linking to the requested one.
+#[derive(Debug, Clone, Serialize, async_graphql::SimpleObject)]
+pub struct Backlink {
+    pub id: Uuid,
+    pub title: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A `[[...]]` reference written in the requested note. `note_id` and
+/// `note_title` are `None` while no note with that title exists.
+#[derive(Debug, Serialize)]
+pub struct OutgoingLink {
+    pub title: String,
+    pub note_id: Option<Uuid>,
+    pub note_title: Option<String>,
+}
+
+/// Collects the distinct titles referenced as `[[Title]]` in `content`, in
+/// order of first appearance.
+pub fn parse_wiki_links(content: &str) -> Vec<String> {
+    let mut titles: Vec<String> = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find("[[") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find("]]") else {
+            break;
+        };
+        let title = rest[..end].trim();
+        if !title.is_empty() && !title.contains('[') && title.chars().count() <= 255 && !titles.iter().any(|t| t == title) {
+            titles.push(title.to_string());
+        }
+        rest = &rest[end + 2..];
+    }
+
+    titles
+}
+
+/// Replaces the stored outgoing links of `source_id` with the ones found in
+/// `content`. Links that were already resolved keep their target, so renaming
+/// the target note doesn't break them.
+pub async fn sync_links(conn: &mut PgConnection, source_id: Uuid, content: &str) -> Result<(), sqlx::Error> {
+    let titles = parse_wiki_links(content);
+
+    sqlx::query("DELETE FROM note_links WHERE source_id = $1 AND NOT (target_title = ANY($2))")
+        .bind(source_id)
+        .bind(&titles)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        concat!("INSERT INTO note_links (source_id, target_title, target_id)
+         SELECT $1, t.title, (
+             SELECT n.id FROM notes n
+             WHERE n.title = t.title AND ", visible!("n"), "
+             ORDER BY n.created_at LIMIT 1
+         )
+         FROM UNNEST($2::varchar[]) AS t(title)
+         ON CONFLICT (source_id, target_title)
+         DO UPDATE SET target_id = COALESCE(note_links.target_id, EXCLUDED.target_id)"),
+    )
+    .bind(source_id)
+    .bind(&titles)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Points pending links written as `[[title]]` at `note_id`.
+pub async fn resolve_pending(conn: &mut PgConnection, note_id: Uuid, title: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE note_links SET target_id = $1 WHERE target_id IS NULL AND target_title = $2")
+        .bind(note_id)
+        .bind(title)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_backlinks(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<Backlink>>, AppError> {
+    if !note_exists(&state, id).await? {
+        return Err((StatusCode::NOT_FOUND, "Note not found".to_string()).into());
+    }
+
+    let mut backlinks = backlinks_for(&state.db, &[id]).await?;
+    Ok(Json(backlinks.remove(&id).unwrap_or_default()))
+}
+
+/// The backlinks of several notes in one query, keyed by the linked note,
+/// most recently updated first. Notes nothing links to are left out.
+pub async fn backlinks_for(db: &PgPool, ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Backlink>>, sqlx::Error> {
+    let rows = sqlx::query(
+        concat!("SELECT l.target_id, n.id, n.title, n.updated_at FROM note_links l JOIN notes n ON n.id = l.source_id
+         WHERE l.target_id = ANY($1) AND ", visible!("n"), "
+         ORDER BY n.updated_at DESC"),
+    )
+    .bind(ids)
+    .fetch_all(db)
+    .await?;
+
+    let mut backlinks: HashMap<Uuid, Vec<Backlink>> = HashMap::new();
+    for row in rows {
+        backlinks.entry(row.try_get("target_id")?).or_default().push(Backlink {
+            id: row.try_get("id")?,
+            title: row.try_get("title")?,
+            updated_at: row.try_get("updated_at")?,
+        });
+    }
+    Ok(backlinks)
+}
+
+pub async fn get_links(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<OutgoingLink>>, AppError> {
+    if !note_exists(&state, id).await? {
+        return Err((StatusCode::NOT_FOUND, "Note not found".to_string()).into());
+    }
+
+    let rows = sqlx::query(
+        concat!("SELECT l.target_title, n.id, n.title FROM note_links l
+         LEFT JOIN notes n ON n.id = l.target_id AND ", visible!("n"), "
+         WHERE l.source_id = $1 ORDER BY l.target_title"),
+    )
+    .bind(id)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut links = Vec::new();
+    for row in rows {
+        links.push(OutgoingLink {
+            title: row.try_get("target_title")?,
+            note_id: row.try_get("id")?,
+            note_title: row.try_get("title")?,
+        });
+    }
+
+    Ok(Json(links))
+}
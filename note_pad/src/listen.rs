@@ -0,0 +1,154 @@
+use axum::{extract::Request, Router, ServiceExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+use tokio::{net::TcpListener, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+use tower_http::normalize_path::NormalizePath;
+
+const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+const DEFAULT_PORT: u16 = 8080;
+
+/// Pending connections the OS queues per listener.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// The addresses to listen on: `LISTEN_ADDRS`, a comma separated list such
+/// as `0.0.0.0:8080,[::]:8080`, or else `HOST` (an IP address, default
+/// `0.0.0.0`) and `PORT` (default 8080). Port 0, which asks the OS for a
+/// free port, is refused unless `ALLOW_EPHEMERAL_PORT` is true, as it is for
+/// tests that read the bound port from the startup log.
+pub fn addrs_from_env() -> Vec<SocketAddr> {
+    let addrs = match std::env::var("LISTEN_ADDRS") {
+        Ok(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|addr| !addr.is_empty())
+            .map(|addr| {
+                addr.parse()
+                    .unwrap_or_else(|_| panic!("LISTEN_ADDRS entry {:?} must be an address such as [::]:8080", addr))
+            })
+            .collect(),
+        Err(_) => {
+            let host = std::env::var("HOST")
+                .ok()
+                .map(|value| value.parse().expect("HOST must be an IP address such as 127.0.0.1"))
+                .unwrap_or(DEFAULT_HOST);
+            let port = std::env::var("PORT")
+                .ok()
+                .map(|value| value.parse().expect("PORT must be a port number from 1 to 65535"))
+                .unwrap_or(DEFAULT_PORT);
+            vec![SocketAddr::new(host, port)]
+        }
+    };
+    assert!(!addrs.is_empty(), "LISTEN_ADDRS must name at least one address");
+
+    let allow_ephemeral = std::env::var("ALLOW_EPHEMERAL_PORT")
+        .ok()
+        .map(|value| value.parse().expect("ALLOW_EPHEMERAL_PORT must be true or false"))
+        .unwrap_or(false);
+    assert!(
+        addrs.iter().all(|addr| addr.port() != 0) || allow_ephemeral,
+        "PORT must be a port number from 1 to 65535 (set ALLOW_EPHEMERAL_PORT=true to let the OS pick one)"
+    );
+
+    addrs
+}
+
+/// Binds every address, exiting with an error naming the first that fails,
+/// such as when the port is taken or needs privileges.
+///
+/// A lone IPv6 address like `[::]` also accepts IPv4 clients where the OS
+/// allows it. With several addresses, IPv6 listeners only take IPv6, so
+/// `0.0.0.0:8080` and `[::]:8080` can be bound side by side.
+pub fn bind_all(addrs: &[SocketAddr]) -> Vec<TcpListener> {
+    let only_v6 = addrs.len() > 1;
+    addrs
+        .iter()
+        .map(|&addr| match bind(addr, only_v6) {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!(error = %e, "could not listen on {}", addr);
+                std::process::exit(1);
+            }
+        })
+        .collect()
+}
+
+fn bind(addr: SocketAddr, only_v6: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    // As `TcpListener::bind` does, so restarts don't wait out TIME_WAIT.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Serves `app` on every listener until Ctrl-C or SIGTERM, then lets
+/// requests in flight finish on all of them. A listener that fails shuts
+/// the others down too.
+pub async fn serve(listeners: Vec<TcpListener>, app: NormalizePath<Router>) {
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("shutting down");
+            shutdown.cancel();
+        }
+    });
+
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let service = ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app.clone());
+        let shutdown = shutdown.clone();
+        servers.spawn(async move {
+            axum::serve(listener, service)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+        });
+    }
+
+    let mut failed = false;
+    while let Some(result) = servers.join_next().await {
+        let error = match result {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+        tracing::error!(error, "server stopped");
+        failed = true;
+        shutdown.cancel();
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
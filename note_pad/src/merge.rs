@@ -0,0 +1,129 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use serde::Deserialize;
+use sqlx::Row;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    content_limit, crypto, duplicates, error::AppError, events::NoteEvent, extract::{Json, Path, Query}, hypermedia::{BaseUrl, LinksParams}, links, passwords, query::visible,
+    read_only, AppState, Note,
+};
+
+const DEFAULT_SEPARATOR: &str = "\n\n---\n\n";
+
+#[derive(Debug, Deserialize)]
+pub struct MergeRequest {
+    source_id: Uuid,
+    separator: Option<String>,
+    #[serde(default)]
+    delete_source: bool,
+}
+
+/// Appends the source note's content to the target, separated by
+/// `separator` (a horizontal rule by default), and with `delete_source`
+/// deletes the source. Its attachments then move to the target and links to
+/// it follow. Everything happens in one transaction, and the response is the
+/// merged target.
+pub async fn merge_note(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(link_params): Query<LinksParams>,
+    base: BaseUrl,
+    headers: HeaderMap,
+    Json(payload): Json<MergeRequest>,
+) -> Result<Json<Note>, AppError> {
+    let source_id = payload.source_id;
+    if source_id == id {
+        return Err((StatusCode::BAD_REQUEST, "A note can't be merged into itself".to_string()).into());
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    // Both rows are locked in id order, so two merges of the same pair in
+    // opposite directions can't deadlock.
+    let rows = sqlx::query(
+        concat!("SELECT * FROM notes
+         WHERE id = ANY($1) AND ", visible!(), "
+         ORDER BY id
+         FOR UPDATE"),
+    )
+    .bind([id, source_id].as_slice())
+    .fetch_all(&mut tx)
+    .await?;
+    let find = |wanted: Uuid, field: &'static str, name: &str| {
+        rows.iter()
+            .find(|row| row.try_get::<Uuid, _>("id").is_ok_and(|row_id| row_id == wanted))
+            .ok_or_else(|| {
+                AppError::new(StatusCode::NOT_FOUND, "note_not_found", format!("{} note not found", name))
+                    .with_details(serde_json::json!({ "field": field }))
+            })
+    };
+    let target = find(id, "id", "Target")?;
+    let source = find(source_id, "source_id", "Source")?;
+
+    read_only::ensure_writable(&mut tx, id).await?;
+    if payload.delete_source {
+        read_only::ensure_writable(&mut tx, source_id).await?;
+    }
+    let supplied = passwords::supplied(&headers, None);
+    passwords::unlock(&state, id, target.try_get("password_hash")?, supplied.clone()).await?;
+    passwords::unlock(&state, source_id, source.try_get("password_hash")?, supplied).await?;
+
+    let separator = payload.separator.as_deref().unwrap_or(DEFAULT_SEPARATOR);
+    let merged = format!("{}{}{}", crypto::content(target)?, separator, crypto::content(source)?);
+    content_limit::check(state.max_content_bytes, &merged)?;
+    let sealed = crypto::seal(&merged);
+
+    let row = sqlx::query(
+        "UPDATE notes
+         SET content = $1,
+             content_nonce = $2,
+             content_ciphertext = $3,
+             updated_at = NOW(),
+             version = version + 1
+         WHERE id = $4
+         RETURNING *",
+    )
+    .bind(sealed.plaintext)
+    .bind(sealed.nonce)
+    .bind(sealed.ciphertext)
+    .bind(id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    let mut note = Note::from_row(&row)?;
+    links::sync_links(&mut tx, id, &note.content)
+        .await?;
+    duplicates::store_hash(&mut tx, id, &note.title, &note.content).await?;
+
+    if payload.delete_source {
+        sqlx::query("UPDATE attachments SET note_id = $1 WHERE note_id = $2")
+            .bind(id)
+            .bind(source_id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("UPDATE note_links SET target_id = $1 WHERE target_id = $2")
+            .bind(id)
+            .bind(source_id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM notes WHERE id = $1")
+            .bind(source_id)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    note.links = link_params.note_links(&base, id);
+
+    state.events.publish(NoteEvent::Updated { note_id: id });
+    if payload.delete_source {
+        state.events.publish(NoteEvent::Deleted { note_id: source_id });
+    }
+
+    Ok(Json(note))
+}
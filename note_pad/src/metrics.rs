@@ -0,0 +1,128 @@
+use axum::http::header;
+use axum::response::IntoResponse;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    future::Future,
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tracing::Instrument;
+
+use crate::telemetry::{self, RowCount};
+
+const DEFAULT_SLOW_QUERY_MS: u64 = 250;
+
+/// Upper bounds of the duration histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Default)]
+struct OperationStats {
+    /// Queries at or under each bound of `BUCKETS`, not cumulative.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum_secs: f64,
+    errors: u64,
+}
+
+static OPERATIONS: LazyLock<Mutex<BTreeMap<&'static str, OperationStats>>> = LazyLock::new(Default::default);
+
+static SLOW_QUERY: OnceLock<Duration> = OnceLock::new();
+
+/// Reads `SLOW_QUERY_MS`, the duration from which a query is logged as slow
+/// (default 250).
+pub fn init_from_env() {
+    let ms = std::env::var("SLOW_QUERY_MS")
+        .ok()
+        .map(|value| value.parse().expect("SLOW_QUERY_MS must be a number of milliseconds"))
+        .unwrap_or(DEFAULT_SLOW_QUERY_MS);
+    SLOW_QUERY.set(Duration::from_millis(ms)).ok();
+}
+
+/// Runs a database call, recording its duration and whether it failed under
+/// `operation`, such as `notes.list`. Slow calls are logged with their
+/// operation and duration only, never the values bound to them. With
+/// tracing on, the call also gets a span with its row count.
+pub async fn timed<T: RowCount, E>(operation: &'static str, call: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    let span = telemetry::query_span(operation);
+    let started = Instant::now();
+    let result = call.instrument(span.clone()).await;
+    let elapsed = started.elapsed();
+
+    match &result {
+        Ok(value) => {
+            span.record("db.rows", value.row_count());
+        }
+        Err(_) => {
+            span.record("otel.status_code", "ERROR");
+        }
+    }
+
+    {
+        let mut operations = OPERATIONS.lock().unwrap();
+        let stats = operations.entry(operation).or_default();
+        let secs = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| secs <= *bound) {
+            stats.buckets[bucket] += 1;
+        }
+        stats.count += 1;
+        stats.sum_secs += secs;
+        if result.is_err() {
+            stats.errors += 1;
+        }
+    }
+
+    let slow = SLOW_QUERY.get().copied().unwrap_or(Duration::from_millis(DEFAULT_SLOW_QUERY_MS));
+    if elapsed >= slow {
+        tracing::warn!(operation, duration_ms = elapsed.as_millis() as u64, "slow database query");
+    }
+    result
+}
+
+/// The recorded operations in the Prometheus text format.
+fn render() -> String {
+    let operations = OPERATIONS.lock().unwrap();
+    let mut out = String::new();
+
+    out.push_str("# HELP note_pad_db_query_duration_seconds Time spent in database calls, by operation.\n");
+    out.push_str("# TYPE note_pad_db_query_duration_seconds histogram\n");
+    for (operation, stats) in operations.iter() {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(stats.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "note_pad_db_query_duration_seconds_bucket{{operation=\"{}\",le=\"{}\"}} {}",
+                operation, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "note_pad_db_query_duration_seconds_bucket{{operation=\"{}\",le=\"+Inf\"}} {}",
+            operation, stats.count
+        );
+        let _ = writeln!(
+            out,
+            "note_pad_db_query_duration_seconds_sum{{operation=\"{}\"}} {}",
+            operation, stats.sum_secs
+        );
+        let _ = writeln!(
+            out,
+            "note_pad_db_query_duration_seconds_count{{operation=\"{}\"}} {}",
+            operation, stats.count
+        );
+    }
+
+    out.push_str("# HELP note_pad_db_query_errors_total Database calls that failed, by operation.\n");
+    out.push_str("# TYPE note_pad_db_query_errors_total counter\n");
+    for (operation, stats) in operations.iter() {
+        let _ = writeln!(out, "note_pad_db_query_errors_total{{operation=\"{}\"}} {}", operation, stats.errors);
+    }
+    out
+}
+
+/// `GET /metrics`: for Prometheus to scrape. Holds operation names and
+/// numbers only, like the health check.
+pub async fn get_metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], render())
+}
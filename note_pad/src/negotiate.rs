@@ -0,0 +1,170 @@
+use axum::http::{header, HeaderMap, StatusCode};
+
+use crate::error::AppError;
+
+/// Response formats an endpoint can offer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    Json,
+    PlainText,
+    Html,
+}
+
+impl MediaType {
+    pub fn essence(self) -> &'static str {
+        match self {
+            MediaType::Json => "application/json",
+            MediaType::PlainText => "text/plain",
+            MediaType::Html => "text/html",
+        }
+    }
+
+    /// Quality the `Accept` ranges give this type: the most specific
+    /// matching range wins, so `text/*;q=0, text/plain` still allows plain
+    /// text.
+    fn quality(self, ranges: &[(&str, f32)]) -> f32 {
+        let essence = self.essence();
+        let (kind, _) = essence.split_once('/').unwrap_or((essence, ""));
+
+        let mut best: Option<(u8, f32)> = None;
+        for &(range, q) in ranges {
+            let specificity = if range.eq_ignore_ascii_case(essence) {
+                2
+            } else if range.strip_suffix("/*").is_some_and(|range_kind| range_kind.eq_ignore_ascii_case(kind)) {
+                1
+            } else if range == "*/*" {
+                0
+            } else {
+                continue;
+            };
+
+            if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+                best = Some((specificity, q));
+            }
+        }
+
+        best.map_or(0.0, |(_, q)| q)
+    }
+}
+
+/// Picks the response format from the `Accept` header among `supported`,
+/// whose first entry is the default when the header is missing or ties. If
+/// nothing acceptable is supported, fails with 406 listing the options.
+pub fn negotiate(headers: &HeaderMap, supported: &[MediaType]) -> Result<MediaType, AppError> {
+    let accept = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    if accept.trim().is_empty() {
+        return Ok(supported[0]);
+    }
+
+    let ranges: Vec<(&str, f32)> = accept
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let range = parts.next().filter(|range| !range.is_empty())?;
+            let q = parts
+                .filter_map(|param| param.strip_prefix("q=").or_else(|| param.strip_prefix("Q=")))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((range, q))
+        })
+        .collect();
+
+    let mut chosen: Option<(MediaType, f32)> = None;
+    for &media_type in supported {
+        let q = media_type.quality(&ranges);
+        if q > 0.0 && chosen.is_none_or(|(_, best)| q > best) {
+            chosen = Some((media_type, q));
+        }
+    }
+
+    chosen.map(|(media_type, _)| media_type).ok_or_else(|| {
+        let supported: Vec<&str> = supported.iter().map(|media_type| media_type.essence()).collect();
+        AppError::new(
+            StatusCode::NOT_ACCEPTABLE,
+            "not_acceptable",
+            format!("None of the requested media types are available. Supported: {}", supported.join(", ")),
+        )
+        .with_details(serde_json::json!({ "supported": supported }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    const LISTING: &[MediaType] = &[MediaType::Json, MediaType::PlainText];
+
+    fn accept(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn pick(values: &[&str]) -> MediaType {
+        negotiate(&accept(values), LISTING).unwrap()
+    }
+
+    #[test]
+    fn missing_or_empty_accept_gets_the_default() {
+        assert_eq!(negotiate(&HeaderMap::new(), LISTING).unwrap(), MediaType::Json);
+        assert_eq!(pick(&[""]), MediaType::Json);
+        assert_eq!(
+            negotiate(&HeaderMap::new(), &[MediaType::PlainText, MediaType::Json]).unwrap(),
+            MediaType::PlainText
+        );
+    }
+
+    #[test]
+    fn explicit_types_are_honoured() {
+        assert_eq!(pick(&["application/json"]), MediaType::Json);
+        assert_eq!(pick(&["text/plain"]), MediaType::PlainText);
+        assert_eq!(pick(&["TEXT/PLAIN"]), MediaType::PlainText);
+        assert_eq!(pick(&["text/plain; charset=utf-8"]), MediaType::PlainText);
+    }
+
+    #[test]
+    fn higher_quality_wins() {
+        assert_eq!(pick(&["application/json;q=0.5, text/plain"]), MediaType::PlainText);
+        assert_eq!(pick(&["text/plain;q=0.4, application/json;q=0.9"]), MediaType::Json);
+        assert_eq!(pick(&["text/plain;Q=0.9, application/json;q=0.1"]), MediaType::PlainText);
+        // A tie goes to the endpoint's default.
+        assert_eq!(pick(&["text/plain, application/json"]), MediaType::Json);
+    }
+
+    #[test]
+    fn separate_accept_headers_are_combined() {
+        assert_eq!(pick(&["application/json;q=0.2", "text/plain"]), MediaType::PlainText);
+    }
+
+    #[test]
+    fn wildcards_match_by_specificity() {
+        assert_eq!(pick(&["*/*"]), MediaType::Json);
+        assert_eq!(pick(&["text/*"]), MediaType::PlainText);
+        assert_eq!(pick(&["*/*;q=0.1, text/*"]), MediaType::PlainText);
+        // The most specific range decides, even when a broader one refuses.
+        assert_eq!(pick(&["text/*;q=0, text/plain"]), MediaType::PlainText);
+        assert_eq!(pick(&["*/*, application/json;q=0"]), MediaType::PlainText);
+    }
+
+    #[test]
+    fn unsupported_types_get_406_listing_the_options() {
+        for values in [&["image/png"][..], &["text/html"], &["application/json;q=0, text/plain;q=0"]] {
+            let error = negotiate(&accept(values), LISTING).unwrap_err();
+            assert_eq!(error.status(), StatusCode::NOT_ACCEPTABLE, "{:?}", values);
+            assert_eq!(error.code(), "not_acceptable");
+            assert_eq!(
+                error.details(),
+                Some(&serde_json::json!({ "supported": ["application/json", "text/plain"] }))
+            );
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use sqlx::{PgConnection, Row};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{error::AppError, events::NoteEvent, extract::{Json, Path}, query::visible, read_only, AppState};
+
+/// Header carrying the password of a protected note.
+pub const PASSWORD_HEADER: &str = "x-note-password";
+
+#[derive(Debug, Deserialize)]
+pub struct SetPassword {
+    /// The new password, or `null` to remove protection.
+    password: Option<String>,
+    current_password: Option<String>,
+}
+
+/// The password supplied with a request, from the header or else a body
+/// field.
+pub fn supplied(headers: &HeaderMap, body: Option<String>) -> Option<String> {
+    headers
+        .get(PASSWORD_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or(body)
+}
+
+/// Checks `supplied` against a note's stored hash; notes without a password
+/// are always unlocked. Wrong attempts are limited per note, so a stolen
+/// note id can't be used to brute-force its password.
+pub async fn unlock(
+    state: &AppState,
+    note_id: Uuid,
+    password_hash: Option<String>,
+    supplied: Option<String>,
+) -> Result<(), AppError> {
+    let Some(password_hash) = password_hash else {
+        return Ok(());
+    };
+    let Some(supplied) = supplied else {
+        return Err(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "note_locked",
+            format!("This note is password protected; send the password in the {} header", PASSWORD_HEADER),
+        ));
+    };
+
+    state.password_limiter.peek(note_id)?;
+
+    let verified = tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&password_hash)
+            .map(|hash| Argon2::default().verify_password(supplied.as_bytes(), &hash).is_ok())
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if !verified {
+        // Counting only failures means the owner is never throttled.
+        let _ = state.password_limiter.check(note_id);
+        return Err(AppError::new(
+            StatusCode::UNAUTHORIZED,
+            "note_locked",
+            "Incorrect note password",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Looks up a note's password and unlocks it, failing with 404 if the note
+/// doesn't exist. Inside a transaction the row stays locked until commit.
+pub async fn unlock_note(
+    state: &AppState,
+    conn: &mut PgConnection,
+    note_id: Uuid,
+    supplied: Option<String>,
+) -> Result<(), AppError> {
+    let row = sqlx::query(
+        concat!("SELECT password_hash FROM notes
+         WHERE id = $1 AND ", visible!(), "
+         FOR UPDATE"),
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+    let password_hash = row.try_get("password_hash")?;
+
+    unlock(state, note_id, password_hash, supplied).await
+}
+
+async fn hash_password(password: String) -> Result<String, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
+}
+
+/// Sets, changes or (with `"password": null`) removes a note's password.
+/// Changing or removing an existing password requires the current one.
+/// Setting or changing it revokes the note's share links, which would
+/// otherwise keep showing the content to anyone holding an old link; a link
+/// made afterwards, with the password, works as usual.
+pub async fn set_password(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(payload): Json<SetPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    if payload.password.as_deref().is_some_and(str::is_empty) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "password must not be empty".to_string()).into());
+    }
+
+    let new_hash = match payload.password {
+        Some(password) => Some(hash_password(password).await?),
+        None => None,
+    };
+
+    let mut tx = state.db.begin().await?;
+
+    read_only::ensure_writable(&mut tx, id).await?;
+    unlock_note(&state, &mut tx, id, supplied(&headers, payload.current_password)).await?;
+
+    sqlx::query("UPDATE notes SET password_hash = $1, updated_at = NOW(), version = version + 1 WHERE id = $2")
+        .bind(&new_hash)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+
+    if new_hash.is_some() {
+        sqlx::query("UPDATE note_shares SET revoked_at = NOW() WHERE note_id = $1 AND revoked_at IS NULL")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    state.events.publish(NoteEvent::Updated { note_id: id });
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, Request},
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{self, TestApp, TestResponse};
+
+    async fn send_with_password(app: &TestApp, mut request: Request<Body>, password: Option<&str>) -> TestResponse {
+        if let Some(password) = password {
+            request
+                .headers_mut()
+                .insert(PASSWORD_HEADER, password.parse().unwrap());
+        }
+        app.request(request).await
+    }
+
+    async fn get_with_password(app: &TestApp, uri: &str, password: Option<&str>) -> TestResponse {
+        let mut request = Request::get(uri);
+        if let Some(password) = password {
+            request = request.header(PASSWORD_HEADER, password);
+        }
+        app.request(request.body(Body::empty()).unwrap()).await
+    }
+
+    async fn share(app: &TestApp, note_id: &str, password: Option<&str>) -> String {
+        let mut request = Request::post(format!("/api/v1/notes/{}/share", note_id));
+        if let Some(password) = password {
+            request = request.header(PASSWORD_HEADER, password);
+        }
+        let response = app.request(request.body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        response.json()["token"].as_str().unwrap().to_string()
+    }
+
+    async fn lock(app: &TestApp, note_id: &str, password: &str) {
+        let response = app
+            .send_json(
+                Method::PUT,
+                &format!("/api/v1/notes/{}/password", note_id),
+                json!({"password": password}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT, "{}", response.text());
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn setting_a_password_revokes_existing_share_links(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({"title": "Diary", "content": "secret"})).await;
+        let id = note["id"].as_str().unwrap();
+        let token = share(&app, id, None).await;
+        let shared = format!("/api/v1/shared/{}", token);
+        assert_eq!(app.get(&shared).await.status, StatusCode::OK);
+
+        lock(&app, id, "hunter2").await;
+        assert_eq!(app.get(&shared).await.status, StatusCode::NOT_FOUND);
+
+        // Sharing with the password is a deliberate choice and still works.
+        let token = share(&app, id, Some("hunter2")).await;
+        let response = app.get(&format!("/api/v1/shared/{}", token)).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()["content"], "secret");
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn attachments_of_a_protected_note_need_its_password(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({"title": "Scans", "content": ""})).await;
+        let id = note["id"].as_str().unwrap();
+        let uploaded = app.upload(id.parse().unwrap(), &[("passport.png", b"scan")]).await;
+        let attachment_id = uploaded.json()[0]["id"].as_str().unwrap().to_string();
+        lock(&app, id, "hunter2").await;
+
+        let list = format!("/api/v1/notes/{}/attachments", id);
+        let download = format!("/api/v1/attachments/{}", attachment_id);
+        for uri in [&list, &download] {
+            let response = get_with_password(&app, uri, None).await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{}", uri);
+            assert_eq!(response.json()["error"]["code"], "note_locked");
+            let response = get_with_password(&app, uri, Some("wrong")).await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+
+        let response = get_with_password(&app, &list, Some("hunter2")).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.json()[0]["filename"], "passport.png");
+        let response = get_with_password(&app, &download, Some("hunter2")).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(&response.body[..], b"scan");
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn deleting_a_protected_note_needs_its_password(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({"title": "Diary", "content": "secret"})).await;
+        let id = note["id"].as_str().unwrap();
+        lock(&app, id, "hunter2").await;
+        let uri = format!("/api/v1/notes/{}", id);
+        let delete = || Request::delete(&uri).body(Body::empty()).unwrap();
+
+        for password in [None, Some("wrong")] {
+            let response = send_with_password(&app, delete(), password).await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{:?}", password);
+            assert_eq!(response.json()["error"]["code"], "note_locked");
+        }
+        let response = get_with_password(&app, &uri, Some("hunter2")).await;
+        assert_eq!(response.status, StatusCode::OK);
+
+        let response = send_with_password(&app, delete(), Some("hunter2")).await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT, "{}", response.text());
+        assert_eq!(app.get(&uri).await.status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn changing_attachments_of_a_protected_note_needs_its_password(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({"title": "Scans", "content": ""})).await;
+        let id = note["id"].as_str().unwrap();
+        let uploaded = app.upload(id.parse().unwrap(), &[("passport.png", b"scan")]).await;
+        let attachment_id = uploaded.json()[0]["id"].as_str().unwrap().to_string();
+        lock(&app, id, "hunter2").await;
+
+        let list = format!("/api/v1/notes/{}/attachments", id);
+        let upload = || test_support::multipart(&list, "file", &[("visa.png", b"stamp")]);
+        let download = format!("/api/v1/attachments/{}", attachment_id);
+        let delete = || Request::delete(&download).body(Body::empty()).unwrap();
+
+        for password in [None, Some("wrong")] {
+            let response = send_with_password(&app, upload(), password).await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "upload with {:?}", password);
+            let response = send_with_password(&app, delete(), password).await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED, "delete with {:?}", password);
+        }
+        let response = get_with_password(&app, &list, Some("hunter2")).await;
+        assert_eq!(response.json().as_array().unwrap().len(), 1);
+
+        let response = send_with_password(&app, upload(), Some("hunter2")).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        let response = send_with_password(&app, delete(), Some("hunter2")).await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT, "{}", response.text());
+
+        let remaining = get_with_password(&app, &list, Some("hunter2")).await.json();
+        assert_eq!(remaining.as_array().unwrap().len(), 1);
+        assert_eq!(remaining[0]["filename"], "visa.png");
+    }
+}
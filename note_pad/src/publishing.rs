@@ -0,0 +1,233 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError, events::NoteEvent, extract::{Json, Path, Query}, hypermedia::{BaseUrl, LinksParams}, passwords, query::visible, read_only, AppState, Note,
+};
+
+const DEFAULT_TICK_SECS: u64 = 30;
+
+/// Notes claimed per round trip while publishing scheduled notes.
+const CLAIM_BATCH_SIZE: i64 = 100;
+
+/// Whether a note is visible to published-site integrations. `Scheduled`
+/// notes are flipped to `Published` by the scheduler at their `publish_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, async_graphql::Enum)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "note_status", rename_all = "lowercase")]
+pub enum NoteStatus {
+    Draft,
+    Scheduled,
+    Published,
+}
+
+impl NoteStatus {
+    pub const ALL: [NoteStatus; 3] = [NoteStatus::Draft, NoteStatus::Scheduled, NoteStatus::Published];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NoteStatus::Draft => "draft",
+            NoteStatus::Scheduled => "scheduled",
+            NoteStatus::Published => "published",
+        }
+    }
+
+    /// Parses the list endpoint's `?status=` filter, where `all` means no
+    /// filtering.
+    pub fn parse_filter(value: &str) -> Result<Option<Self>, (StatusCode, String)> {
+        if value == "all" {
+            return Ok(None);
+        }
+
+        Self::ALL.into_iter().find(|status| status.name() == value).map(Some).ok_or_else(|| {
+            let valid: Vec<&str> = Self::ALL.iter().map(|status| status.name()).collect();
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown status '{}'. Valid values are: {}, all", value, valid.join(", ")),
+            )
+        })
+    }
+
+    /// Create and update may only set `draft` or `published`; scheduling
+    /// needs a time and goes through the publish endpoint.
+    pub fn validate_settable(status: Option<Self>) -> Result<(), (StatusCode, String)> {
+        match status {
+            Some(NoteStatus::Scheduled) => Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Use POST /api/v1/notes/{id}/publish with an `at` time to schedule a note".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PublishRequest {
+    at: Option<DateTime<Utc>>,
+}
+
+pub fn published_event(note: &Note) -> NoteEvent {
+    NoteEvent::Published {
+        note_id: note.id,
+        title: note.title.clone(),
+        published_at: note.published_at.unwrap_or(note.updated_at),
+    }
+}
+
+/// Publishes the note now, or schedules it when `at` lies in the future. A
+/// new schedule replaces any previous one.
+pub async fn publish_note(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(link_params): Query<LinksParams>,
+    base: BaseUrl,
+    headers: HeaderMap,
+    payload: Option<Json<PublishRequest>>,
+) -> Result<Json<Note>, AppError> {
+    let Json(payload) = payload.unwrap_or_default();
+    let scheduled_at = payload.at.filter(|at| *at > Utc::now());
+
+    let mut tx = state.db.begin().await?;
+
+    read_only::ensure_writable(&mut tx, id).await?;
+    passwords::unlock_note(&state, &mut tx, id, passwords::supplied(&headers, None)).await?;
+
+    let row = sqlx::query(
+        concat!("UPDATE notes
+         SET status = CASE WHEN $1::timestamptz IS NULL THEN 'published' ELSE 'scheduled' END::note_status,
+             publish_at = $1,
+             published_at = CASE WHEN $1::timestamptz IS NULL THEN COALESCE(published_at, NOW()) END,
+             updated_at = NOW(),
+             version = version + 1
+         WHERE id = $2 AND ", visible!(), "
+         RETURNING *"),
+    )
+    .bind(scheduled_at)
+    .bind(id)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+
+    tx.commit().await?;
+
+    let mut note = Note::from_row(&row)?;
+    note.links = link_params.note_links(&base, id);
+
+    if note.status == NoteStatus::Published {
+        state.events.publish(published_event(&note));
+    } else {
+        state.events.publish(NoteEvent::Updated { note_id: id });
+    }
+
+    Ok(Json(note))
+}
+
+/// Returns the note to draft, cancelling any pending schedule.
+pub async fn unpublish_note(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(link_params): Query<LinksParams>,
+    base: BaseUrl,
+    headers: HeaderMap,
+) -> Result<Json<Note>, AppError> {
+    let mut tx = state.db.begin().await?;
+
+    read_only::ensure_writable(&mut tx, id).await?;
+    passwords::unlock_note(&state, &mut tx, id, passwords::supplied(&headers, None)).await?;
+
+    let row = sqlx::query(
+        concat!("UPDATE notes
+         SET status = 'draft', publish_at = NULL, published_at = NULL, updated_at = NOW(),
+             version = version + 1
+         WHERE id = $1 AND ", visible!(), "
+         RETURNING *"),
+    )
+    .bind(id)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+
+    tx.commit().await?;
+
+    let mut note = Note::from_row(&row)?;
+    note.links = link_params.note_links(&base, id);
+
+    state.events.publish(NoteEvent::Updated { note_id: id });
+
+    Ok(Json(note))
+}
+
+/// How often scheduled notes are checked, from `PUBLISH_TICK_SECS`
+/// (default 30).
+pub fn tick_from_env() -> Duration {
+    let secs = std::env::var("PUBLISH_TICK_SECS")
+        .ok()
+        .map(|value| value.parse().expect("PUBLISH_TICK_SECS must be a number of seconds"))
+        .unwrap_or(DEFAULT_TICK_SECS);
+
+    Duration::from_secs(secs)
+}
+
+/// Publishes every scheduled note whose time has come and emits a
+/// `note.published` event for each. All schedule state lives in the
+/// database and rows are claimed with `SKIP LOCKED`, so restarts and
+/// concurrent instances neither lose nor duplicate a publication.
+pub async fn publish_scheduled(state: &AppState) -> Result<usize, sqlx::Error> {
+    let mut published = 0;
+
+    loop {
+        let rows = sqlx::query(
+            concat!("UPDATE notes
+             SET status = 'published', published_at = publish_at, publish_at = NULL, updated_at = NOW(),
+                 version = version + 1
+             WHERE id IN (
+                 SELECT id FROM notes
+                 WHERE status = 'scheduled' AND publish_at <= NOW()
+                   AND ", visible!(), "
+                 ORDER BY publish_at
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, title, published_at"),
+        )
+        .bind(CLAIM_BATCH_SIZE)
+        .fetch_all(&state.db)
+        .await?;
+
+        let claimed = rows.len();
+        for row in rows {
+            state.events.publish(NoteEvent::Published {
+                note_id: row.try_get("id")?,
+                title: row.try_get("title")?,
+                published_at: row.try_get("published_at")?,
+            });
+        }
+        published += claimed;
+
+        if (claimed as i64) < CLAIM_BATCH_SIZE {
+            return Ok(published);
+        }
+    }
+}
+
+pub fn spawn_publish_scheduler(state: Arc<AppState>, tick: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tick);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match publish_scheduled(&state).await {
+                Ok(0) => {}
+                Ok(published) => tracing::info!(published, "published scheduled notes"),
+                Err(e) => tracing::error!(error = %e, "failed to publish scheduled notes"),
+            }
+        }
+    });
+}
@@ -0,0 +1,352 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::{publishing::NoteStatus, unaccent};
+
+/// The condition for a note to be served, as SQL for `concat!`: it hasn't
+/// expired, whether or not the purge task has removed it yet. Every query
+/// reading or changing notes includes it, so a condition added here hides
+/// notes from every endpoint at once. `visible!("n")` is for queries that
+/// alias the notes table.
+macro_rules! visible {
+    () => {
+        "(expires_at IS NULL OR expires_at > NOW())"
+    };
+    ($alias:literal) => {
+        concat!("(", $alias, ".expires_at IS NULL OR ", $alias, ".expires_at > NOW())")
+    };
+}
+pub(crate) use visible;
+
+/// Columns the notes list can be ordered by through `?sort_by=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    CreatedAt,
+    UpdatedAt,
+    Title,
+    DueAt,
+    ViewCount,
+    LastViewedAt,
+}
+
+impl SortField {
+    pub const ALL: [SortField; 6] = [
+        SortField::CreatedAt,
+        SortField::UpdatedAt,
+        SortField::Title,
+        SortField::DueAt,
+        SortField::ViewCount,
+        SortField::LastViewedAt,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+            SortField::Title => "title",
+            SortField::DueAt => "due_at",
+            SortField::ViewCount => "view_count",
+            SortField::LastViewedAt => "last_viewed_at",
+        }
+    }
+
+    /// Due dates read naturally soonest first; everything else newest first.
+    fn default_order(self) -> SortOrder {
+        match self {
+            SortField::DueAt => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, (StatusCode, String)> {
+        Self::ALL.into_iter().find(|field| field.name() == name).ok_or_else(|| {
+            let valid: Vec<&str> = Self::ALL.iter().map(|field| field.name()).collect();
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown sort_by '{}'. Valid values are: {}", name, valid.join(", ")),
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, (StatusCode, String)> {
+        match name {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown order '{}'. Valid values are: asc, desc", name),
+            )),
+        }
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+/// The sort used when a listing request doesn't give one.
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultSort {
+    pub field: SortField,
+    /// `None` uses the field's natural direction.
+    pub order: Option<SortOrder>,
+}
+
+impl DefaultSort {
+    /// Reads `DEFAULT_SORT`, any `sort_by` value (default `created_at`), and
+    /// `DEFAULT_ORDER`, `asc` or `desc` (default the field's natural
+    /// direction). Other values stop startup.
+    pub fn from_env() -> Self {
+        let field = match std::env::var("DEFAULT_SORT") {
+            Ok(name) => SortField::parse(&name).unwrap_or_else(|_| {
+                let valid: Vec<&str> = SortField::ALL.iter().map(|field| field.name()).collect();
+                panic!("DEFAULT_SORT {:?} is not one of: {}", name, valid.join(", "))
+            }),
+            Err(_) => SortField::CreatedAt,
+        };
+        let order = std::env::var("DEFAULT_ORDER").ok().map(|name| {
+            SortOrder::parse(&name)
+                .unwrap_or_else(|_| panic!("DEFAULT_ORDER {:?} is not one of: asc, desc", name))
+        });
+
+        DefaultSort { field, order }
+    }
+}
+
+/// How the client asked for a page, so pagination links can answer in
+/// kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageStyle {
+    /// `?limit=&offset=`.
+    Offset,
+    /// `?page=&per_page=`, with pages counted from 1.
+    Page,
+}
+
+/// Filters, ordering and paging for a notes listing. Every endpoint that
+/// lists notes builds its SQL through this so filters compose everywhere.
+#[derive(Debug, Clone)]
+pub struct NoteQuery {
+    pub due_before: Option<DateTime<Utc>>,
+    pub due_after: Option<DateTime<Utc>>,
+    pub has_due: Option<bool>,
+    pub status: Option<NoteStatus>,
+    /// Only notes whose title contains this, ignoring case and accents.
+    pub title: Option<String>,
+    /// Only notes that have been viewed at least once.
+    pub viewed: bool,
+    pub sort: SortField,
+    pub order: SortOrder,
+    pub limit: i64,
+    pub offset: i64,
+    pub page_style: PageStyle,
+}
+
+impl Default for NoteQuery {
+    fn default() -> Self {
+        NoteQuery {
+            due_before: None,
+            due_after: None,
+            has_due: None,
+            status: None,
+            title: None,
+            viewed: false,
+            sort: SortField::CreatedAt,
+            order: SortOrder::Desc,
+            limit: 10,
+            offset: 0,
+            page_style: PageStyle::Offset,
+        }
+    }
+}
+
+impl NoteQuery {
+    /// Sets the sort column, using its natural direction unless `order` is
+    /// given.
+    pub fn sorted_by(mut self, sort: SortField, order: Option<SortOrder>) -> Self {
+        self.sort = sort;
+        self.order = order.unwrap_or_else(|| sort.default_order());
+        self
+    }
+
+    /// Builds `SELECT <columns> FROM notes WHERE ... ORDER BY ... LIMIT ...`
+    /// over the notes that haven't expired.
+    pub fn build(&self, columns: &str) -> QueryBuilder<'static, Postgres> {
+        let mut builder = self.filtered(columns);
+
+        builder.push(format!(
+            " ORDER BY {} {} NULLS LAST, id",
+            self.sort.name(),
+            self.order.sql()
+        ));
+        builder.push(" LIMIT ").push_bind(self.limit);
+        builder.push(" OFFSET ").push_bind(self.offset);
+
+        builder
+    }
+
+    /// Builds a `SELECT` of the number of notes on every page, `total`, and a
+    /// `fingerprint` of their IDs and versions. The fingerprint is a sum, so
+    /// it changes whenever a note is added to, removed from or edited within
+    /// the set, but not when the order does.
+    pub fn build_totals(&self) -> QueryBuilder<'static, Postgres> {
+        self.filtered("COUNT(*) AS total, COALESCE(SUM(hashtext(id::text || ':' || version)), 0)::BIGINT AS fingerprint")
+    }
+
+    fn filtered(&self, columns: &str) -> QueryBuilder<'static, Postgres> {
+        let mut builder = QueryBuilder::new(format!(
+            concat!("SELECT {} FROM notes WHERE ", visible!()),
+            columns
+        ));
+
+        if let Some(due_before) = self.due_before {
+            builder.push(" AND due_at < ").push_bind(due_before);
+        }
+        if let Some(due_after) = self.due_after {
+            builder.push(" AND due_at > ").push_bind(due_after);
+        }
+        match self.has_due {
+            Some(true) => {
+                builder.push(" AND due_at IS NOT NULL");
+            }
+            Some(false) => {
+                builder.push(" AND due_at IS NULL");
+            }
+            None => {}
+        }
+        if let Some(status) = self.status {
+            builder.push(" AND status = ").push_bind(status);
+        }
+        if let Some(title) = &self.title {
+            let (open, close) = unaccent::fold_parts();
+            builder
+                .push(format!(" AND {} LIKE {}", unaccent::fold("title"), open))
+                .push_bind(format!("%{}%", escape_like(title)))
+                .push(format!("{} ESCAPE '\\'", close));
+        }
+        if self.viewed {
+            builder.push(" AND last_viewed_at IS NOT NULL");
+        }
+
+        builder
+    }
+}
+
+/// Escapes `LIKE` wildcards so `term` matches literally.
+fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use chrono::Duration;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    use crate::{cache::MemoryCache, test_support, test_support::TestApp};
+
+    /// Where a note shows up, from each endpoint that reads notes.
+    #[derive(Debug, PartialEq, Eq)]
+    struct Seen {
+        listed: bool,
+        fetched: bool,
+        toc: bool,
+        related_to_other: bool,
+        linked_from_other: bool,
+        linking_to_other: bool,
+        in_feed: bool,
+        shared: bool,
+    }
+
+    impl Seen {
+        fn everywhere(seen: bool) -> Self {
+            Seen {
+                listed: seen,
+                fetched: seen,
+                toc: seen,
+                related_to_other: seen,
+                linked_from_other: seen,
+                linking_to_other: seen,
+                in_feed: seen,
+                shared: seen,
+            }
+        }
+    }
+
+    fn contains_id(list: &Value, id: &str) -> bool {
+        list.as_array().unwrap().iter().any(|note| note["id"] == id)
+    }
+
+    async fn seen(app: &TestApp, id: &str, other: &str, token: &str) -> Seen {
+        let links = app.get(&format!("/api/v1/notes/{}/links", other)).await.json();
+        Seen {
+            listed: contains_id(&app.get("/api/v1/notes?limit=100").await.json()["notes"], id),
+            fetched: app.get(&format!("/api/v1/notes/{}", id)).await.status.is_success(),
+            toc: app.get(&format!("/api/v1/notes/{}/toc", id)).await.status.is_success(),
+            related_to_other: contains_id(&app.get(&format!("/api/v1/notes/{}/related", other)).await.json(), id),
+            linked_from_other: links.as_array().unwrap().iter().any(|link| link["note_id"] == id),
+            linking_to_other: contains_id(&app.get(&format!("/api/v1/notes/{}/backlinks", other)).await.json(), id),
+            in_feed: app.get("/api/v1/notes/feed.atom").await.text().contains(id),
+            shared: app.get(&format!("/api/v1/shared/{}", token)).await.status.is_success(),
+        }
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn expired_notes_are_hidden_everywhere(pool: sqlx::PgPool) {
+        let mut state = test_support::state(pool).await;
+        // Cached copies must not outlive the note either.
+        state.note_cache = Some(Arc::new(MemoryCache::new(100, std::time::Duration::from_secs(60))));
+        let app = TestApp::with_state(state);
+
+        let expires_at = chrono::Utc::now() + Duration::seconds(2);
+        let note = app
+            .create_note(json!({
+                "title": "Garden watering schedule",
+                "content": "# Tomatoes\n\nWater the garden tomatoes daily, as [[Garden tools]] says.",
+                "status": "published",
+                "expires_at": expires_at,
+            }))
+            .await;
+        let other = app
+            .create_note(json!({
+                "title": "Garden tools",
+                "content": "Watering cans for the garden tomatoes. See [[Garden watering schedule]].",
+                "status": "published",
+            }))
+            .await;
+        let (id, other) = (note["id"].as_str().unwrap(), other["id"].as_str().unwrap());
+        let share = app.send_json(Method::POST, &format!("/api/v1/notes/{}/share", id), json!({})).await;
+        let token = share.json()["token"].as_str().unwrap().to_string();
+
+        assert_eq!(seen(&app, id, other, &token).await, Seen::everywhere(true));
+
+        let remaining = (expires_at - chrono::Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(remaining + std::time::Duration::from_millis(100)).await;
+
+        assert_eq!(seen(&app, id, other, &token).await, Seen::everywhere(false));
+        for path in ["links", "backlinks", "related"] {
+            let response = app.get(&format!("/api/v1/notes/{}/{}", id, path)).await;
+            assert_eq!(response.status, axum::http::StatusCode::NOT_FOUND, "{}", path);
+        }
+    }
+}
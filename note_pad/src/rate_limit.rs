@@ -0,0 +1,106 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::error::AppError;
+
+/// Entries kept before stale windows are pruned from the table.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A fixed-window request counter per key, kept in memory. Keys are client
+/// addresses unless stated otherwise.
+#[derive(Debug)]
+pub struct RateLimiter<K = IpAddr> {
+    limit: u32,
+    window: Duration,
+    clients: Mutex<HashMap<K, (Instant, u32)>>,
+}
+
+/// Returned when a client has used up its window.
+#[derive(Debug)]
+pub struct RateLimited {
+    retry_after: Duration,
+}
+
+impl From<RateLimited> for AppError {
+    fn from(limited: RateLimited) -> Self {
+        AppError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", "Too many requests")
+            .with_retry_after(limited.retry_after.as_secs().max(1))
+    }
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        AppError::from(self).into_response()
+    }
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            limit,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads `<PREFIX>_RATE_LIMIT` requests per `<PREFIX>_RATE_WINDOW_SECS`.
+    pub fn from_env(prefix: &str, default_limit: u32, default_window_secs: u64) -> Self {
+        let limit = std::env::var(format!("{}_RATE_LIMIT", prefix))
+            .ok()
+            .map(|value| value.parse().expect("rate limit must be a number of requests"))
+            .unwrap_or(default_limit);
+        let window_secs = std::env::var(format!("{}_RATE_WINDOW_SECS", prefix))
+            .ok()
+            .map(|value| value.parse().expect("rate window must be a number of seconds"))
+            .unwrap_or(default_window_secs);
+
+        Self::new(limit, Duration::from_secs(window_secs))
+    }
+
+    /// Counts one request from `client`, failing once it's over the limit for
+    /// the current window.
+    pub fn check(&self, client: K) -> Result<(), RateLimited> {
+        self.update(client, true)
+    }
+
+    /// Fails if `client` is over the limit, without counting a request. Pair
+    /// with `check` to count only some outcomes, such as failed attempts.
+    pub fn peek(&self, client: K) -> Result<(), RateLimited> {
+        self.update(client, false)
+    }
+
+    fn update(&self, client: K, count_request: bool) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() >= PRUNE_THRESHOLD {
+            clients.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+        }
+
+        let (started, count) = clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+
+        if *count >= self.limit {
+            return Err(RateLimited {
+                retry_after: self.window - now.duration_since(*started),
+            });
+        }
+
+        if count_request {
+            *count += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use axum::http::{header, HeaderMap, StatusCode};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::error::AppError;
+
+/// Header carrying the title of a note created from a raw text body.
+pub const TITLE_HEADER: &str = "x-note-title";
+
+/// Longest title that fits the `title` column.
+const MAX_TITLE_LENGTH: usize = 255;
+
+const UNTITLED: &str = "Untitled";
+
+/// Turns a create request body into the JSON payload of the JSON route, so
+/// both formats share the same validation. `application/json` bodies are
+/// parsed as they are; `text/markdown` and `text/plain` bodies become the
+/// content, titled by `X-Note-Title` or else the first line.
+pub fn create_payload(headers: &HeaderMap, body: &[u8]) -> Result<serde_json::Value, AppError> {
+    let Some(content) = text_body(headers, body)? else {
+        return json_body(body);
+    };
+    let title = match headers.get(TITLE_HEADER) {
+        Some(value) => String::from_utf8(value.as_bytes().to_vec())
+            .map_err(|_| (StatusCode::BAD_REQUEST, "X-Note-Title must be valid UTF-8".to_string()))?
+            .trim()
+            .to_string(),
+        None => derive_title(content),
+    };
+
+    Ok(serde_json::json!({ "title": title, "content": content }))
+}
+
+/// The same for an append request, where a raw body is the text to append.
+pub fn append_payload(headers: &HeaderMap, body: &[u8]) -> Result<serde_json::Value, AppError> {
+    match text_body(headers, body)? {
+        Some(content) => Ok(serde_json::json!({ "content": content })),
+        None => json_body(body),
+    }
+}
+
+/// The body as text for `text/markdown` and `text/plain`, or `None` for
+/// `application/json`.
+fn text_body<'a>(headers: &HeaderMap, body: &'a [u8]) -> Result<Option<&'a str>, AppError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    match essence.as_str() {
+        "application/json" => Ok(None),
+        "text/markdown" | "text/plain" => std::str::from_utf8(body)
+            .map(Some)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Body must be valid UTF-8".to_string()).into()),
+        _ => Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/json, text/markdown or text/plain".to_string(),
+        )
+            .into()),
+    }
+}
+
+fn json_body(body: &[u8]) -> Result<serde_json::Value, AppError> {
+    serde_json::from_slice(body).map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid JSON body: {}", e)).into())
+}
+
+/// Uses the first non-empty line, without Markdown heading markers, cut to
+/// the column's length.
+fn derive_title(content: &str) -> String {
+    let line = content
+        .lines()
+        .map(|line| line.trim().trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or(UNTITLED);
+
+    line.graphemes(true).take(MAX_TITLE_LENGTH).collect()
+}
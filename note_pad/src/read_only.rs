@@ -0,0 +1,129 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use sqlx::{PgConnection, Row};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{error::AppError, events::NoteEvent, extract::{Json, Path, Query}, hypermedia::{BaseUrl, LinksParams}, passwords, query::visible, AppState, Note};
+
+/// Refuses to change a read-only note, failing with 404 if the note doesn't
+/// exist. Inside a transaction the row stays locked until commit, so the
+/// note can't be made read-only between this check and the write.
+pub async fn ensure_writable(conn: &mut PgConnection, note_id: Uuid) -> Result<(), AppError> {
+    let row = sqlx::query(
+        concat!("SELECT read_only FROM notes
+         WHERE id = $1 AND ", visible!(), "
+         FOR UPDATE"),
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+
+    if row.try_get("read_only")? {
+        return Err(AppError::new(
+            StatusCode::LOCKED,
+            "note_read_only",
+            "This note is read-only; make it writable with DELETE /api/v1/notes/{id}/read-only to change it",
+        ));
+    }
+    Ok(())
+}
+
+/// `POST /api/v1/notes/{id}/read-only`: makes the note read-only, so it
+/// can't be edited or deleted until it's made writable again. Unrelated to
+/// the note's password.
+pub async fn make_read_only(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(link_params): Query<LinksParams>,
+    base: BaseUrl,
+    headers: HeaderMap,
+) -> Result<Json<Note>, AppError> {
+    set_read_only(&state, id, &link_params, &base, &headers, true).await
+}
+
+/// `DELETE /api/v1/notes/{id}/read-only`: makes the note editable again; the
+/// only change a read-only note accepts.
+pub async fn make_writable(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(link_params): Query<LinksParams>,
+    base: BaseUrl,
+    headers: HeaderMap,
+) -> Result<Json<Note>, AppError> {
+    set_read_only(&state, id, &link_params, &base, &headers, false).await
+}
+
+async fn set_read_only(
+    state: &AppState,
+    id: Uuid,
+    link_params: &LinksParams,
+    base: &BaseUrl,
+    headers: &HeaderMap,
+    read_only: bool,
+) -> Result<Json<Note>, AppError> {
+    let mut tx = state.db.begin().await?;
+
+    passwords::unlock_note(state, &mut tx, id, passwords::supplied(headers, None)).await?;
+
+    // Repeating either isn't a change, so it keeps the version.
+    let row = sqlx::query(
+        "UPDATE notes
+         SET read_only = $1,
+             updated_at = CASE WHEN read_only = $1 THEN updated_at ELSE NOW() END,
+             version = CASE WHEN read_only = $1 THEN version ELSE version + 1 END
+         WHERE id = $2
+         RETURNING *",
+    )
+    .bind(read_only)
+    .bind(id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let mut note = Note::from_row(&row)?;
+    note.links = link_params.note_links(base, id);
+
+    state.events.publish(NoteEvent::Updated { note_id: id });
+
+    Ok(Json(note))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::json;
+
+    use crate::test_support::TestApp;
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn read_only_is_set_and_cleared_apart_from_the_password(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let note = app.create_note(json!({"title": "Frozen", "content": "as is"})).await;
+        let uri = format!("/api/v1/notes/{}", note["id"].as_str().unwrap());
+
+        let response = app.send_json(Method::POST, &format!("{uri}/read-only"), json!({})).await;
+        assert_eq!(response.status, 200, "{}", response.text());
+        assert_eq!(response.json()["read_only"], true);
+        assert_eq!(response.json()["locked"], false);
+
+        let response = app.send_json(Method::PUT, &uri, json!({"title": "Thawed", "content": ""})).await;
+        assert_eq!(response.status, 423);
+        assert_eq!(response.json()["error"]["code"], "note_read_only");
+
+        let response = app.send_json(Method::DELETE, &format!("{uri}/read-only"), json!({})).await;
+        assert_eq!(response.status, 200, "{}", response.text());
+        assert_eq!(response.json()["read_only"], false);
+        let response = app.send_json(Method::PUT, &uri, json!({"title": "Thawed", "content": ""})).await;
+        assert_eq!(response.status, 200, "{}", response.text());
+
+        for old in ["lock", "unlock"] {
+            let response = app.send_json(Method::POST, &format!("{uri}/{old}"), json!({})).await;
+            assert_eq!(response.status, 404);
+        }
+    }
+}
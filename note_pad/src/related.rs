@@ -0,0 +1,91 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{attachments::note_exists, error::AppError, extract::{Json, Path, Query}, query::visible, unaccent, AppState};
+
+const DEFAULT_LIMIT: i64 = 5;
+const MAX_LIMIT: i64 = 50;
+
+/// Number of most frequent lexemes of the source note matched against the
+/// other notes.
+const TOP_LEXEMES: i64 = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct RelatedParams {
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelatedNote {
+    pub id: Uuid,
+    pub title: String,
+    pub updated_at: DateTime<Utc>,
+    pub score: f32,
+}
+
+/// Ranks other notes by how much they resemble the given one: trigram
+/// similarity of the titles (when above the `pg_trgm` threshold) plus the
+/// normalized full-text rank of the note's most frequent lexemes against each
+/// candidate. Each part contributes at most 1 to the score.
+pub async fn get_related(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<RelatedParams>,
+) -> Result<Json<Vec<RelatedNote>>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    if !note_exists(&state, id).await? {
+        return Err((StatusCode::NOT_FOUND, "Note not found".to_string()).into());
+    }
+
+    let rows = sqlx::query(
+        concat!("WITH source AS (
+             SELECT id, title, to_tsvector($4::regconfig, title || ' ' || content) AS doc FROM notes
+             WHERE id = $1 AND ", visible!(), "
+         ),
+         terms AS (
+             SELECT to_tsquery($4::regconfig, string_agg(quote_literal(lexeme), ' | ')) AS query
+             FROM (
+                 SELECT t.lexeme FROM source, unnest(source.doc) AS t
+                 ORDER BY coalesce(array_length(t.positions, 1), 1) DESC, t.lexeme
+                 LIMIT $2
+             ) top
+         ),
+         scored AS (
+             SELECT n.id, n.title, n.updated_at,
+                    ((CASE WHEN n.title % source.title THEN similarity(n.title, source.title) ELSE 0 END)
+                     + coalesce(ts_rank_cd(to_tsvector($4::regconfig, n.title || ' ' || n.content), terms.query, 32), 0))::real AS score
+             FROM notes n, source, terms
+             WHERE n.id <> source.id AND ", visible!("n"), "
+         )
+         SELECT id, title, updated_at, score FROM scored
+         WHERE score > 0
+         ORDER BY score DESC, updated_at DESC
+         LIMIT $3"),
+    )
+    .bind(id)
+    .bind(TOP_LEXEMES)
+    .bind(limit)
+    .bind(unaccent::text_search_config())
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut related = Vec::new();
+    for row in rows {
+        related.push(RelatedNote {
+            id: row.try_get("id")?,
+            title: row.try_get("title")?,
+            updated_at: row.try_get("updated_at")?,
+            score: row.try_get("score")?,
+        });
+    }
+
+    Ok(Json(related))
+}
@@ -0,0 +1,170 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::sync::Arc;
+
+use crate::{
+    error::AppError, events::NoteEvent, extract::{Json, Query}, query::{visible, SortField}, AppState, ListNotesParams, NoteSummary, SUMMARY_COLUMNS,
+};
+
+const DEFAULT_WITHIN_HOURS: i64 = 48;
+const MAX_WITHIN_HOURS: i64 = 24 * 366;
+
+#[derive(Debug, Deserialize)]
+pub struct UpcomingParams {
+    within_hours: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OverdueNote {
+    #[serde(flatten)]
+    note: NoteSummary,
+    overdue_by_seconds: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpcomingNote {
+    #[serde(flatten)]
+    note: NoteSummary,
+    due_in_seconds: i64,
+}
+
+/// Notes whose due date has passed, most overdue first.
+pub async fn get_overdue(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ListNotesParams>,
+) -> Result<Json<Vec<OverdueNote>>, AppError> {
+    let now = Utc::now();
+    let mut query = params.to_query(state.default_sort)?.sorted_by(SortField::DueAt, None);
+    query.due_before = Some(query.due_before.map_or(now, |before| before.min(now)));
+
+    let rows = query
+        .build(SUMMARY_COLUMNS)
+        .build()
+        .fetch_all(&state.db)
+        .await?;
+
+    let mut notes = Vec::new();
+    for row in rows {
+        let note = NoteSummary::from_row(&row, params.full_content)?;
+        let overdue_by_seconds = note.due_at.map_or(0, |due_at| (now - due_at).num_seconds());
+        notes.push(OverdueNote { note, overdue_by_seconds });
+    }
+
+    Ok(Json(notes))
+}
+
+/// Notes falling due within the next `within_hours` hours, soonest first.
+pub async fn get_upcoming(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ListNotesParams>,
+    Query(window): Query<UpcomingParams>,
+) -> Result<Json<Vec<UpcomingNote>>, AppError> {
+    let within_hours = window.within_hours.unwrap_or(DEFAULT_WITHIN_HOURS);
+    if !(1..=MAX_WITHIN_HOURS).contains(&within_hours) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("within_hours must be between 1 and {}", MAX_WITHIN_HOURS),
+        )
+            .into());
+    }
+
+    let now = Utc::now();
+    let horizon = now + Duration::hours(within_hours);
+    let mut query = params.to_query(state.default_sort)?.sorted_by(SortField::DueAt, None);
+    query.due_after = Some(query.due_after.map_or(now, |after| after.max(now)));
+    query.due_before = Some(query.due_before.map_or(horizon, |before| before.min(horizon)));
+
+    let rows = query
+        .build(SUMMARY_COLUMNS)
+        .build()
+        .fetch_all(&state.db)
+        .await?;
+
+    let mut notes = Vec::new();
+    for row in rows {
+        let note = NoteSummary::from_row(&row, params.full_content)?;
+        let due_in_seconds = note.due_at.map_or(0, |due_at| (due_at - now).num_seconds());
+        notes.push(UpcomingNote { note, due_in_seconds });
+    }
+
+    Ok(Json(notes))
+}
+
+const DEFAULT_TICK_SECS: u64 = 60;
+
+/// Notes claimed per round trip while firing reminders.
+const CLAIM_BATCH_SIZE: i64 = 100;
+
+/// How often the reminder scheduler looks for notes that came due, from
+/// `REMINDER_TICK_SECS` (default 60).
+pub fn tick_from_env() -> std::time::Duration {
+    let secs = std::env::var("REMINDER_TICK_SECS")
+        .ok()
+        .map(|value| value.parse().expect("REMINDER_TICK_SECS must be a number of seconds"))
+        .unwrap_or(DEFAULT_TICK_SECS);
+
+    std::time::Duration::from_secs(secs)
+}
+
+/// Marks every due, not yet reminded note as reminded and publishes a
+/// `note.due` event for each, returning how many fired.
+///
+/// Selection is by `reminded_at IS NULL` rather than by the time since the
+/// previous tick, so after downtime every reminder that was missed fires
+/// exactly once on the first tick. Rows are claimed with `SKIP LOCKED`, so
+/// concurrent instances never fire the same reminder twice.
+pub async fn fire_due_reminders(state: &AppState) -> Result<usize, sqlx::Error> {
+    let mut fired = 0;
+
+    loop {
+        let rows = sqlx::query(
+            concat!("UPDATE notes SET reminded_at = NOW()
+             WHERE id IN (
+                 SELECT id FROM notes
+                 WHERE reminded_at IS NULL AND due_at <= NOW()
+                   AND ", visible!(), "
+                 ORDER BY due_at
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, title, due_at"),
+        )
+        .bind(CLAIM_BATCH_SIZE)
+        .fetch_all(&state.db)
+        .await?;
+
+        let claimed = rows.len();
+        for row in rows {
+            state.events.publish(NoteEvent::Due {
+                note_id: row.try_get("id")?,
+                title: row.try_get("title")?,
+                due_at: row.try_get("due_at")?,
+            });
+        }
+        fired += claimed;
+
+        if (claimed as i64) < CLAIM_BATCH_SIZE {
+            return Ok(fired);
+        }
+    }
+}
+
+pub fn spawn_reminder_scheduler(state: Arc<AppState>, tick: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tick);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match fire_due_reminders(&state).await {
+                Ok(0) => {}
+                Ok(fired) => tracing::info!(fired, "fired due note reminders"),
+                Err(e) => tracing::error!(error = %e, "failed to fire due note reminders"),
+            }
+        }
+    });
+}
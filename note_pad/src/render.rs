@@ -0,0 +1,236 @@
+use pulldown_cmark::{html, CowStr, Event, Parser, Tag, TagEnd};
+use std::collections::HashMap;
+
+/// URL schemes links and images may use in rendered output.
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// Renders Markdown to HTML that is safe to show to people other than the
+/// author: raw HTML is shown as text and links with script-capable schemes
+/// such as `javascript:` are neutralised. Headings get the anchors
+/// `headings` reports as their `id`.
+pub fn markdown_to_html(content: &str) -> String {
+    let mut events = safe_events(content);
+    anchor_headings(&mut events);
+
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, events.into_iter());
+    rendered
+}
+
+/// A Markdown heading, as rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    pub level: u8,
+    pub text: String,
+    pub anchor: String,
+}
+
+/// The headings of `content` in document order, with the same anchors
+/// `markdown_to_html` gives them.
+pub fn headings(content: &str) -> Vec<Heading> {
+    anchor_headings(&mut safe_events(content))
+}
+
+fn safe_events(content: &str) -> Vec<Event<'_>> {
+    Parser::new(content)
+        .map(|event| match event {
+            Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => Event::Start(Tag::Link {
+                link_type,
+                dest_url: safe_url(dest_url),
+                title,
+                id,
+            }),
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => Event::Start(Tag::Image {
+                link_type,
+                dest_url: safe_url(dest_url),
+                title,
+                id,
+            }),
+            other => other,
+        })
+        .collect()
+}
+
+/// Sets the `id` of every heading in `events` to its anchor and returns
+/// the headings.
+fn anchor_headings(events: &mut [Event<'_>]) -> Vec<Heading> {
+    let mut anchors = Anchors::default();
+    let mut headings = Vec::new();
+    let mut start = None;
+    let mut text = String::new();
+    for i in 0..events.len() {
+        match &events[i] {
+            Event::Start(Tag::Heading { .. }) => {
+                start = Some(i);
+                text.clear();
+            }
+            Event::Text(part) | Event::Code(part) if start.is_some() => text.push_str(part),
+            Event::SoftBreak if start.is_some() => text.push(' '),
+            Event::End(TagEnd::Heading(level)) => {
+                let level = *level as u8;
+                if let Some(Event::Start(Tag::Heading { id, .. })) = start.take().map(|start| &mut events[start]) {
+                    let anchor = anchors.next(&text);
+                    *id = Some(CowStr::from(anchor.clone()));
+                    headings.push(Heading {
+                        level,
+                        text: text.trim().to_string(),
+                        anchor,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// Hands out heading anchors unique within one document, the way GitHub
+/// does: a repeated slug gets `-1`, `-2` and so on, skipping any already
+/// taken.
+#[derive(Debug, Default)]
+pub struct Anchors {
+    taken: HashMap<String, usize>,
+}
+
+impl Anchors {
+    pub fn next(&mut self, text: &str) -> String {
+        let base = slug(text);
+        let mut anchor = base.clone();
+        while self.taken.contains_key(&anchor) {
+            let count = self.taken.entry(base.clone()).or_insert(0);
+            *count += 1;
+            anchor = format!("{}-{}", base, count);
+        }
+        self.taken.insert(anchor.clone(), 0);
+        anchor
+    }
+}
+
+/// GitHub's slug for a heading: lowercased, with spaces turned into `-`
+/// and everything but letters, digits, `-` and `_` dropped, emoji and
+/// punctuation included. Headings that leave nothing become `section`.
+pub fn slug(text: &str) -> String {
+    let slug: String = text
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect();
+    if slug.is_empty() { "section".to_string() } else { slug }
+}
+
+/// Relative URLs and safe schemes pass through; anything else becomes `#`.
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+
+    match scheme {
+        Some(scheme) if !SAFE_SCHEMES.iter().any(|safe| safe.eq_ignore_ascii_case(scheme.trim())) => CowStr::Borrowed("#"),
+        _ => url,
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anchors(content: &str) -> Vec<String> {
+        headings(content).into_iter().map(|heading| heading.anchor).collect()
+    }
+
+    #[test]
+    fn slugs_drop_punctuation_and_emoji() {
+        assert_eq!(slug("Hello, World!"), "hello-world");
+        assert_eq!(slug("  What's new in v2.0?  "), "whats-new-in-v20");
+        assert_eq!(slug("snake_case and kebab-case"), "snake_case-and-kebab-case");
+        assert_eq!(slug("🎉 Party time 🎉"), "-party-time-");
+        assert_eq!(slug("A  B"), "a--b");
+    }
+
+    #[test]
+    fn slugs_keep_letters_of_any_script() {
+        assert_eq!(slug("Привет, мир"), "привет-мир");
+        assert_eq!(slug("日本語の見出し"), "日本語の見出し");
+        assert_eq!(slug("Ünïcödé Straße"), "ünïcödé-straße");
+        assert_eq!(slug("مرحبا بالعالم"), "مرحبا-بالعالم");
+    }
+
+    #[test]
+    fn headings_that_leave_nothing_become_section() {
+        assert_eq!(slug("!!!"), "section");
+        assert_eq!(slug("🎉"), "section");
+        assert_eq!(slug("   "), "section");
+        assert_eq!(anchors("# ???\n\n# ...\n"), ["section", "section-1"]);
+    }
+
+    #[test]
+    fn repeated_headings_are_numbered() {
+        assert_eq!(anchors("# Intro\n\n## Intro\n\n### Intro\n"), ["intro", "intro-1", "intro-2"]);
+    }
+
+    #[test]
+    fn numbering_skips_anchors_already_taken() {
+        assert_eq!(anchors("# Intro\n\n# Intro-1\n\n# Intro\n"), ["intro", "intro-1", "intro-2"]);
+        assert_eq!(anchors("# Intro\n\n# Intro\n\n# Intro-1\n"), ["intro", "intro-1", "intro-1-1"]);
+    }
+
+    #[test]
+    fn headings_are_read_as_rendered() {
+        let headings = headings("# Using `cargo`\nand more\n\n## <b>Bold</b> move\n");
+        assert_eq!(
+            headings,
+            [
+                Heading {
+                    level: 1,
+                    text: "Using cargo".to_string(),
+                    anchor: "using-cargo".to_string(),
+                },
+                Heading {
+                    level: 2,
+                    text: "<b>Bold</b> move".to_string(),
+                    anchor: "bboldb-move".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rendered_headings_carry_their_anchors() {
+        let html = markdown_to_html("# Intro\n\n# Intro\n");
+        assert!(html.contains(r#"<h1 id="intro">Intro</h1>"#), "{}", html);
+        assert!(html.contains(r#"<h1 id="intro-1">Intro</h1>"#), "{}", html);
+    }
+}
@@ -0,0 +1,127 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+/// Header carrying the request ID, in both directions.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request ID that is kept; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The request being handled, for code that has no access to it, such as
+/// the panic handler.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub id: String,
+    pub route: Option<String>,
+}
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+}
+
+/// The context of the request handled by the current task, if any.
+pub fn current() -> Option<RequestContext> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+fn valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Keeps the caller's `X-Request-Id` when it looks sane, or assigns a new
+/// one, and echoes it on the response. The handler runs with the ID in
+/// scope for `current`.
+pub async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+
+    let header = HeaderValue::from_str(&id).expect("request IDs are visible ASCII");
+    request.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+
+    let mut response = CURRENT.scope(RequestContext { id, route }, next.run(request)).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, header);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode};
+
+    use super::*;
+    use crate::test_support::TestApp;
+
+    fn with_id(uri: &str, id: &str) -> Request {
+        Request::get(uri).header(REQUEST_ID_HEADER, id).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn only_short_visible_ascii_ids_are_kept() {
+        assert!(valid("req-42"));
+        assert!(valid(&"a".repeat(MAX_REQUEST_ID_LEN)));
+        assert!(!valid(""));
+        assert!(!valid(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+        assert!(!valid("has space"));
+        assert!(!valid("tab\there"));
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn echoes_an_incoming_request_id(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let response = app.request(with_id("/api/v1/notes", "client-chosen-123")).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.header(REQUEST_ID_HEADER), Some("client-chosen-123"));
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn generates_an_id_when_none_or_an_unusable_one_is_sent(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let first = app.get("/api/v1/notes").await;
+        let second = app.get("/api/v1/notes").await;
+        let first = first.header(REQUEST_ID_HEADER).unwrap();
+        assert!(Uuid::parse_str(first).is_ok(), "{}", first);
+        assert_ne!(Some(first), second.header(REQUEST_ID_HEADER));
+
+        let too_long = app.request(with_id("/api/v1/notes", &"x".repeat(MAX_REQUEST_ID_LEN + 1))).await;
+        assert!(Uuid::parse_str(too_long.header(REQUEST_ID_HEADER).unwrap()).is_ok());
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn error_bodies_carry_the_same_id(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        for uri in [
+            "/no/such/route",
+            "/api/v1/notes/00000000-0000-0000-0000-000000000000",
+            "/api/v1/notes?sort_by=bogus",
+            "/api/v1/notes?limit=abc",
+            "/api/v1/notes/not-a-uuid",
+            "/api/v1/debug/panic",
+        ] {
+            let response = app.request(with_id(uri, "trace-me")).await;
+            assert!(response.status.is_client_error() || response.status.is_server_error(), "{}", uri);
+            assert_eq!(response.header(REQUEST_ID_HEADER), Some("trace-me"), "{}", uri);
+            assert_eq!(response.json()["error"]["request_id"], "trace-me", "{}", uri);
+
+            let generated = app.get(uri).await;
+            assert_eq!(
+                generated.json()["error"]["request_id"].as_str(),
+                generated.header(REQUEST_ID_HEADER),
+                "{}",
+                uri
+            );
+        }
+    }
+}
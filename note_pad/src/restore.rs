@@ -0,0 +1,565 @@
+use axum::{
+    extract::{FromRequest, Multipart, Request, State},
+    http::{header, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Postgres, Row, Transaction};
+use std::{collections::HashSet, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    admin::Admin, attachments, backup, duplicates, error::AppError, events::NoteEvent, extract::{Json, Query}, statement_timeout, AppState,
+};
+
+/// Largest backup accepted as an upload. Files in `BACKUP_DIR` aren't
+/// limited.
+pub const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreMode {
+    /// Adds the backup's rows and overwrites those with the same key, but
+    /// never deletes anything.
+    #[default]
+    Merge,
+    /// Also deletes every row the backup doesn't have, leaving exactly the
+    /// backup behind.
+    Replace,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreParams {
+    #[serde(default)]
+    mode: RestoreMode,
+    /// Required to run a `replace` that isn't a dry run.
+    #[serde(default)]
+    confirm: bool,
+    /// Works out the changes and rolls them back.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreFile {
+    filename: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RowCounts {
+    created: u64,
+    updated: u64,
+    deleted: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    mode: RestoreMode,
+    dry_run: bool,
+    schema_version: i64,
+    backup_created_at: serde_json::Value,
+    notes: RowCounts,
+    note_items: RowCounts,
+    note_links: RowCounts,
+}
+
+/// A backup table, with its key and the column naming the note a row
+/// belongs to.
+struct Table {
+    name: &'static str,
+    key: &'static str,
+    note_column: &'static str,
+    /// A `SELECT` of the backup's rows, `$1` being the backup. Rows must
+    /// come out with every column of the table.
+    rows: &'static str,
+    /// Columns computed from the others, which are recomputed rather than
+    /// taken from the backup.
+    derived: &'static [&'static str],
+}
+
+const NOTES: Table = Table {
+    name: "notes",
+    key: "id",
+    note_column: "id",
+    rows: "SELECT * FROM jsonb_populate_recordset(NULL::notes, $1->'notes')",
+    derived: &["content_hash"],
+};
+
+/// Items of notes missing from the database and the backup are skipped.
+const NOTE_ITEMS: Table = Table {
+    name: "note_items",
+    key: "id",
+    note_column: "note_id",
+    rows: "SELECT i.* FROM jsonb_populate_recordset(NULL::note_items, $1->'note_items') i
+           WHERE EXISTS (SELECT 1 FROM notes WHERE notes.id = i.note_id)",
+    derived: &[],
+};
+
+/// A link whose target note is gone is restored unresolved, just as
+/// deleting the target leaves it.
+const NOTE_LINKS: Table = Table {
+    name: "note_links",
+    key: "source_id, target_title",
+    note_column: "source_id",
+    rows: "SELECT l.source_id, l.target_title, t.id AS target_id
+           FROM jsonb_populate_recordset(NULL::note_links, $1->'note_links') l
+           JOIN notes s ON s.id = l.source_id
+           LEFT JOIN notes t ON t.id = l.target_id",
+    derived: &[],
+};
+
+/// Restores a backup, either uploaded as the only file of a multipart body
+/// or named by `{"filename": ...}` from those in `BACKUP_DIR`. The
+/// `schema_version` is checked before anything else happens, and the
+/// whole restore is one transaction, so it either applies fully or not at
+/// all. A backup with fields this server has no column for, as one from a
+/// newer server can have, is refused rather than restored without them.
+///
+/// `?mode=replace` deletes what the backup doesn't have, attachments of
+/// deleted notes included, and is refused without `confirm=true`.
+/// `?dry_run=true` reports the same counts without keeping any change. Rows
+/// that are already as the backup has them aren't counted as updated.
+pub async fn restore_backup(
+    _: Admin,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RestoreParams>,
+    request: Request,
+) -> Result<Json<RestoreReport>, AppError> {
+    if matches!(params.mode, RestoreMode::Replace) && !params.confirm && !params.dry_run {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "confirmation_required",
+            "A replace restore deletes every note missing from the backup; add confirm=true to go ahead",
+        )
+        .with_details(json!({ "field": "confirm" })));
+    }
+
+    let bytes = read_backup(&state, request).await?;
+    let backup: serde_json::Value = serde_json::from_slice(&bytes)
+        .map_err(|e| AppError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_backup", e.to_string()))?;
+
+    let schema_version = backup["schema_version"].as_i64();
+    if schema_version != Some(backup::SCHEMA_VERSION as i64) {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unsupported_schema_version",
+            format!("Only backups with schema version {} can be restored", backup::SCHEMA_VERSION),
+        )
+        .with_details(json!({
+            "schema_version": backup["schema_version"],
+            "supported": [backup::SCHEMA_VERSION],
+        })));
+    }
+    for table in [NOTES.name, NOTE_ITEMS.name, NOTE_LINKS.name] {
+        if !backup[table].is_array() {
+            return Err(AppError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_backup",
+                format!("The backup has no {} list", table),
+            )
+            .with_details(json!({ "field": table })));
+        }
+    }
+
+    let mut tx = state.db.begin().await?;
+    statement_timeout::extend(&mut tx, state.timeouts.bulk).await?;
+    for table in [&NOTES, &NOTE_ITEMS, &NOTE_LINKS] {
+        check_fields(&mut tx, table, &backup).await?;
+    }
+    check_content_sizes(&mut tx, &backup, state.max_content_bytes).await?;
+    let mut changed = HashSet::new();
+    let mut deleted_notes = Vec::new();
+    let mut attachment_ids = Vec::new();
+    let mut report = RestoreReport {
+        mode: params.mode,
+        dry_run: params.dry_run,
+        schema_version: backup::SCHEMA_VERSION as i64,
+        backup_created_at: backup["created_at"].clone(),
+        notes: RowCounts::default(),
+        note_items: RowCounts::default(),
+        note_links: RowCounts::default(),
+    };
+
+    if matches!(params.mode, RestoreMode::Replace) {
+        // Children first, so their counts aren't hidden by the cascade.
+        report.note_links.deleted = delete_missing(
+            &mut tx,
+            "DELETE FROM note_links WHERE NOT EXISTS (
+                 SELECT 1 FROM jsonb_populate_recordset(NULL::note_links, $1->'note_links') l
+                 WHERE l.source_id = note_links.source_id AND l.target_title = note_links.target_title)
+             RETURNING source_id AS note_id",
+            &backup,
+            &mut changed,
+        )
+        .await?;
+        report.note_items.deleted = delete_missing(
+            &mut tx,
+            "DELETE FROM note_items WHERE NOT EXISTS (
+                 SELECT 1 FROM jsonb_populate_recordset(NULL::note_items, $1->'note_items') i
+                 WHERE i.id = note_items.id)
+             RETURNING note_id",
+            &backup,
+            &mut changed,
+        )
+        .await?;
+
+        let missing = "NOT EXISTS (
+            SELECT 1 FROM jsonb_populate_recordset(NULL::notes, $1->'notes') n WHERE n.id = notes.id)";
+        attachment_ids = sqlx::query_scalar(&format!(
+            "DELETE FROM attachments WHERE note_id IN (SELECT id FROM notes WHERE {}) RETURNING id",
+            missing
+        ))
+        .bind(&backup)
+        .fetch_all(&mut tx)
+        .await
+        .map_err(invalid_backup)?;
+        deleted_notes = sqlx::query_scalar(&format!("DELETE FROM notes WHERE {} RETURNING id", missing))
+            .bind(&backup)
+            .fetch_all(&mut tx)
+            .await
+            .map_err(invalid_backup)?;
+        report.notes.deleted = deleted_notes.len() as u64;
+    }
+
+    for (table, counts) in [
+        (&NOTES, &mut report.notes),
+        (&NOTE_ITEMS, &mut report.note_items),
+        (&NOTE_LINKS, &mut report.note_links),
+    ] {
+        let (created, updated) = upsert(&mut tx, table, &backup, &mut changed).await?;
+        counts.created = created;
+        counts.updated = updated;
+    }
+
+    // A merge can leave a note with its current items and the backup's
+    // sharing positions, so positions are renumbered in their order.
+    let changed: Vec<Uuid> = changed.into_iter().collect();
+    sqlx::query(
+        "UPDATE note_items SET position = r.position
+         FROM (SELECT id, ROW_NUMBER() OVER (PARTITION BY note_id ORDER BY position, created_at, id) - 1 AS position
+               FROM note_items WHERE note_id = ANY($1)) r
+         WHERE note_items.id = r.id AND note_items.position <> r.position",
+    )
+    .bind(&changed)
+    .execute(&mut tx)
+    .await?;
+    duplicates::rehash(&mut tx, &changed).await?;
+
+    if params.dry_run {
+        tx.rollback().await?;
+        return Ok(Json(report));
+    }
+    tx.commit().await.map_err(invalid_backup)?;
+
+    for note_id in deleted_notes.iter().copied() {
+        state.events.publish(NoteEvent::Deleted { note_id });
+    }
+    for note_id in changed.into_iter().filter(|id| !deleted_notes.contains(id)) {
+        state.events.publish(NoteEvent::Updated { note_id });
+    }
+    attachments::remove_files(&state.attachments, &attachment_ids).await;
+
+    tracing::info!(
+        mode = ?report.mode,
+        notes_created = report.notes.created,
+        notes_updated = report.notes.updated,
+        notes_deleted = report.notes.deleted,
+        "restored backup"
+    );
+    Ok(Json(report))
+}
+
+/// The backup named by a JSON body, or the uploaded one.
+async fn read_backup(state: &Arc<AppState>, request: Request) -> Result<Vec<u8>, AppError> {
+    let is_multipart = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("multipart/form-data"));
+
+    if is_multipart {
+        let mut multipart = Multipart::from_request(request, state)
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.body_text()))?;
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+        {
+            if field.file_name().is_some() {
+                let bytes = field.bytes().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+                return Ok(bytes.to_vec());
+            }
+        }
+        return Err(AppError::new(StatusCode::BAD_REQUEST, "backup_missing", "No backup file was uploaded"));
+    }
+
+    let Json(RestoreFile { filename }) = Json::<RestoreFile>::from_request(request, state).await?;
+    let config = state.backups.as_ref().ok_or_else(|| {
+        AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "backups_disabled",
+            "Backups are not configured on this server",
+        )
+    })?;
+    // Only names from the listing are read, so the name can't reach
+    // outside BACKUP_DIR.
+    let files = backup::list_files(config)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !files.iter().any(|file| file.name == filename) {
+        return Err(
+            AppError::new(StatusCode::NOT_FOUND, "backup_not_found", format!("No backup named {:?}", filename))
+                .with_details(json!({ "field": "filename" })),
+        );
+    }
+    tokio::fs::read(config.dir.join(&filename))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
+}
+
+/// Refuses rows of `table` with fields that aren't its columns, which
+/// `jsonb_populate_recordset` would otherwise drop without a word.
+async fn check_fields(
+    tx: &mut Transaction<'_, Postgres>,
+    table: &Table,
+    backup: &serde_json::Value,
+) -> Result<(), AppError> {
+    let unknown: Vec<String> = sqlx::query_scalar(
+        "SELECT DISTINCT field FROM jsonb_array_elements($1->$2) AS r(row), jsonb_object_keys(r.row) AS field
+         WHERE field NOT IN (
+             SELECT column_name::text FROM information_schema.columns
+             WHERE table_schema = current_schema() AND table_name = $2
+         )
+         ORDER BY field",
+    )
+    .bind(backup)
+    .bind(table.name)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(invalid_backup)?;
+
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "schema_mismatch",
+        format!(
+            "The backup's {} have fields this server doesn't know: {}. It was likely written by a newer version",
+            table.name,
+            unknown.join(", ")
+        ),
+    )
+    .with_details(json!({
+        "schema_version": backup["schema_version"],
+        "table": table.name,
+        "unknown_fields": unknown,
+    })))
+}
+
+/// Refuses a backup with a note over `MAX_CONTENT_BYTES`, naming the
+/// largest. Encrypted content is measured without its 16 byte tag.
+async fn check_content_sizes(
+    tx: &mut Transaction<'_, Postgres>,
+    backup: &serde_json::Value,
+    max_bytes: usize,
+) -> Result<(), AppError> {
+    let largest = sqlx::query(
+        "SELECT id, COALESCE(octet_length(content_ciphertext) - 16, octet_length(content))::bigint AS size
+         FROM jsonb_populate_recordset(NULL::notes, $1->'notes')
+         WHERE COALESCE(octet_length(content_ciphertext) - 16, octet_length(content)) > $2
+         ORDER BY size DESC
+         LIMIT 1",
+    )
+    .bind(backup)
+    .bind(max_bytes as i64)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(invalid_backup)?;
+
+    let Some(row) = largest else {
+        return Ok(());
+    };
+    let id: Uuid = row.try_get("id")?;
+    let size: i64 = row.try_get("size")?;
+    Err(AppError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "content_too_large",
+        format!(
+            "Note {} in the backup has {} bytes of content, over the limit of {} bytes",
+            id, size, max_bytes
+        ),
+    )
+    .with_details(json!({
+        "note_id": id,
+        "field": "content",
+        "limit": max_bytes,
+        "size": size,
+    })))
+}
+
+async fn delete_missing(
+    tx: &mut Transaction<'_, Postgres>,
+    sql: &str,
+    backup: &serde_json::Value,
+    changed: &mut HashSet<Uuid>,
+) -> Result<u64, AppError> {
+    let note_ids: Vec<Uuid> = sqlx::query_scalar(sql)
+        .bind(backup)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(invalid_backup)?;
+    let deleted = note_ids.len() as u64;
+    changed.extend(note_ids);
+    Ok(deleted)
+}
+
+/// Inserts the backup's rows of `table`, overwriting those with the same key
+/// unless they already match. Columns are read from the database, so ones
+/// added later are restored too. Returns the rows created and updated.
+async fn upsert(
+    tx: &mut Transaction<'_, Postgres>,
+    table: &Table,
+    backup: &serde_json::Value,
+    changed: &mut HashSet<Uuid>,
+) -> Result<(u64, u64), AppError> {
+    let columns: Vec<String> = sqlx::query_scalar(
+        "SELECT column_name::text FROM information_schema.columns
+         WHERE table_schema = current_schema() AND table_name = $1
+         ORDER BY ordinal_position",
+    )
+    .bind(table.name)
+    .fetch_all(&mut *tx)
+    .await?;
+    let columns: Vec<String> = columns
+        .into_iter()
+        .filter(|column| !table.derived.contains(&column.as_str()))
+        .collect();
+    let list = columns.join(", ");
+    let qualified = |prefix: &str| {
+        columns
+            .iter()
+            .map(|column| format!("{}.{}", prefix, column))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let current = qualified(table.name);
+    let excluded = qualified("EXCLUDED");
+
+    // xmax is only zero on a row the statement just inserted.
+    let sql = format!(
+        "INSERT INTO {table} ({list}) SELECT {list} FROM ({rows}) AS r
+         ON CONFLICT ({key}) DO UPDATE SET ({list}) = ROW({excluded})
+         WHERE ROW({current}) IS DISTINCT FROM ROW({excluded})
+         RETURNING {note_column} AS note_id, xmax::text = '0' AS created",
+        table = table.name,
+        list = list,
+        rows = table.rows,
+        key = table.key,
+        current = current,
+        excluded = excluded,
+        note_column = table.note_column,
+    );
+    let rows = sqlx::query(&sql)
+        .bind(backup)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(invalid_backup)?;
+
+    let mut created = 0;
+    for row in &rows {
+        changed.insert(row.try_get("note_id")?);
+        if row.try_get("created")? {
+            created += 1;
+        }
+    }
+    Ok((created, rows.len() as u64 - created))
+}
+
+/// Constraint and type errors come from the backup's rows, not the server.
+fn invalid_backup(error: sqlx::Error) -> AppError {
+    match error {
+        sqlx::Error::Database(e) => AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_backup",
+            format!("The backup can't be restored: {}", e.message()),
+        ),
+        e => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::test_support::TestApp;
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn an_export_restores_exactly_into_an_empty_database(pool: sqlx::PgPool) {
+        let app = TestApp::with_admin(pool.clone()).await;
+        let alpha = app
+            .create_note(json!({ "title": "Alpha", "content": "See [[Beta]]" }))
+            .await;
+        app.create_note(json!({ "title": "Beta", "content": "The other one" })).await;
+        let item = app
+            .send_json(
+                Method::POST,
+                &format!("/api/v1/notes/{}/items", alpha["id"].as_str().unwrap()),
+                json!({ "text": "Follow up" }),
+            )
+            .await;
+        assert!(item.status.is_success(), "{}", item.text());
+
+        let before = app.export().await;
+        assert_eq!(before["notes"].as_array().unwrap().len(), 2);
+        assert_eq!(before["note_items"].as_array().unwrap().len(), 1);
+        assert_eq!(before["note_links"].as_array().unwrap().len(), 1);
+
+        sqlx::query("DELETE FROM note_links").execute(&pool).await.unwrap();
+        sqlx::query("DELETE FROM note_items").execute(&pool).await.unwrap();
+        sqlx::query("DELETE FROM notes").execute(&pool).await.unwrap();
+
+        let response = app.restore(&before).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        let report = response.json();
+        assert_eq!(report["mode"], "merge");
+        assert_eq!(report["notes"], json!({ "created": 2, "updated": 0, "deleted": 0 }));
+        assert_eq!(report["note_items"], json!({ "created": 1, "updated": 0, "deleted": 0 }));
+        assert_eq!(report["note_links"], json!({ "created": 1, "updated": 0, "deleted": 0 }));
+
+        let after = app.export().await;
+        for table in ["notes", "note_items", "note_links"] {
+            assert_eq!(after[table], before[table], "{} differ after the round trip", table);
+        }
+
+        // Restoring the same backup again changes nothing.
+        let report = app.restore(&before).await.json();
+        assert_eq!(report["notes"], json!({ "created": 0, "updated": 0, "deleted": 0 }));
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn unknown_columns_are_refused_without_changing_anything(pool: sqlx::PgPool) {
+        let app = TestApp::with_admin(pool.clone()).await;
+        app.create_note(json!({ "title": "Alpha", "content": "Kept as it is" })).await;
+
+        let mut backup = app.export().await;
+        let note = &mut backup["notes"][0];
+        note["title"] = json!("Renamed by the backup");
+        note["colour"] = json!("red");
+        let mut extra = note.clone();
+        extra["id"] = json!(Uuid::new_v4());
+        backup["notes"].as_array_mut().unwrap().push(extra);
+
+        let response = app.restore(&backup).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        let error = &response.json()["error"];
+        assert_eq!(error["code"], "schema_mismatch");
+        assert_eq!(error["details"]["table"], "notes");
+        assert_eq!(error["details"]["unknown_fields"], json!(["colour"]));
+
+        let titles: Vec<String> = sqlx::query_scalar("SELECT title FROM notes").fetch_all(&pool).await.unwrap();
+        assert_eq!(titles, ["Alpha"]);
+    }
+}
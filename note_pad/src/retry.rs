@@ -0,0 +1,85 @@
+use rand::Rng;
+use std::{future::Future, time::Duration};
+
+use crate::{metrics, telemetry::RowCount};
+
+const DEFAULT_RETRIES: u32 = 2;
+
+const DEFAULT_BASE_DELAY_MS: u64 = 50;
+
+/// Retries for read-only queries that fail because the connection did, as
+/// happens while Postgres fails over. Writes never go through this: a write
+/// whose connection dropped may still have been applied.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadRetry {
+    retries: u32,
+    base_delay: Duration,
+}
+
+impl ReadRetry {
+    /// Reads `DB_READ_RETRIES` (default 2) and `DB_READ_RETRY_DELAY_MS`, the
+    /// delay before the first retry (default 50), which doubles after each.
+    pub fn from_env() -> Self {
+        let retries = std::env::var("DB_READ_RETRIES")
+            .ok()
+            .map(|value| value.parse().expect("DB_READ_RETRIES must be a number"))
+            .unwrap_or(DEFAULT_RETRIES);
+        let base_delay_ms = std::env::var("DB_READ_RETRY_DELAY_MS")
+            .ok()
+            .map(|value| value.parse().expect("DB_READ_RETRY_DELAY_MS must be a number of milliseconds"))
+            .unwrap_or(DEFAULT_BASE_DELAY_MS);
+
+        ReadRetry {
+            retries,
+            base_delay: Duration::from_millis(base_delay_ms),
+        }
+    }
+
+    /// Runs `query`, running it again after a jittered backoff while it fails
+    /// with a transient error and retries are left. Each attempt is timed
+    /// under `operation`.
+    pub async fn run<T, F, Fut>(&self, operation: &'static str, mut query: F) -> Result<T, sqlx::Error>
+    where
+        T: RowCount,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match metrics::timed(operation, query()).await {
+                Ok(value) => {
+                    if attempt > 0 {
+                        tracing::info!(operation, retries = attempt, "database read succeeded after retrying");
+                    }
+                    return Ok(value);
+                }
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    attempt += 1;
+                    let delay = self.base_delay * 2u32.pow(attempt - 1);
+                    let delay = delay + delay.mul_f64(rand::thread_rng().gen_range(0.0..1.0));
+                    tracing::warn!(operation, attempt, error = %e, ?delay, "retrying database read");
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    if attempt > 0 {
+                        tracing::warn!(operation, retries = attempt, error = %e, "database read failed after retrying");
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Whether an error means the connection failed rather than the query:
+/// I/O errors, pool timeouts, and Postgres connection failures (class 08)
+/// or shutdowns (57P01 to 57P03).
+pub fn is_transient(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(e) => e
+            .code()
+            .is_some_and(|code| code.starts_with("08") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03")),
+        _ => false,
+    }
+}
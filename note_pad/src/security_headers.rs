@@ -0,0 +1,188 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::AppState;
+
+/// For rendered notes: no scripts, frames or forms, inline styles only, and
+/// images from anywhere since notes link to them.
+const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src http: https: data:; \
+     style-src 'unsafe-inline'; base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
+
+const STRICT_TRANSPORT_SECURITY: &str = "max-age=31536000";
+
+/// The policy for HTML responses, from `CONTENT_SECURITY_POLICY` or the
+/// default. A route that needs more, such as an API explorer loading
+/// scripts, sets its own `Content-Security-Policy` header, which is kept.
+pub fn content_security_policy_from_env() -> HeaderValue {
+    let policy =
+        std::env::var("CONTENT_SECURITY_POLICY").unwrap_or_else(|_| DEFAULT_CONTENT_SECURITY_POLICY.to_string());
+    HeaderValue::from_str(&policy).expect("CONTENT_SECURITY_POLICY must be a valid header value")
+}
+
+/// Adds the usual hardening headers to every response, including errors,
+/// without replacing any a handler set itself: `nosniff`, no framing, no
+/// referrer, the content security policy on HTML, and HSTS for requests
+/// that a trusted proxy says came over HTTPS. The server itself doesn't
+/// terminate TLS.
+pub async fn set_security_headers(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let https = peer.is_some_and(|peer| state.trusted_proxies.trusts(peer)) && forwarded_https(request.headers());
+
+    let mut response = next.run(request).await;
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+
+    let headers = response.headers_mut();
+    let mut set = |name, value| {
+        headers.entry(name).or_insert(value);
+    };
+    set(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    set(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    set(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+    if is_html {
+        set(header::CONTENT_SECURITY_POLICY, state.content_security_policy.clone());
+    }
+    if https {
+        set(header::STRICT_TRANSPORT_SECURITY, HeaderValue::from_static(STRICT_TRANSPORT_SECURITY));
+    }
+
+    response
+}
+
+/// Whether the client reached the first proxy over HTTPS, by the first
+/// `Forwarded` element or `X-Forwarded-Proto` value.
+fn forwarded_https(headers: &HeaderMap) -> bool {
+    let first = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .next()
+            .map(str::trim)
+    };
+
+    if let Some(element) = first(header::FORWARDED.as_str()) {
+        return element.split(';').filter_map(|pair| pair.split_once('=')).any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("proto") && value.trim().trim_matches('"').eq_ignore_ascii_case("https")
+        });
+    }
+    first("x-forwarded-proto").is_some_and(|proto| proto.eq_ignore_ascii_case("https"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{TestApp, TestResponse, PEER};
+
+    fn assert_hardened(response: &TestResponse) {
+        assert_eq!(response.header("x-content-type-options"), Some("nosniff"));
+        assert_eq!(response.header("x-frame-options"), Some("DENY"));
+        assert_eq!(response.header("referrer-policy"), Some("no-referrer"));
+    }
+
+    async fn shared_page(app: &TestApp) -> TestResponse {
+        let note = app.create_note(json!({"title": "Shared", "content": "Hello"})).await;
+        let share = app
+            .request(
+                Request::post(format!("/api/v1/notes/{}/share", note["id"].as_str().unwrap()))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        let token = share.json()["token"].as_str().unwrap().to_string();
+        app.request(
+            Request::get(format!("/api/v1/shared/{}", token))
+                .header(header::ACCEPT, "text/html")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn json_responses_are_hardened_without_a_content_policy(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let response = app.get("/api/v1/notes").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_hardened(&response);
+        assert_eq!(response.header("content-security-policy"), None);
+        assert_eq!(response.header("strict-transport-security"), None);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn html_responses_get_the_content_policy(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let response = shared_page(&app).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.header("content-type").unwrap().starts_with("text/html"));
+        assert_hardened(&response);
+        assert_eq!(response.header("content-security-policy"), Some(DEFAULT_CONTENT_SECURITY_POLICY));
+    }
+
+    /// GraphiQL, the one page with its own policy, is only in debug builds.
+    #[cfg(debug_assertions)]
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn a_handlers_own_content_policy_is_kept(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let response = app.get("/api/graphql").await;
+        assert_eq!(response.status, StatusCode::OK);
+        let policy = response.header("content-security-policy").unwrap();
+        assert_ne!(policy, DEFAULT_CONTENT_SECURITY_POLICY);
+        assert!(policy.contains("script-src"));
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn error_responses_are_hardened(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        for (uri, status) in [
+            ("/no/such/route", StatusCode::NOT_FOUND),
+            ("/api/v1/notes/00000000-0000-0000-0000-000000000000", StatusCode::NOT_FOUND),
+            ("/api/v1/notes/not-a-uuid", StatusCode::BAD_REQUEST),
+            ("/api/v1/debug/panic", StatusCode::INTERNAL_SERVER_ERROR),
+        ] {
+            let response = app.get(uri).await;
+            assert_eq!(response.status, status, "{}", uri);
+            assert_hardened(&response);
+        }
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn hsts_only_for_https_through_a_trusted_proxy(pool: sqlx::PgPool) {
+        let mut state = crate::test_support::state(pool).await;
+        state.trusted_proxies = std::net::IpAddr::from(PEER.0).to_string().parse().unwrap();
+        let app = TestApp::with_state(state);
+
+        let via = |name: &str, value: &str| {
+            Request::get("/api/v1/notes").header(name, value).body(Body::empty()).unwrap()
+        };
+        let response = app.request(via("x-forwarded-proto", "https")).await;
+        assert_eq!(response.header("strict-transport-security"), Some(STRICT_TRANSPORT_SECURITY));
+        let response = app.request(via("forwarded", "for=198.51.100.1;proto=https")).await;
+        assert_eq!(response.header("strict-transport-security"), Some(STRICT_TRANSPORT_SECURITY));
+        let response = app.request(via("x-forwarded-proto", "http")).await;
+        assert_eq!(response.header("strict-transport-security"), None);
+
+        // The same claim from a peer that isn't a proxy is ignored.
+        let untrusted = TestApp::new(app.state.db.clone()).await;
+        let response = untrusted.request(via("x-forwarded-proto", "https")).await;
+        assert_eq!(response.header("strict-transport-security"), None);
+    }
+}
@@ -0,0 +1,64 @@
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::AppError, insert_note, publishing::NoteStatus, CreateNote, Note};
+
+/// Sample notes for demo deployments, with fixed ids.
+const SEED_NOTES: &str = include_str!("../seed/notes.json");
+
+#[derive(Debug, Deserialize)]
+struct SeedNote {
+    id: Uuid,
+    title: String,
+    content: String,
+    status: Option<NoteStatus>,
+}
+
+/// Whether to seed on startup, from `SEED_NOTES` (default false).
+pub fn seed_from_env() -> bool {
+    std::env::var("SEED_NOTES")
+        .ok()
+        .map(|value| value.parse().expect("SEED_NOTES must be true or false"))
+        .unwrap_or(false)
+}
+
+/// Inserts the sample notes if the notes table is empty, through the same
+/// path as `POST /api/v1/notes`, and returns the notes it created. The table
+/// is locked against writes while checking, so concurrent runs can't both
+/// seed, and a database that already has notes is never touched.
+pub async fn seed(db: &PgPool) -> Result<Vec<Note>, AppError> {
+    let seeds: Vec<SeedNote> = serde_json::from_str(SEED_NOTES).expect("seed/notes.json must be valid");
+
+    let mut tx = db.begin().await?;
+
+    sqlx::query("LOCK TABLE notes IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut tx)
+        .await?;
+    let has_notes: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM notes)")
+        .fetch_one(&mut tx)
+        .await?;
+    if has_notes {
+        tracing::info!(seeded = 0, "notes table is not empty, skipping seed data");
+        return Ok(Vec::new());
+    }
+
+    let mut notes = Vec::new();
+    for seed in seeds {
+        NoteStatus::validate_settable(seed.status)?;
+        let payload = CreateNote {
+            id: Some(seed.id),
+            title: seed.title,
+            content: seed.content,
+            due_at: None,
+            expires_at: None,
+            status: seed.status,
+        };
+        notes.push(insert_note(&mut tx, &payload).await?);
+    }
+
+    tx.commit().await?;
+
+    tracing::info!(seeded = notes.len(), "inserted seed notes");
+    Ok(notes)
+}
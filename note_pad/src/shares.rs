@@ -0,0 +1,312 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{postgres::PgRow, Row};
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    attachments::note_exists,
+    client_ip::ClientIp,
+    crypto,
+    error::AppError, extract::{Json, Path},
+    negotiate::{negotiate, MediaType},
+    passwords,
+    query::visible,
+    render, validate_expires_at, AppState,
+};
+
+const DEFAULT_PUBLIC_BASE_URL: &str = "http://localhost:8080";
+
+/// Random bytes per share token; 256 bits can't be guessed.
+const TOKEN_BYTES: usize = 32;
+
+const DEFAULT_CLEANUP_INTERVAL_SECS: u64 = 3600;
+
+/// How long expired and revoked shares stay listed before they're purged.
+const RETENTION_PERIOD: chrono::Duration = chrono::Duration::days(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareStatus {
+    Active,
+    Expired,
+    Revoked,
+}
+
+/// A share link as shown to the note's owner. The token itself is only
+/// returned once, when the link is created.
+#[derive(Debug, Serialize)]
+pub struct Share {
+    pub id: Uuid,
+    pub note_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub status: ShareStatus,
+    pub view_count: i64,
+    pub last_viewed_at: Option<DateTime<Utc>>,
+}
+
+impl Share {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        let expires_at: Option<DateTime<Utc>> = row.try_get("expires_at")?;
+        let revoked_at: Option<DateTime<Utc>> = row.try_get("revoked_at")?;
+        let status = if revoked_at.is_some() {
+            ShareStatus::Revoked
+        } else if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            ShareStatus::Expired
+        } else {
+            ShareStatus::Active
+        };
+
+        Ok(Share {
+            id: row.try_get("id")?,
+            note_id: row.try_get("note_id")?,
+            created_at: row.try_get("created_at")?,
+            expires_at,
+            revoked_at,
+            status,
+            view_count: row.try_get("view_count")?,
+            last_viewed_at: row.try_get("last_viewed_at")?,
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateShare {
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedShare {
+    #[serde(flatten)]
+    share: Share,
+    token: String,
+    url: String,
+    /// How repeated share requests behave, so clients don't have to guess.
+    policy: &'static str,
+}
+
+/// Base URL used to build public share links, from `PUBLIC_BASE_URL`.
+pub fn public_base_url_from_env() -> String {
+    std::env::var("PUBLIC_BASE_URL")
+        .unwrap_or_else(|_| DEFAULT_PUBLIC_BASE_URL.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Only this digest of a token is stored, so a database leak doesn't leak
+/// working links.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Creates a new share link for the note, optionally dying at `expires_at`.
+/// Every call issues an independent token; earlier links keep working until
+/// they are revoked or expire. Sharing a protected note needs its password,
+/// since the link reveals the content.
+pub async fn create_share(
+    State(state): State<Arc<AppState>>,
+    Path(note_id): Path<Uuid>,
+    headers: HeaderMap,
+    payload: Option<Json<CreateShare>>,
+) -> Result<(StatusCode, Json<CreatedShare>), AppError> {
+    let Json(payload) = payload.unwrap_or_default();
+    validate_expires_at(payload.expires_at)?;
+
+    let mut conn = state
+        .db
+        .acquire()
+        .await?;
+    passwords::unlock_note(&state, &mut conn, note_id, passwords::supplied(&headers, None)).await?;
+
+    let token = generate_token();
+    let row = sqlx::query("INSERT INTO note_shares (note_id, token_hash, expires_at) VALUES ($1, $2, $3) RETURNING *")
+        .bind(note_id)
+        .bind(hash_token(&token))
+        .bind(payload.expires_at)
+        .fetch_one(&mut conn)
+        .await?;
+
+    let share = Share::from_row(&row)?;
+    let url = format!("{}/api/v1/shared/{}", state.public_base_url, token);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedShare {
+            share,
+            token,
+            url,
+            policy: "Each share request creates a new independent link; existing links stay active.",
+        }),
+    ))
+}
+
+/// Lists the note's links, including expired and revoked ones until the
+/// cleanup task purges them.
+pub async fn list_shares(
+    State(state): State<Arc<AppState>>,
+    Path(note_id): Path<Uuid>,
+) -> Result<Json<Vec<Share>>, AppError> {
+    if !note_exists(&state, note_id).await? {
+        return Err((StatusCode::NOT_FOUND, "Note not found".to_string()).into());
+    }
+
+    let rows = sqlx::query(
+        "SELECT * FROM note_shares WHERE note_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(note_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    let shares = rows
+        .iter()
+        .map(Share::from_row)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(shares))
+}
+
+/// Revokes a share link. The public endpoint checks every request against
+/// the database, so the link stops working immediately.
+pub async fn revoke_share(
+    State(state): State<Arc<AppState>>,
+    Path((note_id, share_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    let result = sqlx::query(
+        "UPDATE note_shares SET revoked_at = COALESCE(revoked_at, NOW())
+         WHERE id = $1 AND note_id = $2",
+    )
+    .bind(share_id)
+    .bind(note_id)
+    .execute(&state.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Share not found".to_string()).into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// What a share link reveals: nothing that identifies the note internally.
+#[derive(Debug, Serialize)]
+pub struct SharedNote {
+    title: String,
+    content: String,
+    updated_at: DateTime<Utc>,
+}
+
+/// Resolves a share token without authentication, as JSON or, for clients
+/// that accept `text/html`, as a rendered page. Unknown, expired and revoked
+/// tokens all look the same so a response never confirms a note exists.
+pub async fn get_shared(
+    State(state): State<Arc<AppState>>,
+    ClientIp(client_ip): ClientIp,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Err(limited) = state.share_limiter.check(client_ip) {
+        return Ok(limited.into_response());
+    }
+    let media_type = negotiate(&headers, &[MediaType::Json, MediaType::Html])?;
+
+    let row = sqlx::query(
+        concat!("UPDATE note_shares s
+         SET view_count = s.view_count + 1, last_viewed_at = NOW()
+         FROM notes n
+         WHERE s.token_hash = $1 AND n.id = s.note_id AND s.revoked_at IS NULL
+           AND (s.expires_at IS NULL OR s.expires_at > NOW())
+           AND ", visible!("n"), "
+         RETURNING n.title, n.content, n.content_nonce, n.content_ciphertext, n.updated_at"),
+    )
+    .bind(hash_token(&token))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Shared note not found".to_string()))?;
+
+    let note = SharedNote {
+        title: row.try_get("title")?,
+        content: crypto::content(&row)?,
+        updated_at: row.try_get("updated_at")?,
+    };
+
+    let mut response = match media_type {
+        MediaType::Html => Html(render_html(&note)).into_response(),
+        _ => Json(note).into_response(),
+    };
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept"));
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response_headers.insert("x-robots-tag", HeaderValue::from_static("noindex"));
+
+    Ok(response)
+}
+
+/// Renders the note as a standalone page.
+fn render_html(note: &SharedNote) -> String {
+    let body = render::markdown_to_html(&note.content);
+    let title = render::escape_html(&note.title);
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<article>\n<h1>{title}</h1>\n{body}<footer><time datetime=\"{updated}\">Updated {updated}</time></footer>\n</article>\n</body>\n</html>\n",
+        title = title,
+        body = body,
+        updated = note.updated_at.to_rfc3339(),
+    )
+}
+
+/// How often old shares are purged, from `SHARE_CLEANUP_INTERVAL_SECS`
+/// (default 3600).
+pub fn cleanup_interval_from_env() -> Duration {
+    let secs = std::env::var("SHARE_CLEANUP_INTERVAL_SECS")
+        .ok()
+        .map(|value| value.parse().expect("SHARE_CLEANUP_INTERVAL_SECS must be a number of seconds"))
+        .unwrap_or(DEFAULT_CLEANUP_INTERVAL_SECS);
+
+    Duration::from_secs(secs)
+}
+
+/// Deletes shares that expired or were revoked more than the retention
+/// period ago. They already stopped working; this only tidies the owner's
+/// list and the table.
+pub async fn purge_stale_shares(state: &AppState) -> Result<u64, sqlx::Error> {
+    let cutoff = Utc::now() - RETENTION_PERIOD;
+
+    let purged = sqlx::query("DELETE FROM note_shares WHERE revoked_at < $1 OR expires_at < $1")
+        .bind(cutoff)
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+
+    Ok(purged)
+}
+
+pub fn spawn_share_cleanup(state: Arc<AppState>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match purge_stale_shares(&state).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!(purged, "purged stale share links"),
+                Err(e) => tracing::error!(error = %e, "failed to purge stale share links"),
+            }
+        }
+    });
+}
@@ -0,0 +1,89 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use sqlx::{PgConnection, Postgres, Transaction};
+
+use crate::request_id;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+const DEFAULT_BULK_TIMEOUT_SECS: u64 = 600;
+
+/// Postgres' code for a statement cancelled by `statement_timeout`, or by
+/// hand with `pg_cancel_backend`.
+const QUERY_CANCELED: &str = "57014";
+
+/// Statement timeouts, in seconds, with 0 meaning no limit.
+#[derive(Debug, Clone, Copy)]
+pub struct StatementTimeouts {
+    /// Applies to every statement, from `DB_STATEMENT_TIMEOUT_SECS`
+    /// (default 30).
+    pub default: Duration,
+    /// For whole database exports and imports, such as backups, from
+    /// `DB_BULK_STATEMENT_TIMEOUT_SECS` (default 600).
+    pub bulk: Duration,
+}
+
+impl StatementTimeouts {
+    pub fn from_env() -> Self {
+        let secs = |name, default| {
+            std::env::var(name)
+                .ok()
+                .map(|value| {
+                    value
+                        .parse()
+                        .unwrap_or_else(|_| panic!("{} must be a number of seconds", name))
+                })
+                .unwrap_or(default)
+        };
+        StatementTimeouts {
+            default: Duration::from_secs(secs("DB_STATEMENT_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS)),
+            bulk: Duration::from_secs(secs("DB_BULK_STATEMENT_TIMEOUT_SECS", DEFAULT_BULK_TIMEOUT_SECS)),
+        }
+    }
+}
+
+/// Sets the default timeout on a new pool connection, so it applies to
+/// every statement without each handler asking.
+pub async fn apply(conn: &mut PgConnection, timeout: Duration) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("SET statement_timeout = {}", timeout.as_millis()))
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
+
+/// Raises the timeout for the rest of `tx` only; it is back to the default
+/// once the transaction ends, however it ends.
+pub async fn extend(tx: &mut Transaction<'_, Postgres>, timeout: Duration) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("SET LOCAL statement_timeout = {}", timeout.as_millis()))
+        .execute(&mut *tx)
+        .await
+        .map(|_| ())
+}
+
+pub fn is_timeout(error: &sqlx::Error) -> bool {
+    error
+        .as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == QUERY_CANCELED)
+}
+
+static TIMEOUTS: LazyLock<Mutex<HashMap<String, u64>>> = LazyLock::new(Default::default);
+
+/// Counts a timed out statement against the route being handled, or
+/// `<background>` for scheduled tasks.
+pub fn record() -> String {
+    let route = request_id::current()
+        .and_then(|request| request.route)
+        .unwrap_or_else(|| "<background>".to_string());
+    *TIMEOUTS.lock().unwrap().entry(route.clone()).or_default() += 1;
+    route
+}
+
+/// Timed out statements per route since startup, for the health check.
+pub fn snapshot() -> serde_json::Value {
+    serde_json::json!(*TIMEOUTS.lock().unwrap())
+}
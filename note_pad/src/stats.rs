@@ -0,0 +1,295 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{error::AppError, extract::{Json, Query}, query::visible, AppState};
+
+const DEFAULT_TIMEZONE: &str = "UTC";
+
+const DEFAULT_CACHE_SECS: u64 = 60;
+
+/// Days covered by a time series when `from` isn't given.
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+const MAX_BUCKETS: i64 = 500;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub total_notes: i64,
+    pub created_today: i64,
+    pub created_this_week: i64,
+    pub created_this_month: i64,
+    pub total_words: i64,
+    /// Average content length in characters, over plaintext notes.
+    pub average_length: f64,
+    pub last_updated_at: Option<DateTime<Utc>>,
+    /// The zone the day, week and month boundaries are taken in.
+    pub timezone: String,
+    pub generated_at: DateTime<Utc>,
+}
+
+/// Settings for `GET /api/v1/stats` and its last result.
+#[derive(Debug)]
+pub struct StatsCache {
+    timezone: String,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, Stats)>>,
+}
+
+impl StatsCache {
+    /// Reads `STATS_TIMEZONE` (an IANA name such as `Europe/Berlin`, default
+    /// UTC) and `STATS_CACHE_SECS` (default 60). The zone is checked against
+    /// the database so a typo fails at startup rather than on every request.
+    pub async fn from_env(db: &PgPool) -> Self {
+        let timezone = std::env::var("STATS_TIMEZONE").unwrap_or_else(|_| DEFAULT_TIMEZONE.to_string());
+        sqlx::query("SELECT NOW() AT TIME ZONE $1")
+            .bind(&timezone)
+            .execute(db)
+            .await
+            .expect("STATS_TIMEZONE must be a time zone name known to Postgres");
+
+        let ttl = std::env::var("STATS_CACHE_SECS")
+            .ok()
+            .map(|value| value.parse().expect("STATS_CACHE_SECS must be a number of seconds"))
+            .unwrap_or(DEFAULT_CACHE_SECS);
+
+        StatsCache {
+            timezone,
+            ttl: Duration::from_secs(ttl),
+            cached: Mutex::new(None),
+        }
+    }
+
+    fn get(&self) -> Option<Stats> {
+        let cached = self.cached.lock().unwrap();
+        cached
+            .as_ref()
+            .filter(|(computed, _)| computed.elapsed() < self.ttl)
+            .map(|(_, stats)| stats.clone())
+    }
+
+    fn put(&self, stats: Stats) {
+        *self.cached.lock().unwrap() = Some((Instant::now(), stats));
+    }
+}
+
+/// `GET /api/v1/stats`: aggregate numbers for dashboards, computed by a
+/// single query and cached for `STATS_CACHE_SECS`. Word counts and lengths
+/// only cover plaintext content, since encrypted notes can't be read by the
+/// database.
+pub async fn get_stats(State(state): State<Arc<AppState>>) -> Result<Json<Stats>, AppError> {
+    if let Some(stats) = state.stats.get() {
+        return Ok(Json(stats));
+    }
+
+    let row = sqlx::query(concat!(
+        "WITH bounds AS (
+             SELECT date_trunc('day', NOW() AT TIME ZONE $1) AT TIME ZONE $1 AS day,
+                    date_trunc('week', NOW() AT TIME ZONE $1) AT TIME ZONE $1 AS week,
+                    date_trunc('month', NOW() AT TIME ZONE $1) AT TIME ZONE $1 AS month
+         )
+         SELECT COUNT(*) AS total_notes,
+                COUNT(*) FILTER (WHERE created_at >= bounds.day) AS created_today,
+                COUNT(*) FILTER (WHERE created_at >= bounds.week) AS created_this_week,
+                COUNT(*) FILTER (WHERE created_at >= bounds.month) AS created_this_month,
+                COALESCE(SUM(array_length(regexp_split_to_array(NULLIF(btrim(content), ''), '\\s+'), 1)), 0)::BIGINT
+                    AS total_words,
+                COALESCE(AVG(char_length(content)) FILTER (WHERE content_nonce IS NULL), 0)::FLOAT8 AS average_length,
+                MAX(updated_at) AS last_updated_at
+         FROM notes CROSS JOIN bounds
+         WHERE ",
+        visible!()
+    ))
+    .bind(&state.stats.timezone)
+    .fetch_one(&state.db)
+    .await?;
+
+    let stats = Stats {
+        total_notes: row.try_get("total_notes")?,
+        created_today: row.try_get("created_today")?,
+        created_this_week: row.try_get("created_this_week")?,
+        created_this_month: row.try_get("created_this_month")?,
+        total_words: row.try_get("total_words")?,
+        average_length: row.try_get("average_length")?,
+        last_updated_at: row.try_get("last_updated_at")?,
+        timezone: state.stats.timezone.clone(),
+        generated_at: Utc::now(),
+    };
+    state.stats.put(stats.clone());
+
+    Ok(Json(stats))
+}
+
+/// Which timestamp a time series counts notes by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Created,
+    Updated,
+}
+
+impl Metric {
+    fn parse(name: &str) -> Result<Self, (StatusCode, String)> {
+        match name {
+            "created" => Ok(Metric::Created),
+            "updated" => Ok(Metric::Updated),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown metric '{}'. Valid values are: created, updated", name),
+            )),
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            Metric::Created => "created_at",
+            Metric::Updated => "updated_at",
+        }
+    }
+}
+
+/// The width of each bucket in a time series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    fn parse(name: &str) -> Result<Self, (StatusCode, String)> {
+        match name {
+            "day" => Ok(Interval::Day),
+            "week" => Ok(Interval::Week),
+            "month" => Ok(Interval::Month),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown interval '{}'. Valid values are: day, week, month", name),
+            )),
+        }
+    }
+
+    /// The field name `date_trunc` takes, which doubles as a unit for
+    /// `interval` literals.
+    fn sql(self) -> &'static str {
+        match self {
+            Interval::Day => "day",
+            Interval::Week => "week",
+            Interval::Month => "month",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeseriesParams {
+    metric: Option<String>,
+    interval: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    pub bucket_start: DateTime<Utc>,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Timeseries {
+    pub metric: Metric,
+    pub interval: Interval,
+    /// The zone buckets start in.
+    pub timezone: String,
+    /// Oldest first, with a bucket for every interval in the range, empty
+    /// ones included.
+    pub buckets: Vec<Bucket>,
+}
+
+/// `GET /api/v1/stats/timeseries`: how many notes were created or last
+/// updated in each day, week (from Monday) or month of `[from, to)`, in
+/// `STATS_TIMEZONE`. `metric` defaults to `created`, `interval` to `day`,
+/// `to` to now and `from` to 30 days before `to`. The first bucket starts
+/// at the interval holding `from` but only counts from `from` on. A range
+/// needing more than 500 buckets is a 400.
+pub async fn get_timeseries(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TimeseriesParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let metric = params.metric.as_deref().map(Metric::parse).transpose()?.unwrap_or(Metric::Created);
+    let interval = params.interval.as_deref().map(Interval::parse).transpose()?.unwrap_or(Interval::Day);
+    let to = params.to.unwrap_or_else(Utc::now);
+    let from = params.from.unwrap_or(to - chrono::Duration::days(DEFAULT_RANGE_DAYS));
+    if from >= to {
+        return Err((StatusCode::BAD_REQUEST, "from must be before to".to_string()).into());
+    }
+
+    // The series stops just short of `to`, so a `to` on a bucket boundary
+    // doesn't add an empty bucket after the range. One bucket past the cap
+    // is fetched to tell a range that's too long.
+    let rows = sqlx::query(&format!(
+        concat!("WITH series AS (
+             SELECT bucket FROM generate_series(
+                 date_trunc($2, $3 AT TIME ZONE $1),
+                 ($4 AT TIME ZONE $1) - INTERVAL '1 microsecond',
+                 ('1 ' || $2)::INTERVAL
+             ) AS bucket
+             LIMIT $5
+         ),
+         counts AS (
+             SELECT date_trunc($2, {column} AT TIME ZONE $1) AS bucket, COUNT(*) AS count FROM notes
+             WHERE {column} >= $3 AND {column} < $4 AND ", visible!(), "
+             GROUP BY 1
+         )
+         SELECT series.bucket AT TIME ZONE $1 AS bucket_start, COALESCE(counts.count, 0) AS count
+         FROM series LEFT JOIN counts USING (bucket)
+         ORDER BY series.bucket"),
+        column = metric.column()
+    ))
+    .bind(&state.stats.timezone)
+    .bind(interval.sql())
+    .bind(from)
+    .bind(to)
+    .bind(MAX_BUCKETS + 1)
+    .fetch_all(&state.db)
+    .await?;
+
+    if rows.len() as i64 > MAX_BUCKETS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The range needs more than {} buckets; narrow it or use a longer interval", MAX_BUCKETS),
+        )
+        .into());
+    }
+
+    let mut buckets = Vec::with_capacity(rows.len());
+    for row in &rows {
+        buckets.push(Bucket {
+            bucket_start: row.try_get("bucket_start")?,
+            count: row.try_get("count")?,
+        });
+    }
+
+    let cache_control = format!("max-age={}", state.stats.ttl.as_secs());
+    Ok((
+        [(
+            header::CACHE_CONTROL,
+            HeaderValue::from_str(&cache_control).unwrap_or_else(|_| HeaderValue::from_static("no-cache")),
+        )],
+        Json(Timeseries {
+            metric,
+            interval,
+            timezone: state.stats.timezone.clone(),
+            buckets,
+        }),
+    ))
+}
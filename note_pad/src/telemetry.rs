@@ -0,0 +1,170 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use sqlx::postgres::{PgQueryResult, PgRow};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{field::Empty, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::request_id::REQUEST_ID_HEADER;
+
+/// Whether spans are exported, so the request and query spans aren't even
+/// created when they'd go nowhere.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The installed tracer, flushed when dropped so spans of a short run, such
+/// as `seed`, aren't lost with the process.
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            tracing::warn!(error = %e, "failed to flush trace spans");
+        }
+    }
+}
+
+/// Installs the log subscriber and, when `OTEL_EXPORTER_OTLP_ENDPOINT` is
+/// set, a tracer exporting spans to it over OTLP/HTTP. The exporter reads
+/// the other standard `OTEL_EXPORTER_OTLP_*` variables itself; the service
+/// is named by `OTEL_SERVICE_NAME`, `note_pad` by default.
+pub fn init() -> Telemetry {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "note_pad=info".into());
+
+    let provider = std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").map(|_| {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()
+            .expect("OTEL_EXPORTER_OTLP_* must configure a valid exporter");
+        let service_name = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "note_pad".to_string());
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build()
+    });
+    let otel = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("note_pad")));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel)
+        .init();
+
+    if provider.is_some() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        ENABLED.store(true, Ordering::Relaxed);
+        tracing::info!("exporting traces over OTLP");
+    }
+    Telemetry { provider }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Runs the request in a span named after its route, continuing the trace
+/// of an incoming `traceparent` header. Only layered when tracing is on.
+/// Like the access log, it records the route rather than the path, which
+/// may hold note IDs the client didn't mean to share with the collector.
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let route = route.as_deref().unwrap_or("<unmatched>");
+    let method = request.method().as_str();
+    let span = tracing::info_span!(
+        "http.request",
+        otel.name = format!("{} {}", method, route),
+        otel.kind = "server",
+        http.request.method = method,
+        http.route = route,
+        request_id = Empty,
+        http.response.status_code = Empty,
+        otel.status_code = Empty,
+    );
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+    span.set_parent(parent);
+
+    let response = next.run(request).instrument(span.clone()).await;
+
+    // The ID is assigned inside this layer, so it's read off the response.
+    if let Some(request_id) = response.headers().get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok()) {
+        span.record("request_id", request_id);
+    }
+    span.record("http.response.status_code", response.status().as_u16());
+    if response.status().is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+    response
+}
+
+/// Rows a database call returned or changed, for its span.
+pub trait RowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl RowCount for PgRow {
+    fn row_count(&self) -> u64 {
+        1
+    }
+}
+
+impl RowCount for Option<PgRow> {
+    fn row_count(&self) -> u64 {
+        self.is_some().into()
+    }
+}
+
+impl RowCount for Vec<PgRow> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl RowCount for PgQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+/// A span for a database call under `operation`, or none with tracing off.
+/// Like the slow query log, it carries no statement or bound values.
+pub fn query_span(operation: &'static str) -> Span {
+    if !enabled() {
+        return Span::none();
+    }
+    tracing::info_span!(
+        "db.query",
+        otel.name = operation,
+        otel.kind = "client",
+        db.system = "postgresql",
+        db.operation = operation,
+        db.rows = Empty,
+        otel.status_code = Empty,
+    )
+}
@@ -0,0 +1,247 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, Row};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+use uuid::Uuid;
+
+use crate::{content_limit, error::AppError, extract::{Json, Path, Query}, hypermedia::{BaseUrl, LinksParams}, insert_note, publishing::NoteStatus, AppState, CreateNote, Note};
+
+/// Longest title a note can have, matching the column.
+const MAX_TITLE_CHARS: usize = 255;
+
+#[derive(Debug, Serialize)]
+pub struct Template {
+    pub id: Uuid,
+    pub name: String,
+    pub title_template: String,
+    pub content_template: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Template {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Template {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            title_template: row.try_get("title_template")?,
+            content_template: row.try_get("content_template")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTemplate {
+    name: String,
+    title_template: String,
+    #[serde(default)]
+    content_template: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTemplate {
+    name: Option<String>,
+    title_template: Option<String>,
+    content_template: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Instantiate {
+    /// Values for `{{name}}` placeholders, on top of `date` and `time`.
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    /// Fail instead of leaving unknown placeholders in the note.
+    #[serde(default)]
+    strict: bool,
+}
+
+fn validate_not_empty(field: &str, value: Option<&str>) -> Result<(), AppError> {
+    if value.is_some_and(|value| value.trim().is_empty()) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("{} must not be empty", field)).into());
+    }
+    Ok(())
+}
+
+/// Replaces `{{name}}` placeholders with their values. Placeholders without
+/// a value are kept as written and collected in `missing`.
+fn fill(template: &str, values: &HashMap<String, String>, missing: &mut BTreeSet<String>) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+
+        let name = after[..end].trim();
+        let placeholder = &rest[start..start + 2 + end + 2];
+        let is_name = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        match values.get(name) {
+            Some(value) if is_name => filled.push_str(value),
+            _ => {
+                if is_name {
+                    missing.insert(name.to_string());
+                }
+                filled.push_str(placeholder);
+            }
+        }
+        rest = &after[end + 2..];
+    }
+
+    filled.push_str(rest);
+    filled
+}
+
+pub async fn list_templates(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Template>>, AppError> {
+    let rows = sqlx::query("SELECT * FROM templates ORDER BY name, id")
+        .fetch_all(&state.db)
+        .await?;
+
+    let templates = rows.iter().map(Template::from_row).collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(templates))
+}
+
+pub async fn create_template(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateTemplate>,
+) -> Result<(StatusCode, Json<Template>), AppError> {
+    validate_not_empty("name", Some(&payload.name))?;
+    validate_not_empty("title_template", Some(&payload.title_template))?;
+
+    let row = sqlx::query(
+        "INSERT INTO templates (name, title_template, content_template) VALUES ($1, $2, $3) RETURNING *",
+    )
+    .bind(&payload.name)
+    .bind(&payload.title_template)
+    .bind(&payload.content_template)
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(Template::from_row(&row)?)))
+}
+
+pub async fn get_template(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Template>, AppError> {
+    let row = sqlx::query("SELECT * FROM templates WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Template not found".to_string()))?;
+
+    Ok(Json(Template::from_row(&row)?))
+}
+
+pub async fn update_template(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<UpdateTemplate>,
+) -> Result<Json<Template>, AppError> {
+    validate_not_empty("name", payload.name.as_deref())?;
+    validate_not_empty("title_template", payload.title_template.as_deref())?;
+
+    let row = sqlx::query(
+        "UPDATE templates
+         SET name = COALESCE($1, name),
+             title_template = COALESCE($2, title_template),
+             content_template = COALESCE($3, content_template)
+         WHERE id = $4
+         RETURNING *",
+    )
+    .bind(payload.name)
+    .bind(payload.title_template)
+    .bind(payload.content_template)
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Template not found".to_string()))?;
+
+    Ok(Json(Template::from_row(&row)?))
+}
+
+/// Deletes a template. Notes created from it are independent and stay.
+pub async fn delete_template(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let result = sqlx::query("DELETE FROM templates WHERE id = $1")
+        .bind(id)
+        .execute(&state.db)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Template not found".to_string()).into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `POST /api/v1/templates/{id}/instantiate`: creates a draft note from a
+/// template. `{{date}}` and `{{time}}` are today's UTC date and the current
+/// UTC time; `variables` fill in the rest and may override those two.
+/// Unknown placeholders are left as written, or rejected with `strict`.
+pub async fn instantiate_template(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(link_params): Query<LinksParams>,
+    base: BaseUrl,
+    payload: Option<Json<Instantiate>>,
+) -> Result<(StatusCode, Json<Note>), AppError> {
+    let Json(payload) = payload.unwrap_or_default();
+    let Json(template) = get_template(State(state.clone()), Path(id)).await?;
+
+    let now = Utc::now();
+    let mut values = HashMap::from([
+        ("date".to_string(), now.format("%Y-%m-%d").to_string()),
+        ("time".to_string(), now.format("%H:%M").to_string()),
+    ]);
+    values.extend(payload.variables);
+
+    let mut missing = BTreeSet::new();
+    let title = fill(&template.title_template, &values, &mut missing);
+    let content = fill(&template.content_template, &values, &mut missing);
+
+    if payload.strict && !missing.is_empty() {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "missing_variables",
+            "The template has placeholders without a value",
+        )
+        .with_details(serde_json::json!({ "missing": missing })));
+    }
+    if title.trim().is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("The filled-in title must be 1 to {} characters", MAX_TITLE_CHARS),
+        )
+            .into());
+    }
+    content_limit::check(state.max_content_bytes, &content)?;
+
+    let mut tx = state.db.begin().await?;
+
+    let payload = CreateNote {
+        id: None,
+        title,
+        content,
+        due_at: None,
+        expires_at: None,
+        status: Some(NoteStatus::Draft),
+    };
+    let mut note = insert_note(&mut tx, &payload).await?;
+    note.links = link_params.note_links(&base, note.id);
+
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(note)))
+}
@@ -0,0 +1,21 @@
+//! The migrations `#[sqlx::test]` databases are built with. Shared with the
+//! tests under `tests/`, which include this file by path.
+
+use sqlx::migrate::Migrator;
+use std::sync::LazyLock;
+
+/// `20250910020648_note_pad` repeats the `CREATE TABLE notes` of the
+/// migration before it, so it can't run on a fresh database. Deployed
+/// databases have it recorded with its checksum, so the file stays as it is
+/// and only test databases skip it; the schema comes out the same.
+const DUPLICATE_NOTES_TABLE: i64 = 20250910020648;
+
+/// Every migration in `migrations/` except the duplicate one, for
+/// `#[sqlx::test(migrator = "...")]`.
+pub static MIGRATOR: LazyLock<Migrator> = LazyLock::new(|| {
+    let all = sqlx::migrate!();
+    Migrator {
+        migrations: all.migrations.iter().filter(|m| m.version != DUPLICATE_NOTES_TABLE).cloned().collect(),
+        ..all
+    }
+});
@@ -0,0 +1,185 @@
+//! Runs the whole app, middleware included, over a `#[sqlx::test]` database.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tower::ServiceExt;
+use tower_http::normalize_path::NormalizePath;
+use uuid::Uuid;
+
+use crate::{crypto, listen, metrics, shares::hash_token, statement_timeout::StatementTimeouts, unaccent, AppState};
+
+/// The peer address every test request appears to come from.
+pub const PEER: ([u8; 4], u16) = ([203, 0, 113, 7], 40000);
+
+/// The `ADMIN_TOKEN` of apps made by `TestApp::with_admin`.
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// The app's state over `pool`, configured from the environment like the
+/// server's, except that attachments go to a directory of their own.
+pub async fn state(pool: PgPool) -> AppState {
+    crypto::init_from_env();
+    metrics::init_from_env();
+    unaccent::init(&pool).await;
+
+    let mut state = crate::app_state(pool, StatementTimeouts::from_env()).await;
+    state.attachments.dir = scratch_dir();
+    state
+}
+
+/// An empty directory under the system's temporary directory.
+pub fn scratch_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("note_pad-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).expect("the temporary directory is writable");
+    dir
+}
+
+pub struct TestApp {
+    pub state: Arc<AppState>,
+    router: NormalizePath<Router>,
+}
+
+impl TestApp {
+    pub async fn new(pool: PgPool) -> Self {
+        Self::with_state(state(pool).await)
+    }
+
+    /// An app with the admin routes on, for `as_admin` to call.
+    pub async fn with_admin(pool: PgPool) -> Self {
+        let mut state = state(pool).await;
+        state.admin_token_hash = Some(hash_token(ADMIN_TOKEN));
+        Self::with_state(state)
+    }
+
+    pub fn with_state(state: AppState) -> Self {
+        let state = Arc::new(state);
+        TestApp {
+            router: crate::router(state.clone()),
+            state,
+        }
+    }
+
+    /// Serves the app on a free local port the way `main` does, for tests
+    /// that need real connections.
+    pub async fn serve(&self) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(listen::serve(vec![listener], self.router.clone()));
+        addr
+    }
+
+    pub async fn request(&self, mut request: Request<Body>) -> TestResponse {
+        if request.extensions().get::<ConnectInfo<SocketAddr>>().is_none() {
+            request.extensions_mut().insert(ConnectInfo(SocketAddr::from(PEER)));
+        }
+        let response = self.router.clone().oneshot(request).await.expect("the router is infallible");
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("the response body can be read");
+        TestResponse { status, headers, body }
+    }
+
+    /// Sends `request` with the admin token of `with_admin`.
+    pub async fn as_admin(&self, mut request: Request<Body>) -> TestResponse {
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", ADMIN_TOKEN)).unwrap(),
+        );
+        self.request(request).await
+    }
+
+    /// Downloads an export, panicking unless it succeeds.
+    pub async fn export(&self) -> Value {
+        let response = self
+            .as_admin(Request::get("/api/v1/admin/export").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        response.json()
+    }
+
+    /// Uploads `backup` to be restored in the default mode.
+    pub async fn restore(&self, backup: &Value) -> TestResponse {
+        let bytes = backup.to_string();
+        self.as_admin(multipart("/api/v1/admin/restore", "backup", &[("backup.json", bytes.as_bytes())]))
+            .await
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.request(Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    pub async fn send_json(&self, method: Method, uri: &str, body: Value) -> TestResponse {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        self.request(request).await
+    }
+
+    /// Creates a note, panicking unless it was created, and returns the
+    /// response body.
+    pub async fn create_note(&self, body: Value) -> Value {
+        let response = self.send_json(Method::POST, "/api/v1/notes", body).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        response.json()
+    }
+
+    /// Uploads `files`, as `(filename, contents)`, to a note.
+    pub async fn upload(&self, note_id: Uuid, files: &[(&str, &[u8])]) -> TestResponse {
+        self.request(multipart(&format!("/api/v1/notes/{}/attachments", note_id), "file", files))
+            .await
+    }
+}
+
+/// A `POST` to `uri` of a multipart body holding `files`, as `(filename,
+/// contents)`, each in a field named `field`.
+pub fn multipart(uri: &str, field: &str, files: &[(&str, &[u8])]) -> Request<Body> {
+    let boundary = "note-pad-test-boundary";
+    let mut body = Vec::new();
+    for (filename, contents) in files {
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{field}\"; filename=\"{filename}\"\r\nContent-Type: image/png\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(contents);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={boundary}"))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| panic!("{}: {}", e, self.text()))
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
@@ -0,0 +1,68 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use serde::Serialize;
+use sqlx::Row;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{crypto, error::AppError, extract::{Json, Path}, passwords, query::visible, render, AppState};
+
+/// A heading and the headings under it.
+#[derive(Debug, Serialize)]
+pub struct TocEntry {
+    level: u8,
+    text: String,
+    /// The heading's `id` in rendered HTML, for `#` links.
+    anchor: String,
+    children: Vec<TocEntry>,
+}
+
+/// Nests headings under the closest earlier heading of a higher level. A
+/// heading that skips levels, such as an `###` right after an `#`, is still
+/// a child of the `#`.
+fn tree(headings: Vec<render::Heading>) -> Vec<TocEntry> {
+    fn insert(entries: &mut Vec<TocEntry>, entry: TocEntry) {
+        match entries.last_mut() {
+            Some(last) if last.level < entry.level => insert(&mut last.children, entry),
+            _ => entries.push(entry),
+        }
+    }
+
+    let mut entries = Vec::new();
+    for heading in headings {
+        insert(
+            &mut entries,
+            TocEntry {
+                level: heading.level,
+                text: heading.text,
+                anchor: heading.anchor,
+                children: Vec::new(),
+            },
+        );
+    }
+    entries
+}
+
+/// The outline of a note's Markdown headings, empty for a note without
+/// any. Password protected notes need their password, as reading them
+/// does.
+pub async fn get_toc(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<TocEntry>>, AppError> {
+    let row = sqlx::query(
+        concat!("SELECT content, content_nonce, content_ciphertext, password_hash FROM notes
+         WHERE id = $1 AND ", visible!()),
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or((StatusCode::NOT_FOUND, "Note not found".to_string()))?;
+    passwords::unlock(&state, id, row.try_get("password_hash")?, passwords::supplied(&headers, None)).await?;
+
+    let content = crypto::content(&row)?;
+    Ok(Json(tree(render::headings(&content))))
+}
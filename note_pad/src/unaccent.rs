@@ -0,0 +1,56 @@
+use sqlx::PgPool;
+use std::sync::OnceLock;
+
+/// Whether `immutable_unaccent()` and the `english_unaccent` text search
+/// configuration from the unaccent migration exist.
+static AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+/// Checks for what the unaccent migration creates. Without it, titles match
+/// case-insensitively only and full-text search uses plain `english`,
+/// which is logged rather than failing startup.
+pub async fn init(pool: &PgPool) {
+    let available = sqlx::query_scalar(
+        "SELECT to_regprocedure('immutable_unaccent(text)') IS NOT NULL
+                AND EXISTS (SELECT 1 FROM pg_ts_config WHERE cfgname = 'english_unaccent')",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap_or_else(|e| {
+        tracing::warn!(error = %e, "could not check for unaccent");
+        false
+    });
+    if !available {
+        tracing::warn!("the unaccent extension isn't installed; titles will only match case-insensitively");
+    }
+    AVAILABLE.set(available).ok();
+}
+
+fn available() -> bool {
+    AVAILABLE.get().copied().unwrap_or(false)
+}
+
+/// The SQL around an expression to lowercase it and, where possible, strip
+/// its accents, so `Café` matches `cafe`.
+pub fn fold_parts() -> (&'static str, &'static str) {
+    if available() {
+        ("immutable_unaccent(lower(", "))")
+    } else {
+        ("lower(", ")")
+    }
+}
+
+/// `expr` folded as by `fold_parts`. Applied to `title` it matches the
+/// functional index.
+pub fn fold(expr: &str) -> String {
+    let (open, close) = fold_parts();
+    format!("{}{}{}", open, expr, close)
+}
+
+/// The text search configuration for full-text matching.
+pub fn text_search_config() -> &'static str {
+    if available() {
+        "english_unaccent"
+    } else {
+        "english"
+    }
+}
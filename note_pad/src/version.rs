@@ -0,0 +1,164 @@
+use axum::extract::State;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::{AppState, extract::Json};
+
+/// What was built, as baked in by `build.rs`.
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub build_timestamp: Option<DateTime<Utc>>,
+    pub rustc_version: &'static str,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("NOTE_PAD_GIT_COMMIT"),
+            build_timestamp: env!("NOTE_PAD_BUILD_TIMESTAMP")
+                .parse()
+                .ok()
+                .and_then(|secs| DateTime::from_timestamp(secs, 0)),
+            rustc_version: env!("NOTE_PAD_RUSTC_VERSION"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    #[serde(flatten)]
+    pub build: BuildInfo,
+    /// The newest migration recorded by `sqlx migrate run`, if the database
+    /// was migrated that way.
+    pub migration_version: Option<i64>,
+    pub migrations: MigrationStatus,
+}
+
+/// How the database's migrations compare to those this build was made
+/// with. What's applied is only known for databases migrated by
+/// `sqlx migrate run`.
+#[derive(Debug, Serialize)]
+pub struct MigrationStatus {
+    /// The newest applied migration, as `<version>_<description>`.
+    pub applied: Option<String>,
+    /// The newest migration of this build, named the same way.
+    pub latest: Option<&'static str>,
+    /// Migrations of this build the database hasn't applied.
+    pub pending: Option<usize>,
+}
+
+/// The migrations `build.rs` found, as `(version, name)`, oldest first.
+fn embedded_migrations() -> impl Iterator<Item = (i64, &'static str)> {
+    env!("NOTE_PAD_MIGRATIONS")
+        .split(',')
+        .filter_map(|name| Some((name.split_once('_')?.0.parse().ok()?, name)))
+}
+
+pub async fn migration_status(db: &PgPool) -> MigrationStatus {
+    let latest = embedded_migrations().last().map(|(_, name)| name);
+    let applied: Vec<(i64, String)> =
+        match sqlx::query_as("SELECT version, description FROM _sqlx_migrations WHERE success ORDER BY version")
+            .fetch_all(db)
+            .await
+        {
+            Ok(applied) => applied,
+            Err(_) => {
+                return MigrationStatus {
+                    applied: None,
+                    latest,
+                    pending: None,
+                };
+            }
+        };
+
+    let pending = embedded_migrations()
+        .filter(|(version, _)| !applied.iter().any(|(applied, _)| applied == version))
+        .count();
+    // sqlx stores the description with spaces for the underscores.
+    let applied = applied
+        .last()
+        .map(|(version, description)| format!("{}_{}", version, description.replace(' ', "_")));
+
+    MigrationStatus {
+        applied,
+        latest,
+        pending: Some(pending),
+    }
+}
+
+/// The latest successfully applied migration. Databases migrated by hand
+/// have no `_sqlx_migrations` table and report `None`.
+pub async fn migration_version(db: &PgPool) -> Option<i64> {
+    sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(db)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Logs the build and schema versions once at startup.
+pub async fn log_startup(db: &PgPool) {
+    let build = BuildInfo::current();
+    tracing::info!(
+        version = build.version,
+        git_commit = build.git_commit,
+        build_timestamp = ?build.build_timestamp,
+        rustc_version = build.rustc_version,
+        migration_version = ?migration_version(db).await,
+        "starting note_pad"
+    );
+}
+
+/// `GET /api/v1/version`: what is deployed. Holds nothing beyond build and
+/// schema versions, so it's safe to leave public.
+pub async fn get_version(State(state): State<Arc<AppState>>) -> Json<VersionInfo> {
+    Json(VersionInfo {
+        build: BuildInfo::current(),
+        migration_version: migration_version(&state.db).await,
+        migrations: migration_status(&state.db).await,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::Value;
+
+    use crate::test_support::TestApp;
+
+    fn assert_filled(body: &Value, key: &str) {
+        let value = &body[key];
+        assert!(!value.is_null() && value.as_str() != Some(""), "{} is {}", key, value);
+    }
+
+    #[sqlx::test(migrator = "crate::test_migrations::MIGRATOR")]
+    async fn every_version_field_is_filled_in(pool: sqlx::PgPool) {
+        let app = TestApp::new(pool).await;
+        let response = app.get("/api/v1/version").await;
+        assert_eq!(response.status, StatusCode::OK);
+        let body = response.json();
+
+        let keys = ["version", "git_commit", "build_timestamp", "rustc_version", "migration_version", "migrations"];
+        for key in keys {
+            assert_filled(&body, key);
+        }
+        assert_eq!(body.as_object().unwrap().len(), keys.len(), "{}", body);
+        for key in ["applied", "latest", "pending"] {
+            assert_filled(&body["migrations"], key);
+        }
+
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["rustc_version"].as_str().unwrap().starts_with("rustc "));
+        assert!(body["build_timestamp"].as_str().unwrap().parse::<chrono::DateTime<chrono::Utc>>().is_ok());
+        assert_eq!(body["migrations"]["applied"], body["migrations"]["latest"]);
+        // Test databases skip the migration that repeats `CREATE TABLE notes`.
+        assert_eq!(body["migrations"]["pending"], 1);
+        let latest = body["migrations"]["latest"].as_str().unwrap();
+        assert!(latest.starts_with(&body["migration_version"].to_string()), "{}", body);
+    }
+}
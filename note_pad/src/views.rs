@@ -0,0 +1,196 @@
+use axum::extract::State;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError, extract::{Json, Query},
+    query::{NoteQuery, SortField},
+    AppState, ListNotesParams, NoteSummary, SUMMARY_COLUMNS,
+};
+
+const DEFAULT_FLUSH_INTERVAL_SECS: u64 = 10;
+
+const MAX_LIMIT: i64 = 100;
+
+/// `?count_view=false` reads a note without counting a view, for sync
+/// clients and other background fetches.
+#[derive(Debug, Deserialize)]
+pub struct ViewParams {
+    count_view: Option<bool>,
+}
+
+impl ViewParams {
+    pub fn counts(&self) -> bool {
+        self.count_view.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingViews {
+    count: i64,
+    last_viewed_at: DateTime<Utc>,
+}
+
+/// Views counted in memory and written to `notes.view_count` and
+/// `notes.last_viewed_at` in batches, so reading a note never waits on, or
+/// fails because of, a write.
+#[derive(Debug, Default)]
+pub struct ViewCounter {
+    pending: Mutex<HashMap<Uuid, PendingViews>>,
+}
+
+impl ViewCounter {
+    pub fn record(&self, id: Uuid) {
+        let now = Utc::now();
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(id)
+            .and_modify(|views| {
+                views.count += 1;
+                views.last_viewed_at = now;
+            })
+            .or_insert(PendingViews { count: 1, last_viewed_at: now });
+    }
+
+    fn take(&self) -> HashMap<Uuid, PendingViews> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Puts back views whose write failed, to be retried with the next batch.
+    fn restore(&self, views: HashMap<Uuid, PendingViews>) {
+        let mut pending = self.pending.lock().unwrap();
+        for (id, restored) in views {
+            pending
+                .entry(id)
+                .and_modify(|views| views.count += restored.count)
+                .or_insert(restored);
+        }
+    }
+}
+
+/// How often counted views are written, from `VIEW_FLUSH_INTERVAL_SECS`
+/// (default 10). Views counted since the last write are lost if the
+/// process stops.
+pub fn flush_interval_from_env() -> Duration {
+    let secs = std::env::var("VIEW_FLUSH_INTERVAL_SECS")
+        .ok()
+        .map(|value| value.parse().expect("VIEW_FLUSH_INTERVAL_SECS must be a number of seconds"))
+        .unwrap_or(DEFAULT_FLUSH_INTERVAL_SECS);
+
+    Duration::from_secs(secs)
+}
+
+/// Adds the pending views to their notes in one statement. Like other
+/// bookkeeping, this leaves `updated_at` and `version` alone, so it doesn't
+/// change ETags or count as an edit.
+pub async fn flush(state: &AppState) -> Result<u64, sqlx::Error> {
+    let views = state.views.take();
+    if views.is_empty() {
+        return Ok(0);
+    }
+    let mut ids = Vec::with_capacity(views.len());
+    let mut counts = Vec::with_capacity(views.len());
+    let mut viewed_at = Vec::with_capacity(views.len());
+    for (id, pending) in &views {
+        ids.push(*id);
+        counts.push(pending.count);
+        viewed_at.push(pending.last_viewed_at);
+    }
+
+    // GREATEST skips NULLs, and keeps the newer time should another instance
+    // have written a later view first.
+    let result = sqlx::query(
+        "UPDATE notes SET view_count = view_count + v.count,
+                          last_viewed_at = GREATEST(last_viewed_at, v.viewed_at)
+         FROM UNNEST($1::uuid[], $2::bigint[], $3::timestamptz[]) AS v(id, count, viewed_at)
+         WHERE notes.id = v.id",
+    )
+    .bind(&ids)
+    .bind(&counts)
+    .bind(&viewed_at)
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(result) => Ok(result.rows_affected()),
+        Err(e) => {
+            state.views.restore(views);
+            Err(e)
+        }
+    }
+}
+
+pub fn spawn_view_flusher(state: Arc<AppState>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = flush(&state).await {
+                tracing::error!(error = %e, "failed to write note view counts");
+            }
+        }
+    });
+}
+
+#[derive(Debug, Serialize)]
+pub struct ViewedNote {
+    #[serde(flatten)]
+    note: NoteSummary,
+    view_count: i64,
+    last_viewed_at: Option<DateTime<Utc>>,
+}
+
+/// Most viewed notes first.
+pub async fn get_popular(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ListNotesParams>,
+) -> Result<Json<Vec<ViewedNote>>, AppError> {
+    let query = params.to_query(state.default_sort)?.sorted_by(SortField::ViewCount, None);
+    list_viewed(&state, &params, query).await
+}
+
+/// Most recently viewed notes first. Notes never viewed aren't listed.
+pub async fn get_recently_viewed(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ListNotesParams>,
+) -> Result<Json<Vec<ViewedNote>>, AppError> {
+    let mut query = params.to_query(state.default_sort)?.sorted_by(SortField::LastViewedAt, None);
+    query.viewed = true;
+    list_viewed(&state, &params, query).await
+}
+
+/// Runs a view ordered listing, with `limit` capped at `MAX_LIMIT`. Views
+/// still waiting to be written aren't reflected yet.
+async fn list_viewed(
+    state: &AppState,
+    params: &ListNotesParams,
+    mut query: NoteQuery,
+) -> Result<Json<Vec<ViewedNote>>, AppError> {
+    query.limit = query.limit.clamp(1, MAX_LIMIT);
+    query.offset = query.offset.max(0);
+
+    let rows = query
+        .build(SUMMARY_COLUMNS)
+        .build()
+        .fetch_all(&state.db)
+        .await?;
+
+    let mut notes = Vec::new();
+    for row in rows {
+        notes.push(ViewedNote {
+            note: NoteSummary::from_row(&row, params.full_content)?,
+            view_count: row.try_get("view_count")?,
+            last_viewed_at: row.try_get("last_viewed_at")?,
+        });
+    }
+
+    Ok(Json(notes))
+}
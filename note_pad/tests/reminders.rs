@@ -0,0 +1,127 @@
+//! Runs the server binary against a test database to check what happens to
+//! reminders across restarts.
+
+use sqlx::PgPool;
+use std::{
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+};
+
+#[path = "../src/test_migrations.rs"]
+mod test_migrations;
+
+/// A running server and the lines it has logged so far.
+struct Server {
+    child: Child,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Server {
+    async fn start(database_url: &str) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_note_pad"))
+            .env("DATABASE_URL", database_url)
+            .env("HOST", "127.0.0.1")
+            .env("PORT", "0")
+            .env("ALLOW_EPHEMERAL_PORT", "true")
+            .env("REMINDER_TICK_SECS", "1")
+            .env("RUST_LOG", "note_pad=info")
+            .env("NO_COLOR", "1")
+            .env_remove("GRPC_ADDR")
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("the server binary runs");
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let collected = log.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                collected.lock().unwrap().push(line);
+            }
+        });
+
+        let server = Server { child, log };
+        server.wait_for("Server started successfully").await;
+        server
+    }
+
+    async fn wait_for(&self, text: &str) -> String {
+        for _ in 0..100 {
+            if let Some(line) = self.lines_with(text).pop() {
+                return line;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the server never logged {:?}: {:#?}", text, self.log.lock().unwrap());
+    }
+
+    fn lines_with(&self, text: &str) -> Vec<String> {
+        self.log.lock().unwrap().iter().filter(|line| line.contains(text)).cloned().collect()
+    }
+
+    /// Stops the server the way a crash or `kill -9` would.
+    async fn kill(mut self) -> Vec<String> {
+        self.child.kill().await.unwrap();
+        self.log.lock().unwrap().clone()
+    }
+}
+
+/// The URL of the database `#[sqlx::test]` made for this test.
+async fn database_url(pool: &PgPool) -> String {
+    let name: String = sqlx::query_scalar("SELECT current_database()").fetch_one(pool).await.unwrap();
+    let server = std::env::var("DATABASE_URL").expect("DATABASE_URL is set for sqlx::test");
+    let (server, _) = server.rsplit_once('/').expect("DATABASE_URL ends in a database name");
+    format!("{}/{}", server, name)
+}
+
+/// How many reminders a server's log says it fired, over all its ticks.
+fn fired(log: &[String]) -> usize {
+    log.iter()
+        .filter(|line| line.contains("fired due note reminders"))
+        .map(|line| {
+            let (_, count) = line.split_once("fired=").expect("the count is logged");
+            count.split_whitespace().next().unwrap().parse::<usize>().unwrap()
+        })
+        .sum()
+}
+
+async fn reminded_at(pool: &PgPool) -> Vec<Option<chrono::DateTime<chrono::Utc>>> {
+    sqlx::query_scalar("SELECT reminded_at FROM notes ORDER BY title").fetch_all(pool).await.unwrap()
+}
+
+#[sqlx::test(migrator = "test_migrations::MIGRATOR")]
+async fn reminders_missed_while_down_fire_once_on_startup(pool: PgPool) {
+    // Came due while no server was running.
+    sqlx::query(
+        "INSERT INTO notes (title, content, due_at)
+         SELECT 'Missed ' || n, '', NOW() - n * INTERVAL '1 hour' FROM generate_series(1, 3) AS n",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let url = database_url(&pool).await;
+
+    let first = Server::start(&url).await;
+    first.wait_for("fired due note reminders").await;
+    // A few more ticks, which must find nothing left to fire.
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    let log = first.kill().await;
+    assert_eq!(fired(&log), 3, "{:#?}", log);
+
+    let after_first = reminded_at(&pool).await;
+    assert!(after_first.iter().all(Option::is_some));
+
+    for _ in 0..2 {
+        let restarted = Server::start(&url).await;
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        let log = restarted.kill().await;
+        assert_eq!(fired(&log), 0, "{:#?}", log);
+        assert_eq!(reminded_at(&pool).await, after_first);
+    }
+}